@@ -5,5 +5,19 @@
 #![crate_name = "siodb"]
 #![crate_type = "dylib"]
 
+#[cfg(not(any(feature = "openssl", feature = "rustls")))]
+compile_error!("One of the features `openssl` or `rustls` is required for TLS.");
+
 mod siodb;
-pub use siodb::SiodbConn;
+#[cfg(feature = "pkcs11")]
+pub use siodb::Pkcs11Credentials;
+pub use siodb::{
+    AgentCredentials, CommandCredentials, CredentialProvider, DriverError, EnvCredentials,
+    ErrorKind, FileCredentials, PemCredentials, PinSource, ServerMessage,
+};
+#[cfg(feature = "tokio")]
+pub use siodb::{AsyncRows, AsyncSiodbConn};
+pub use siodb::{
+    PoolOptions, PooledConn, Proxy, Scheme, SiodbConn, SiodbConnOptions, SiodbPool, SslMode,
+    Target, Timeouts, TlsVersion, Transport,
+};
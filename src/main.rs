@@ -3,8 +3,7 @@
 // in the LICENSE file.
 
 // Siodb lib crate
-mod siodb;
-use crate::siodb::SiodbConn;
+use siodb::SiodbConn;
 
 // Standard
 use std::time::Instant;
@@ -16,19 +15,22 @@ fn main() -> Result<(), std::io::Error> {
     //let uri = "siodb://root@localhost:50000?identity_file=/home/nico/root_id_rsa";
     // Local Unix socket connection
     //let uri = "siodbu:/run/siodb/siodb.socket?identity_file=/home/siodb/.ssh/id_rsa";
-    let mut siodb_conn = SiodbConn::new(&uri).expect(&format!("Error connecting to URI '{}'", uri));
+    let mut siodb_conn =
+        SiodbConn::new(uri).unwrap_or_else(|_| panic!("Error connecting to URI '{}'", uri));
 
     if siodb_conn
         .query_row("select name from sys_databases where name = 'TEST_DB'".to_string())
+        .expect("Query error")
         .is_none()
     {
         siodb_conn
             .execute("CREATE DATABASE test_db".to_string())
-            .expect(&format!("Database creation error."));
+            .expect("Database creation error.");
     }
 
     if siodb_conn
         .query_row("select name from test_db.sys_tables where name = 'TEST_TABLE'".to_string())
+        .expect("Query error")
         .is_none()
     {
         siodb_conn
@@ -56,7 +58,7 @@ fn main() -> Result<(), std::io::Error> {
                 )"
                 .to_string(),
             )
-            .expect(&format!("Table creation error."));
+            .expect("Table creation error.");
     }
 
     siodb_conn
@@ -82,7 +84,7 @@ fn main() -> Result<(), std::io::Error> {
                            CURRENT_TIMESTAMP ) "
                 .to_string(),
         )
-        .expect(&format!("Insertion error."));
+        .expect("Insertion error.");
 
     println!("Affected row(s): {}", siodb_conn.get_affected_row_count());
 
@@ -90,7 +92,7 @@ fn main() -> Result<(), std::io::Error> {
 
     siodb_conn
         .query("select * from test_db.test_table".to_string())
-        .expect(&format!("Query error"));
+        .expect("Query error");
 
     while siodb_conn.next().unwrap() {
         for data in siodb_conn.scan() {
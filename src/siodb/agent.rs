@@ -0,0 +1,211 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Client side of the ssh-agent protocol (draft-miller-ssh-agent).
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::keys::ecdsa_signature_der;
+use crate::siodb::openssh::SshReader;
+
+// Standard
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+// Base64
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+/// Environment variable holding the path of the agent socket.
+pub const AUTH_SOCK_ENV: &str = "SSH_AUTH_SOCK";
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH2_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH2_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH2_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH2_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+// Agents reject bigger messages anyway.
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// Sign the session challenge with a key held by the agent listening on `socket_path`.
+///
+/// The key is the one of the OpenSSH public key file `public_key_file` when given,
+/// otherwise the agent's first key.
+pub fn sign_challenge(
+    socket_path: &str,
+    public_key_file: Option<&str>,
+    challenge: &[u8],
+) -> Result<Vec<u8>, DriverError> {
+    let mut agent = Agent::connect(socket_path)?;
+    let identities = agent.identities()?;
+
+    let key_blob = match public_key_file {
+        Some(public_key_file) => {
+            let key_blob = read_public_key_file(public_key_file)?;
+            if !identities.contains(&key_blob) {
+                return Err(DriverError::new(
+                    ErrorKind::Auth,
+                    &format!(
+                        "The key of '{}' is not loaded in the ssh-agent.",
+                        public_key_file
+                    ),
+                ));
+            }
+            key_blob
+        }
+        None => identities
+            .into_iter()
+            .next()
+            .ok_or_else(|| DriverError::new(ErrorKind::Auth, "The ssh-agent holds no key."))?,
+    };
+
+    agent.sign(&key_blob, challenge)
+}
+
+/// The OpenSSH public key file going with an identity file, if there is one.
+pub fn public_key_file(identity_file: &str) -> Option<String> {
+    let public_key_file = if identity_file.ends_with(".pub") {
+        identity_file.to_string()
+    } else {
+        format!("{}.pub", identity_file)
+    };
+    if Path::new(&public_key_file).exists() {
+        Some(public_key_file)
+    } else {
+        None
+    }
+}
+
+struct Agent {
+    stream: UnixStream,
+}
+
+impl Agent {
+    fn connect(socket_path: &str) -> Result<Agent, DriverError> {
+        let stream = UnixStream::connect(socket_path).map_err(|e| {
+            DriverError::new(
+                ErrorKind::Auth,
+                &format!("Cannot connect to ssh-agent '{}': {}", socket_path, e),
+            )
+            .with_source(e)
+        })?;
+        Ok(Agent { stream })
+    }
+
+    /// Public key blobs of the keys held by the agent.
+    fn identities(&mut self) -> Result<Vec<Vec<u8>>, DriverError> {
+        let response = self.request(&[SSH2_AGENTC_REQUEST_IDENTITIES])?;
+        let mut reader = SshReader::new(&response);
+        expect_type(reader.read_u8()?, SSH2_AGENT_IDENTITIES_ANSWER)?;
+        let count = reader.read_u32()?;
+        let mut identities = Vec::new();
+        for _ in 0..count {
+            identities.push(reader.read_string()?.to_vec());
+            let _comment = reader.read_string()?;
+        }
+        Ok(identities)
+    }
+
+    /// Sign `data` and return the signature as a key file would have produced it.
+    fn sign(&mut self, key_blob: &[u8], data: &[u8]) -> Result<Vec<u8>, DriverError> {
+        let key_type = SshReader::new(key_blob).read_str()?.to_string();
+        let flags = if key_type == "ssh-rsa" {
+            SSH_AGENT_RSA_SHA2_512
+        } else {
+            0
+        };
+
+        let mut request = vec![SSH2_AGENTC_SIGN_REQUEST];
+        put_string(&mut request, key_blob);
+        put_string(&mut request, data);
+        request.extend_from_slice(&flags.to_be_bytes());
+        let response = self.request(&request)?;
+
+        let mut reader = SshReader::new(&response);
+        expect_type(reader.read_u8()?, SSH2_AGENT_SIGN_RESPONSE)?;
+        let mut signature = SshReader::new(reader.read_string()?);
+        let format = signature.read_str()?;
+        let blob = signature.read_string()?;
+        match format {
+            "rsa-sha2-512" | "ssh-ed25519" => Ok(blob.to_vec()),
+            "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => {
+                // SSH sends (r, s) as two mpints, Siodb takes DER.
+                let mut blob = SshReader::new(blob);
+                let r = blob.read_mpint()?;
+                let s = blob.read_mpint()?;
+                Ok(ecdsa_signature_der(r, s))
+            }
+            _ => Err(DriverError::new(
+                ErrorKind::Auth,
+                &format!("Unsupported ssh-agent signature format '{}'.", format),
+            )),
+        }
+    }
+
+    fn request(&mut self, message: &[u8]) -> Result<Vec<u8>, DriverError> {
+        let mut frame = (message.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(message);
+        self.stream.write_all(&frame)?;
+
+        let mut len = [0u8; 4];
+        self.stream.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return Err(DriverError::new(
+                ErrorKind::Auth,
+                &format!("Invalid ssh-agent message length: {}.", len),
+            ));
+        }
+        let mut response = vec![0u8; len];
+        self.stream.read_exact(&mut response)?;
+        Ok(response)
+    }
+}
+
+fn expect_type(message_type: u8, expected: u8) -> Result<(), DriverError> {
+    match message_type {
+        _ if message_type == expected => Ok(()),
+        SSH_AGENT_FAILURE => Err(DriverError::new(
+            ErrorKind::Auth,
+            "The ssh-agent refused the request.",
+        )),
+        _ => Err(DriverError::new(
+            ErrorKind::Auth,
+            &format!(
+                "Unexpected ssh-agent message type: {}. Expected: {}.",
+                message_type, expected
+            ),
+        )),
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+// An OpenSSH public key file holds "<type> <base64 blob> [comment]".
+fn read_public_key_file(path: &str) -> Result<Vec<u8>, DriverError> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        DriverError::new(
+            ErrorKind::Auth,
+            &format!("Error reading public key '{}': {}", path, e),
+        )
+        .with_source(e)
+    })?;
+    contents
+        .split_whitespace()
+        .nth(1)
+        .and_then(|blob| BASE64.decode(blob).ok())
+        .ok_or_else(|| {
+            DriverError::new(
+                ErrorKind::Auth,
+                &format!("Invalid OpenSSH public key '{}'.", path),
+            )
+        })
+}
@@ -0,0 +1,619 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// The async connection, on tokio. Messages and rows are read into a buffer and
+// only taken out of it once complete, so that a dropped future never loses
+// part of them.
+
+// Siodb
+use crate::siodb::errors::{debug, DriverError, ErrorKind, ServerMessage};
+use crate::siodb::hosts::HostFailures;
+use crate::siodb::options::{Scheme, SiodbConnOptions};
+use crate::siodb::protocol;
+use crate::siodb::results::{ResultSet, Value};
+use crate::siodb::tls::{self, SslMode};
+use crate::siodb::transport::{
+    configure_tcp, connect_error, socket_options_error, unix_connect_error, AsyncStream,
+};
+use crate::siodb::ClientProtocol::{
+    BeginSessionResponse, ClientAuthenticationResponse, ServerResponse,
+};
+use crate::siodb::{broken_error, closed_error};
+
+// Standard
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+// Async
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::task;
+use tokio::time::{self, Sleep};
+
+/// An async connection to Siodb, on tokio.
+///
+/// Every future of the connection can be dropped part-way. A dropped row
+/// stream leaves its remaining rows to be skipped by the next statement. A
+/// statement dropped between sending the command and reading the response
+/// leaves the connection broken: `is_broken()` tells it, and the next
+/// statement fails.
+///
+/// ## For example:
+///
+/// ```no_run
+///   # async fn example() -> Result<(), siodb::DriverError> {
+///   use futures_util::StreamExt;
+///   use siodb::AsyncSiodbConn;
+///
+///   let uri = "siodbs://root@localhost:50000?identity_file=/home/siodb/.ssh/id_rsa";
+///   let mut siodb_conn = AsyncSiodbConn::connect(uri).await?;
+///   let mut rows = siodb_conn.query("SELECT * FROM db1.t1".to_string()).await?;
+///   while let Some(row) = rows.next().await {
+///       println!("{:?}", row?);
+///   }
+///   # Ok(())
+///   # }
+/// ```
+pub struct AsyncSiodbConn {
+    options: SiodbConnOptions,
+    host: String,
+    port: u16,
+    stream: Option<Box<dyn AsyncStream>>,
+    // Bytes read from Siodb and not decoded yet.
+    buffer: Vec<u8>,
+    result_set: Option<ResultSet>,
+    // Id of the last command sent, each command taking the next one.
+    request_id: u64,
+    // An error or a dropped future left the stream in an unknown state.
+    broken: bool,
+}
+
+impl fmt::Debug for AsyncSiodbConn {
+    // This trait requires `fmt` with this exact signature.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} | host: {} | port: {}",
+            self.options, self.host, self.port,
+        )
+    }
+}
+
+impl AsyncSiodbConn {
+    /// Open a new authenticated connection to Siodb from an URI.
+    pub async fn connect(uri_str: &str) -> Result<AsyncSiodbConn, DriverError> {
+        AsyncSiodbConn::open(SiodbConnOptions::from_uri(uri_str)?).await
+    }
+
+    /// Open a new authenticated connection to Siodb from options built in code.
+    pub async fn connect_with_options(
+        options: &SiodbConnOptions,
+    ) -> Result<AsyncSiodbConn, DriverError> {
+        options.validate()?;
+        AsyncSiodbConn::open(options.clone()).await
+    }
+
+    /// The options the connection was opened with.
+    pub fn options(&self) -> &SiodbConnOptions {
+        &self.options
+    }
+
+    /// The host the connection is open to: the one that accepted it for a
+    /// multi-host URI, the socket path for a Unix socket connection.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port the connection is open to.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Whether an error or a dropped future left the connection unusable.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    async fn open(options: SiodbConnOptions) -> Result<AsyncSiodbConn, DriverError> {
+        debug(options.trace, &format!("options: {:?}", options));
+        let mut siodb_conn = AsyncSiodbConn {
+            options,
+            host: String::new(),
+            port: 0,
+            stream: None,
+            buffer: Vec::new(),
+            result_set: None,
+            request_id: 0,
+            broken: false,
+        };
+        siodb_conn.open_hosts().await?;
+        Ok(siodb_conn)
+    }
+    // Connect to the hosts in turn until one accepts the connection and the user.
+    async fn open_hosts(&mut self) -> Result<(), DriverError> {
+        let hosts = self.options.hosts_in_order();
+        let mut failures = HostFailures::new(hosts.len());
+        for (host, port) in hosts {
+            self.host = host;
+            self.port = port;
+            match self.open_host().await {
+                Ok(()) => {
+                    self.broken = false;
+                    return Ok(());
+                }
+                Err(error) => {
+                    self.stream = None;
+                    self.buffer.clear();
+                    debug(
+                        self.options.trace,
+                        &format!(
+                            "Connection to '{}:{}' failed: {}",
+                            self.host, self.port, error
+                        ),
+                    );
+                    failures.add(&self.host, self.port, error)?;
+                }
+            }
+        }
+        Err(failures.into_error())
+    }
+    async fn open_host(&mut self) -> Result<(), DriverError> {
+        self.stream = Some(self.connect_stream().await?);
+        self.authenticate().await
+    }
+    async fn connect_stream(&self) -> Result<Box<dyn AsyncStream>, DriverError> {
+        let connect_timeout = self.options.timeouts.connect;
+        let (host, port) = (self.host.as_str(), self.port);
+
+        // Unix socket connection
+        if self.options.scheme == Scheme::Unix {
+            let stream = io_timeout(connect_timeout, UnixStream::connect(host))
+                .await
+                .map_err(|e| unix_connect_error(host, e))?;
+            return Ok(Box::new(stream));
+        }
+
+        let stream = match &self.options.proxy {
+            // The proxy handshake is blocking: run it off the async threads.
+            Some(proxy) => {
+                let (proxy, proxy_host) = (proxy.clone(), host.to_string());
+                task::spawn_blocking(move || proxy.connect(&proxy_host, port, connect_timeout))
+                    .await
+                    .map_err(io::Error::other)
+                    .and_then(|stream| stream)
+                    .and_then(|stream| {
+                        stream.set_nonblocking(true)?;
+                        TcpStream::from_std(stream)
+                    })
+            }
+            None => io_timeout(connect_timeout, TcpStream::connect((host, port))).await,
+        }
+        .map_err(|e| connect_error(&self.options, host, port, e))?;
+        configure_tcp(&stream, self.options.keepalive, self.options.nodelay)
+            .map_err(|e| socket_options_error(host, port, e))?;
+
+        let tls_options = self.options.tls_options();
+        if tls_options.sslmode == SslMode::Disable {
+            // TCP connection
+            Ok(Box::new(stream))
+        } else {
+            // TLS connection
+            tls::connect_async(&tls_options, host, port, stream, connect_timeout).await
+        }
+    }
+
+    // The connect timeout bounds each message of the authentication.
+    async fn authenticate(&mut self) -> Result<(), DriverError> {
+        let credentials = self.options.credential_provider()?;
+        let timeout = self.options.timeouts.connect;
+
+        // Begin session request
+        let begin_session_request = protocol::begin_session_request(credentials.user_name()?);
+        debug(
+            self.options.trace,
+            &format!("begin_session_request: {:?}", begin_session_request),
+        );
+        self.write_message(
+            protocol::BEGIN_SESSION_REQUEST,
+            &begin_session_request,
+            timeout,
+        )
+        .await?;
+
+        // Read Session response
+        let begin_session_response = self
+            .read_message::<BeginSessionResponse>(protocol::BEGIN_SESSION_RESPONSE, timeout)
+            .await?;
+        protocol::check_session_started(&begin_session_response)?;
+
+        // Hash and Sign challenge, off the async threads as signing may block
+        // on a key file, an agent, a token or a command.
+        let challenge = begin_session_response.get_challenge().to_vec();
+        let signature = task::spawn_blocking(move || credentials.sign_challenge(&challenge))
+            .await
+            .map_err(|e| {
+                DriverError::new(
+                    ErrorKind::Auth,
+                    &format!("Signing the challenge failed: {}", e),
+                )
+                .with_source(e)
+            })??;
+
+        // Start authentication
+        let client_authentication_request = protocol::client_authentication_request(signature);
+        debug(
+            self.options.trace,
+            &format!(
+                "client_authentication_request: {:?}",
+                client_authentication_request
+            ),
+        );
+        self.write_message(
+            protocol::CLIENT_AUTHENTICATION_REQUEST,
+            &client_authentication_request,
+            timeout,
+        )
+        .await?;
+
+        // Read Session response
+        let client_authentication_response = self
+            .read_message::<ClientAuthenticationResponse>(
+                protocol::CLIENT_AUTHENTICATION_RESPONSE,
+                timeout,
+            )
+            .await?;
+        protocol::check_authenticated(&client_authentication_response)
+    }
+
+    /// Close the connection with Siodb.
+    pub async fn close(&mut self) -> Result<(), DriverError> {
+        self.broken = false;
+        self.buffer.clear();
+        let mut stream = self.stream.take().ok_or_else(closed_error)?;
+        stream.shutdown().await.map_err(|e| {
+            DriverError::new(
+                ErrorKind::Io,
+                &format!("Error while closing connection: {}", e),
+            )
+            .with_source(e)
+        })
+    }
+
+    /// Execute a statement, or a script of several statements, in a connection.
+    /// The rows of a query are then read from `rows()`, and the results of the
+    /// next statements of a script with `next_result()`. The rows and results
+    /// left unread are skipped by the next statement.
+    pub async fn execute(&mut self, sql: String) -> Result<(), DriverError> {
+        if self.stream.is_none() {
+            return Err(closed_error());
+        }
+        if self.broken {
+            return Err(broken_error());
+        }
+        self.skip_results().await?;
+
+        // Send command
+        self.request_id += 1;
+        let command = protocol::command(self.request_id, sql);
+        debug(self.options.trace, &format!("command: {:?}", command));
+
+        // Until the response is read, a dropped future leaves the command
+        // half sent or its response unread.
+        self.broken = true;
+        self.write_message(protocol::COMMAND, &command, self.options.timeouts.write)
+            .await?;
+
+        // Read server response
+        let result = self.read_response(0).await;
+        self.broken = is_breaking(&result);
+        result
+    }
+
+    /// Move to the result of the next statement of a script, skipping the rows
+    /// left to read. Return `false` once the results of all its statements were
+    /// read. Like `execute`, a statement that failed in Siodb returns an
+    /// `ErrorKind::Server` error, and the results that follow can still be read.
+    pub async fn next_result(&mut self) -> Result<bool, DriverError> {
+        let response_id = match &self.result_set {
+            Some(result_set) if result_set.has_more_responses() => {
+                result_set.server_response.get_response_id() + 1
+            }
+            _ => return Ok(false),
+        };
+        if self.broken {
+            return Err(broken_error());
+        }
+        self.skip_rows().await?;
+        let result = self.read_response(response_id).await;
+        self.broken = is_breaking(&result);
+        result.map(|()| true)
+    }
+
+    // Read the response `response_id` to the last command and make it the current result.
+    async fn read_response(&mut self, response_id: u32) -> Result<(), DriverError> {
+        let server_response = self
+            .read_message::<ServerResponse>(protocol::SERVER_RESPONSE, self.options.timeouts.read)
+            .await?;
+        protocol::check_response(self.request_id, response_id, &server_response)?;
+        let result_set = ResultSet::new(server_response, self.options.trace);
+        debug(
+            self.options.trace,
+            &format!("ServerResponse: {:?}", result_set.server_response),
+        );
+
+        // Check if error arrives from Siodb server
+        let error = result_set.server_error();
+        self.result_set = Some(result_set);
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Execute a query in a connection and return the stream of its rows.
+    pub async fn query(&mut self, sql: String) -> Result<AsyncRows<'_>, DriverError> {
+        self.execute(sql).await?;
+        Ok(self.rows())
+    }
+
+    /// The stream of the rows left to read from the previous statement.
+    pub fn rows(&mut self) -> AsyncRows<'_> {
+        AsyncRows {
+            siodb_conn: self,
+            deadline: None,
+        }
+    }
+
+    /// Return the total number of rows read so far from the previous statement.
+    pub fn get_row_count(&self) -> u64 {
+        self.result_set
+            .as_ref()
+            .map_or(0, |result_set| result_set.row_count)
+    }
+
+    /// Return the number of affected rows from the previous statement.
+    pub fn get_affected_row_count(&self) -> u64 {
+        match &self.result_set {
+            Some(result_set) if result_set.server_response.get_has_affected_row_count() => {
+                result_set.server_response.get_affected_row_count()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Return the status messages Siodb sent with the result of the previous statement.
+    pub fn get_server_messages(&self) -> Vec<ServerMessage> {
+        self.result_set
+            .as_ref()
+            .map_or_else(Vec::new, |result_set| result_set.server_messages())
+    }
+
+    // Read and discard the rows and the results a dropped stream or future
+    // left unread.
+    async fn skip_results(&mut self) -> Result<(), DriverError> {
+        self.skip_rows().await?;
+        loop {
+            match self.next_result().await {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(error) if error.kind() == ErrorKind::Server => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    // Read and discard the rows a dropped stream left unread.
+    async fn skip_rows(&mut self) -> Result<(), DriverError> {
+        let timeout = self.options.timeouts.read;
+        loop {
+            match read_timeout(timeout, poll_fn(|cx| self.poll_row(cx))).await {
+                Ok(Some(_row)) => {}
+                Ok(None) => return Ok(()),
+                Err(error) => {
+                    self.broken = true;
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    async fn write_message(
+        &mut self,
+        message_type: u32,
+        message: &dyn protobuf::Message,
+        timeout: Option<Duration>,
+    ) -> Result<(), DriverError> {
+        let bytes = protocol::encode_message(message_type, message)?;
+        let stream = self.stream.as_mut().ok_or_else(closed_error)?;
+        let write = async {
+            stream.write_all(&bytes).await?;
+            stream.flush().await
+        };
+        match timeout {
+            Some(timeout) => match time::timeout(timeout, write).await {
+                Ok(result) => Ok(result?),
+                Err(_) => Err(DriverError::new(
+                    ErrorKind::Timeout,
+                    "Writing to Siodb timed out.",
+                )),
+            },
+            None => Ok(write.await?),
+        }
+    }
+    async fn read_message<M: protobuf::Message>(
+        &mut self,
+        message_type: u32,
+        timeout: Option<Duration>,
+    ) -> Result<M, DriverError> {
+        debug(
+            self.options.trace,
+            &format!("message_type: {:?}", message_type),
+        );
+        read_timeout(timeout, poll_fn(|cx| self.poll_message(cx, message_type))).await
+    }
+
+    fn poll_message<M: protobuf::Message>(
+        &mut self,
+        cx: &mut Context,
+        message_type: u32,
+    ) -> Poll<Result<M, DriverError>> {
+        loop {
+            if let Some((message_type_received, range)) = protocol::split_message(&self.buffer)? {
+                let message = protocol::parse_message(
+                    message_type,
+                    message_type_received,
+                    &self.buffer[range.clone()],
+                );
+                self.buffer.drain(..range.end);
+                return Poll::Ready(message);
+            }
+            ready!(self.poll_fill(cx))?;
+        }
+    }
+    fn poll_row(
+        &mut self,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<Vec<Option<Value>>>, DriverError>> {
+        let result = self.poll_raw_row(cx);
+        if let Poll::Ready(Err(error)) = &result {
+            // A decode error leaves the row read in full, the others leave the
+            // stream at an unknown point.
+            if error.kind() != ErrorKind::Decode {
+                self.broken = true;
+            }
+        }
+        result
+    }
+    fn poll_raw_row(
+        &mut self,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<Vec<Option<Value>>>, DriverError>> {
+        loop {
+            let result_set = match &mut self.result_set {
+                Some(result_set) if !result_set.end_of_row => result_set,
+                _ => return Poll::Ready(Ok(None)),
+            };
+            if let Some(range) = protocol::split_row(&self.buffer)? {
+                debug(
+                    self.options.trace,
+                    &format!("Row bytes row_length: {}", range.len()),
+                );
+                if range.is_empty() {
+                    result_set.end_of_row = true;
+                    self.buffer.drain(..range.end);
+                    return Poll::Ready(Ok(None));
+                }
+                result_set.row_count += 1;
+                let row = protocol::decode_row(
+                    &self.buffer[range.clone()],
+                    result_set,
+                    self.options.trace,
+                );
+                self.buffer.drain(..range.end);
+                return Poll::Ready(row.map(Some));
+            }
+            ready!(self.poll_fill(cx))?;
+        }
+    }
+    // Read what Siodb sent into the buffer. The bytes are kept whenever the
+    // caller stops polling.
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<Result<(), DriverError>> {
+        let stream = self.stream.as_mut().ok_or_else(closed_error)?;
+        let length = self.buffer.len();
+        self.buffer
+            .resize(length + self.options.read_buffer_size, 0);
+        let mut read_buf = ReadBuf::new(&mut self.buffer[length..]);
+        let result = Pin::new(stream).poll_read(cx, &mut read_buf);
+        let filled = read_buf.filled().len();
+        self.buffer.truncate(length + filled);
+        match ready!(result) {
+            Err(e) => Poll::Ready(Err(e.into())),
+            // The stream ended, as when Siodb closed the connection.
+            Ok(()) if filled == 0 => Poll::Ready(Err(DriverError::new(
+                ErrorKind::Io,
+                "Unexpected end of stream from Siodb.",
+            ))),
+            Ok(()) => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/// The rows of a statement, read from Siodb as the stream is polled.
+///
+/// The read timeout bounds the wait for each row. Dropping the stream leaves
+/// the remaining rows to be skipped by the next statement.
+pub struct AsyncRows<'a> {
+    siodb_conn: &'a mut AsyncSiodbConn,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncRows<'_> {
+    /// Return the total number of rows read so far.
+    pub fn get_row_count(&self) -> u64 {
+        self.siodb_conn.get_row_count()
+    }
+}
+
+impl Stream for AsyncRows<'_> {
+    type Item = Result<Vec<Option<Value>>, DriverError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let rows = self.get_mut();
+        if let Poll::Ready(row) = rows.siodb_conn.poll_row(cx) {
+            rows.deadline = None;
+            return Poll::Ready(row.transpose());
+        }
+        if let Some(timeout) = rows.siodb_conn.options.timeouts.read {
+            let deadline = rows
+                .deadline
+                .get_or_insert_with(|| Box::pin(time::sleep(timeout)));
+            if deadline.as_mut().poll(cx).is_ready() {
+                rows.deadline = None;
+                rows.siodb_conn.broken = true;
+                return Poll::Ready(Some(Err(read_timed_out())));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+// Any error but a statement error of Siodb leaves the stream at an unknown point.
+fn is_breaking(result: &Result<(), DriverError>) -> bool {
+    matches!(result, Err(error) if error.kind() != ErrorKind::Server)
+}
+
+// Bound a connect with `timeout`, as the blocking sockets do.
+async fn io_timeout<T, F>(timeout: Option<Duration>, future: F) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    match timeout {
+        Some(timeout) => time::timeout(timeout, future)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+        None => future.await,
+    }
+}
+
+// Bound a read with `timeout`. An expired timeout breaks the connection.
+async fn read_timeout<T, F>(timeout: Option<Duration>, future: F) -> Result<T, DriverError>
+where
+    F: Future<Output = Result<T, DriverError>>,
+{
+    match timeout {
+        Some(timeout) => time::timeout(timeout, future)
+            .await
+            .unwrap_or_else(|_| Err(read_timed_out())),
+        None => future.await,
+    }
+}
+
+fn read_timed_out() -> DriverError {
+    DriverError::new(ErrorKind::Timeout, "Reading from Siodb timed out.")
+}
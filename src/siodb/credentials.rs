@@ -0,0 +1,297 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::agent;
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::keys::{self, PassphraseCallback};
+use crate::siodb::profiles::expand_home;
+
+// Standard
+use std::env;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+/// Provides the Siodb user name and signs the session challenge for it.
+///
+/// Implement it to authenticate with keys kept outside of files, e.g. in a secrets
+/// manager, and pass it to `SiodbConn::new_with_credentials`.
+pub trait CredentialProvider: Send + Sync {
+    /// Name of the Siodb user to authenticate as.
+    fn user_name(&self) -> Result<String, DriverError>;
+
+    /// Sign the challenge sent by Siodb when the session begins.
+    fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError>;
+}
+
+/// Credentials read from a private key file (PEM or OpenSSH format).
+/// A leading `~` of the path stands for the home directory.
+pub struct FileCredentials {
+    user: String,
+    path: String,
+    passphrase: Option<String>,
+    passphrase_callback: Option<PassphraseCallback>,
+}
+
+impl FileCredentials {
+    pub fn new(user: &str, path: &str) -> FileCredentials {
+        FileCredentials {
+            user: user.to_string(),
+            path: expand_home(path),
+            passphrase: None,
+            passphrase_callback: None,
+        }
+    }
+
+    /// Passphrase of an encrypted key. Without it, the `SIODB_IDENTITY_PASSPHRASE`
+    /// environment variable and then the passphrase callback are tried.
+    pub fn with_passphrase(mut self, passphrase: &str) -> FileCredentials {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    /// Callback asked for the passphrase of an encrypted key, with the key path as argument.
+    pub fn with_passphrase_callback<F>(mut self, passphrase_callback: F) -> FileCredentials
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.passphrase_callback = Some(Arc::new(passphrase_callback));
+        self
+    }
+
+    pub(crate) fn with_shared_passphrase_callback(
+        mut self,
+        passphrase_callback: Option<PassphraseCallback>,
+    ) -> FileCredentials {
+        self.passphrase_callback = passphrase_callback;
+        self
+    }
+}
+
+impl CredentialProvider for FileCredentials {
+    fn user_name(&self) -> Result<String, DriverError> {
+        Ok(self.user.clone())
+    }
+
+    fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+        let keypair = keys::read_private_key(
+            &self.path,
+            self.passphrase.as_deref(),
+            self.passphrase_callback.as_ref(),
+        )?;
+        keys::sign_challenge(&keypair, challenge)
+    }
+}
+
+/// Credentials from a private key held in memory (PEM or OpenSSH format).
+pub struct PemCredentials {
+    user: String,
+    pem: Vec<u8>,
+    passphrase: Option<String>,
+}
+
+impl PemCredentials {
+    pub fn new(user: &str, pem: &[u8]) -> PemCredentials {
+        PemCredentials {
+            user: user.to_string(),
+            pem: pem.to_vec(),
+            passphrase: None,
+        }
+    }
+
+    /// Passphrase of an encrypted key.
+    pub fn with_passphrase(mut self, passphrase: &str) -> PemCredentials {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+}
+
+impl CredentialProvider for PemCredentials {
+    fn user_name(&self) -> Result<String, DriverError> {
+        Ok(self.user.clone())
+    }
+
+    fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+        sign_with_key(
+            &self.pem,
+            "in-memory key",
+            self.passphrase.as_deref(),
+            challenge,
+        )
+    }
+}
+
+/// Credentials from a private key stored in an environment variable.
+pub struct EnvCredentials {
+    user: String,
+    variable: String,
+    passphrase: Option<String>,
+}
+
+impl EnvCredentials {
+    pub fn new(user: &str, variable: &str) -> EnvCredentials {
+        EnvCredentials {
+            user: user.to_string(),
+            variable: variable.to_string(),
+            passphrase: None,
+        }
+    }
+
+    /// Passphrase of an encrypted key.
+    pub fn with_passphrase(mut self, passphrase: &str) -> EnvCredentials {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+}
+
+impl CredentialProvider for EnvCredentials {
+    fn user_name(&self) -> Result<String, DriverError> {
+        Ok(self.user.clone())
+    }
+
+    fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+        let pem = env::var(&self.variable).map_err(|e| {
+            DriverError::new(
+                ErrorKind::Auth,
+                &format!(
+                    "Error reading private key from environment variable '{}': {}",
+                    self.variable, e
+                ),
+            )
+            .with_source(e)
+        })?;
+        sign_with_key(
+            pem.as_bytes(),
+            &self.variable,
+            self.passphrase.as_deref(),
+            challenge,
+        )
+    }
+}
+
+/// Credentials from a private key printed on the standard output of a command,
+/// e.g. the CLI of a secrets manager. The command runs at every authentication.
+pub struct CommandCredentials {
+    user: String,
+    program: String,
+    args: Vec<String>,
+    passphrase: Option<String>,
+}
+
+impl CommandCredentials {
+    pub fn new(user: &str, program: &str) -> CommandCredentials {
+        CommandCredentials {
+            user: user.to_string(),
+            program: program.to_string(),
+            args: Vec::new(),
+            passphrase: None,
+        }
+    }
+
+    /// Add an argument to the command.
+    pub fn arg(mut self, arg: &str) -> CommandCredentials {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Passphrase of an encrypted key.
+    pub fn with_passphrase(mut self, passphrase: &str) -> CommandCredentials {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+}
+
+impl CredentialProvider for CommandCredentials {
+    fn user_name(&self) -> Result<String, DriverError> {
+        Ok(self.user.clone())
+    }
+
+    fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| {
+                DriverError::new(
+                    ErrorKind::Auth,
+                    &format!("Error running credential command '{}': {}", self.program, e),
+                )
+                .with_source(e)
+            })?;
+        if !output.status.success() {
+            return Err(DriverError::new(
+                ErrorKind::Auth,
+                &format!(
+                    "Credential command '{}' failed: {}.",
+                    self.program, output.status
+                ),
+            ));
+        }
+        sign_with_key(
+            &output.stdout,
+            &self.program,
+            self.passphrase.as_deref(),
+            challenge,
+        )
+    }
+}
+
+/// Credentials signing through an ssh-agent, so the private key never has to be
+/// readable by the process.
+pub struct AgentCredentials {
+    user: String,
+    socket_path: Option<String>,
+    public_key_file: Option<String>,
+}
+
+impl AgentCredentials {
+    /// Use the agent of `SSH_AUTH_SOCK` and its first key.
+    pub fn new(user: &str) -> AgentCredentials {
+        AgentCredentials {
+            user: user.to_string(),
+            socket_path: None,
+            public_key_file: None,
+        }
+    }
+
+    /// Path of the agent socket, instead of `SSH_AUTH_SOCK`.
+    pub fn with_socket(mut self, socket_path: &str) -> AgentCredentials {
+        self.socket_path = Some(socket_path.to_string());
+        self
+    }
+
+    /// OpenSSH public key file of the agent key to use.
+    pub fn with_public_key_file(mut self, public_key_file: &str) -> AgentCredentials {
+        self.public_key_file = Some(public_key_file.to_string());
+        self
+    }
+}
+
+impl CredentialProvider for AgentCredentials {
+    fn user_name(&self) -> Result<String, DriverError> {
+        Ok(self.user.clone())
+    }
+
+    fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+        let socket_path = match &self.socket_path {
+            Some(socket_path) => socket_path.clone(),
+            None => env::var(agent::AUTH_SOCK_ENV)
+                .map_err(|_| DriverError::new(ErrorKind::Auth, "SSH_AUTH_SOCK is not set."))?,
+        };
+        agent::sign_challenge(&socket_path, self.public_key_file.as_deref(), challenge)
+    }
+}
+
+fn sign_with_key(
+    contents: &[u8],
+    name: &str,
+    passphrase: Option<&str>,
+    challenge: &[u8],
+) -> Result<Vec<u8>, DriverError> {
+    let keypair = keys::parse_private_key(contents, name, || {
+        passphrase.map(|passphrase| passphrase.to_string())
+    })?;
+    keys::sign_challenge(&keypair, challenge)
+}
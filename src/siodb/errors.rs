@@ -2,20 +2,119 @@
 // Use of this source code is governed by a license that can be found
 // in the LICENSE file.
 
+// Siodb
+use crate::siodb::CommonTypes::StatusMessage;
+
+// Standard
 use std::error::Error;
 use std::fmt;
 
+/// What went wrong, to tell errors worth a retry from the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Reading from or writing to the connection failed.
+    Io,
+    /// Connecting, reading or writing took longer than its timeout.
+    Timeout,
+    /// The TLS setup or handshake failed.
+    Tls,
+    /// The credentials could not be loaded or used, or Siodb rejected them.
+    Auth,
+    /// Siodb sent something the driver did not expect.
+    Protocol,
+    /// A value sent by Siodb could not be decoded.
+    Decode,
+    /// Siodb returned error messages for the statement.
+    Server,
+    /// Wrong URI or options, or the API was misused.
+    Config,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Io => write!(f, "I/O error"),
+            ErrorKind::Timeout => write!(f, "timeout"),
+            ErrorKind::Tls => write!(f, "TLS error"),
+            ErrorKind::Auth => write!(f, "authentication error"),
+            ErrorKind::Protocol => write!(f, "protocol error"),
+            ErrorKind::Decode => write!(f, "decode error"),
+            ErrorKind::Server => write!(f, "server error"),
+            ErrorKind::Config => write!(f, "configuration error"),
+        }
+    }
+}
+
+/// A status message of a Siodb response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerMessage {
+    pub status_code: i32,
+    pub text: String,
+}
+
+impl From<&StatusMessage> for ServerMessage {
+    fn from(message: &StatusMessage) -> Self {
+        ServerMessage {
+            status_code: message.get_status_code(),
+            text: message.get_text().to_string(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct DriverError {
+    kind: ErrorKind,
     details: String,
+    source: Option<Box<dyn Error + Send + Sync + 'static>>,
+    server_messages: Vec<ServerMessage>,
 }
 
 impl DriverError {
-    pub fn new(msg: &str) -> DriverError {
+    pub fn new(kind: ErrorKind, msg: &str) -> DriverError {
         DriverError {
+            kind,
             details: msg.to_string(),
+            source: None,
+            server_messages: Vec::new(),
+        }
+    }
+
+    /// An error of Siodb for the statement, with all its status messages.
+    pub fn server(messages: Vec<ServerMessage>) -> DriverError {
+        let texts: Vec<&str> = messages.iter().map(|m| m.text.as_str()).collect();
+        DriverError {
+            kind: ErrorKind::Server,
+            details: format!("Error message(s) from Siodb: {}.", texts.join(" ")),
+            source: None,
+            server_messages: messages,
         }
     }
+
+    /// Attach the status messages Siodb sent along with the failure.
+    pub(crate) fn with_server_messages(mut self, messages: Vec<ServerMessage>) -> DriverError {
+        self.server_messages = messages;
+        self
+    }
+
+    /// Attach the underlying error.
+    pub fn with_source<E>(mut self, source: E) -> DriverError
+    where
+        E: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Status messages sent by Siodb with the error: the statement errors of an
+    /// `ErrorKind::Server` error, or the reason of an authentication refusal.
+    pub fn server_messages(&self) -> &[ServerMessage] {
+        &self.server_messages
+    }
 }
 
 impl fmt::Display for DriverError {
@@ -25,26 +124,45 @@ impl fmt::Display for DriverError {
 }
 
 impl Error for DriverError {
-    fn description(&self) -> &str {
-        &self.details
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn Error + 'static))
     }
 }
 
 impl From<protobuf::error::ProtobufError> for DriverError {
     fn from(err: protobuf::error::ProtobufError) -> Self {
-        DriverError::new(&err.to_string())
+        match err {
+            protobuf::error::ProtobufError::IoError(err) => DriverError::from(err),
+            // The stream ended, as when Siodb closed the connection.
+            protobuf::error::ProtobufError::WireError(
+                protobuf::error::WireError::UnexpectedEof,
+            ) => DriverError::new(ErrorKind::Io, "Unexpected end of stream from Siodb."),
+            err => DriverError::new(ErrorKind::Protocol, &err.to_string()).with_source(err),
+        }
     }
 }
 
+#[cfg(feature = "openssl")]
 impl From<openssl::error::ErrorStack> for DriverError {
     fn from(err: openssl::error::ErrorStack) -> Self {
-        DriverError::new(&err.to_string())
+        DriverError::new(ErrorKind::Auth, &err.to_string()).with_source(err)
     }
 }
 
 impl From<std::io::Error> for DriverError {
     fn from(err: std::io::Error) -> Self {
-        DriverError::new(&err.to_string())
+        DriverError::new(io_error_kind(&err), &err.to_string()).with_source(err)
+    }
+}
+
+/// The kind of an I/O error: sockets report an expired timeout as `TimedOut` or,
+/// for reads and writes, as `WouldBlock`.
+pub fn io_error_kind(err: &std::io::Error) -> ErrorKind {
+    match err.kind() {
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+        _ => ErrorKind::Io,
     }
 }
 
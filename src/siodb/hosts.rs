@@ -0,0 +1,164 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+
+// Standard
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+
+// Url for URI
+use url::Url;
+
+/// Port of Siodb when the URI gives none.
+pub const DEFAULT_PORT: u16 = 50000;
+
+/// Which of the hosts of a multi-host URI to connect to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The first host accepting the connection, in the order of the URI.
+    Any,
+    /// The hosts are tried in a random order, to spread the connections.
+    Random,
+}
+
+impl FromStr for Target {
+    type Err = DriverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(Target::Any),
+            "random" => Ok(Target::Random),
+            _ => Err(DriverError::new(
+                ErrorKind::Config,
+                &format!("Wrong target: '{}'. Should be 'any' or 'random'.", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Any => write!(f, "any"),
+            Target::Random => write!(f, "random"),
+        }
+    }
+}
+
+impl Target {
+    /// The hosts in the order they must be tried.
+    pub fn order<T>(self, mut hosts: Vec<T>) -> Vec<T> {
+        if self == Target::Random {
+            // Fisher-Yates with a xorshift seeded by the randomly keyed std hasher.
+            let mut state = RandomState::new().build_hasher().finish() | 1;
+            for i in (1..hosts.len()).rev() {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                hosts.swap(i, (state % (i as u64 + 1)) as usize);
+            }
+        }
+        hosts
+    }
+}
+
+/// The failures of the hosts tried in turn.
+pub struct HostFailures {
+    host_count: usize,
+    failures: Vec<(String, DriverError)>,
+}
+
+impl HostFailures {
+    pub fn new(host_count: usize) -> HostFailures {
+        HostFailures {
+            host_count,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Record the failure of a host, or return it when no other host can do better:
+    /// a wrong setting fails the same way on every host.
+    pub fn add(&mut self, host: &str, port: u16, error: DriverError) -> Result<(), DriverError> {
+        if error.kind() == ErrorKind::Config || self.host_count == 1 {
+            return Err(error);
+        }
+        self.failures
+            .push((format!("'{}:{}': {}", host, port, error), error));
+        Ok(())
+    }
+
+    /// The error once every host failed.
+    pub fn into_error(mut self) -> DriverError {
+        let messages: Vec<String> = self
+            .failures
+            .iter()
+            .map(|(message, _)| message.clone())
+            .collect();
+        match self.failures.pop() {
+            Some((_, last_error)) => DriverError::new(
+                last_error.kind(),
+                &format!("Unable to connect to any host: {}", messages.join(" | ")),
+            )
+            .with_source(last_error),
+            None => DriverError::new(ErrorKind::Config, "No host to connect to."),
+        }
+    }
+}
+
+/// Split the hosts of an URI like `siodbs://root@db1:50000,db2:50000/?target=any`.
+///
+/// `Url` cannot parse such an authority, so the URI is returned with its first
+/// host only, along with the other `host[:port]` entries.
+pub fn split_hosts(uri_str: &str) -> (String, Vec<String>) {
+    let authority_start = match uri_str.find("://") {
+        Some(index) => index + 3,
+        None => return (uri_str.to_string(), Vec::new()),
+    };
+    let rest = &uri_str[authority_start..];
+    let authority_len = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..authority_len];
+    let hosts_start = authority.find('@').map_or(0, |index| index + 1);
+    let hosts = &authority[hosts_start..];
+    if !hosts.contains(',') {
+        return (uri_str.to_string(), Vec::new());
+    }
+
+    let mut entries = hosts.split(',');
+    let first = entries.next().unwrap_or_default();
+    let uri = format!(
+        "{}{}{}",
+        &uri_str[..authority_start + hosts_start],
+        first,
+        &rest[authority_len..]
+    );
+    (uri, entries.map(|entry| entry.to_string()).collect())
+}
+
+/// A host as the sockets take it: an IPv6 address of a URI loses its brackets.
+pub fn bare_host(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Parse a `host[:port]` entry of a multi-host URI.
+pub fn parse_host(
+    scheme: &str,
+    entry: &str,
+    default_port: u16,
+) -> Result<(String, u16), DriverError> {
+    let wrong_host = || DriverError::new(ErrorKind::Config, &format!("Wrong host: '{}'.", entry));
+    let url = Url::parse(&format!("{}://{}", scheme, entry)).map_err(|_| wrong_host())?;
+    match url.host_str() {
+        Some(host) if url.username().is_empty() && url.path().is_empty() => Ok((
+            bare_host(host).to_string(),
+            url.port().unwrap_or(default_port),
+        )),
+        _ => Err(wrong_host()),
+    }
+}
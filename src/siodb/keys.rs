@@ -0,0 +1,141 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Private keys and signing of the session challenge. The cryptography is done by
+// the backend selected with the `openssl` or `rustls` cargo feature.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::openssh;
+
+// Standard
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+// Backend: OpenSSL when both features are enabled.
+#[cfg(feature = "openssl")]
+pub mod with_openssl;
+#[cfg(feature = "openssl")]
+pub use with_openssl as backend;
+#[cfg(all(feature = "rustls", not(feature = "openssl")))]
+pub mod with_rustls;
+#[cfg(all(feature = "rustls", not(feature = "openssl")))]
+pub use with_rustls as backend;
+
+pub use backend::PrivateKey;
+
+/// Environment variable read for the passphrase of an encrypted identity file.
+pub const PASSPHRASE_ENV: &str = "SIODB_IDENTITY_PASSPHRASE";
+
+/// Asked for the passphrase of the encrypted key file given as argument.
+pub type PassphraseCallback = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Curves supported for ECDSA keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    P256,
+    P384,
+    P521,
+}
+
+/// Block cipher modes of AES used by encrypted OpenSSH keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AesMode {
+    Ctr,
+    Cbc,
+}
+
+/// Read the private key of an identity file.
+///
+/// The passphrase of an encrypted key is taken, in this order, from `passphrase`,
+/// from the `SIODB_IDENTITY_PASSPHRASE` environment variable or from `callback`.
+/// None of them is consulted for an unencrypted key.
+pub fn read_private_key(
+    path: &str,
+    passphrase: Option<&str>,
+    callback: Option<&PassphraseCallback>,
+) -> Result<PrivateKey, DriverError> {
+    let contents = fs::read(path).map_err(|e| {
+        DriverError::new(
+            ErrorKind::Auth,
+            &format!("Error reading private key '{}': {}", path, e),
+        )
+        .with_source(e)
+    })?;
+
+    parse_private_key(&contents, path, || {
+        passphrase
+            .map(|passphrase| passphrase.to_string())
+            .or_else(|| env::var(PASSPHRASE_ENV).ok())
+            .or_else(|| callback.and_then(|callback| callback(path)))
+    })
+}
+
+/// Parse a private key: PEM (PKCS#1, SEC1 or PKCS#8) or OpenSSH, encrypted or not.
+///
+/// `passphrase` is only called when the key is encrypted. `name` is used in error messages.
+pub fn parse_private_key<F>(
+    contents: &[u8],
+    name: &str,
+    passphrase: F,
+) -> Result<PrivateKey, DriverError>
+where
+    F: FnOnce() -> Option<String>,
+{
+    if openssh::is_openssh_key(contents) {
+        return openssh::parse_private_key(contents, name, passphrase);
+    }
+    backend::parse_pem_private_key(contents, name, passphrase)
+}
+
+/// Sign the session challenge the way the key's SSH algorithm does:
+/// RSA with SHA-512, ECDSA with the hash matching the curve size, Ed25519 as is.
+/// ECDSA signatures are DER encoded.
+pub fn sign_challenge(keypair: &PrivateKey, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+    backend::sign_challenge(keypair, challenge)
+}
+
+/// DER encoding of the ECDSA signature (r, s), given as big-endian integers.
+pub fn ecdsa_signature_der(r: &[u8], s: &[u8]) -> Vec<u8> {
+    let mut integers = der_integer(r);
+    integers.extend(der_integer(s));
+    let mut der = vec![0x30];
+    der.extend(der_length(integers.len()));
+    der.extend(integers);
+    der
+}
+
+fn der_integer(value: &[u8]) -> Vec<u8> {
+    let start = value
+        .iter()
+        .position(|&byte| byte != 0)
+        .unwrap_or(value.len());
+    let mut content = value[start..].to_vec();
+    // Positive integers with the high bit set take a leading zero.
+    if content.first().is_none_or(|&byte| byte & 0x80 != 0) {
+        content.insert(0, 0);
+    }
+    let mut der = vec![0x02];
+    der.extend(der_length(content.len()));
+    der.extend(content);
+    der
+}
+
+fn der_length(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        vec![len as u8]
+    } else {
+        // The signatures of P-521 are the longest, well below 64 KiB.
+        let bytes: Vec<u8> = (len as u16)
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|&byte| byte == 0)
+            .collect();
+        let mut der = vec![0x80 | bytes.len() as u8];
+        der.extend(bytes);
+        der
+    }
+}
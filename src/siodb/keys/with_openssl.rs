@@ -0,0 +1,209 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Keys and signing with OpenSSL.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::keys::{AesMode, Curve};
+
+// OpenSSL
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::sign::Signer;
+use openssl::symm::{Cipher, Crypter, Mode};
+
+/// A private key to sign with.
+pub type PrivateKey = PKey<Private>;
+
+pub fn parse_pem_private_key<F>(
+    contents: &[u8],
+    name: &str,
+    passphrase: F,
+) -> Result<PrivateKey, DriverError>
+where
+    F: FnOnce() -> Option<String>,
+{
+    let mut passphrase_missing = false;
+    let mut passphrase_given = false;
+    let keypair = PKey::private_key_from_pem_callback(contents, |buf| match passphrase() {
+        Some(passphrase) => {
+            passphrase_given = true;
+            copy_passphrase(passphrase.as_bytes(), buf)
+        }
+        None => {
+            passphrase_missing = true;
+            Ok(0)
+        }
+    });
+    match keypair {
+        Ok(keypair) => Ok(keypair),
+        Err(_) if passphrase_missing => Err(DriverError::new(
+            ErrorKind::Auth,
+            &format!(
+                "Private key '{}' is encrypted and no passphrase was given.",
+                name
+            ),
+        )),
+        Err(e) if passphrase_given => Err(DriverError::new(
+            ErrorKind::Auth,
+            &format!(
+                "Error loading private key '{}': wrong passphrase or unsupported encryption ({})",
+                name, e
+            ),
+        )),
+        Err(e) => Err(DriverError::new(
+            ErrorKind::Auth,
+            &format!("Error loading private key '{}': {}", name, e),
+        )),
+    }
+}
+
+pub fn sign_challenge(keypair: &PrivateKey, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+    let mut signer = match keypair.id() {
+        Id::RSA => Signer::new(MessageDigest::sha512(), keypair)?,
+        Id::EC => Signer::new(ecdsa_digest(keypair)?, keypair)?,
+        Id::ED25519 => Signer::new_without_digest(keypair)?,
+        id => {
+            return Err(DriverError::new(
+                ErrorKind::Auth,
+                &format!("Unsupported private key type: {:?}.", id),
+            ))
+        }
+    };
+
+    Ok(signer.sign_oneshot_to_vec(challenge)?)
+}
+
+/// An RSA key from its components, as big-endian integers.
+pub fn rsa_private_key(
+    n: &[u8],
+    e: &[u8],
+    d: &[u8],
+    iqmp: &[u8],
+    p: &[u8],
+    q: &[u8],
+) -> Result<PrivateKey, DriverError> {
+    let d = BigNum::from_slice(d)?;
+    let p = BigNum::from_slice(p)?;
+    let q = BigNum::from_slice(q)?;
+
+    // The CRT exponents are computed from the other components.
+    let mut ctx = BigNumContext::new()?;
+    let one = BigNum::from_u32(1)?;
+    let mut p1 = BigNum::new()?;
+    p1.checked_sub(&p, &one)?;
+    let mut q1 = BigNum::new()?;
+    q1.checked_sub(&q, &one)?;
+    let mut dmp1 = BigNum::new()?;
+    dmp1.nnmod(&d, &p1, &mut ctx)?;
+    let mut dmq1 = BigNum::new()?;
+    dmq1.nnmod(&d, &q1, &mut ctx)?;
+
+    let rsa = Rsa::from_private_components(
+        BigNum::from_slice(n)?,
+        BigNum::from_slice(e)?,
+        d,
+        p,
+        q,
+        dmp1,
+        dmq1,
+        BigNum::from_slice(iqmp)?,
+    )?;
+    Ok(PKey::from_rsa(rsa)?)
+}
+
+/// An ECDSA key from its encoded public point and its private scalar.
+pub fn ecdsa_private_key(
+    curve: Curve,
+    public_key: &[u8],
+    private_key: &[u8],
+) -> Result<PrivateKey, DriverError> {
+    let nid = match curve {
+        Curve::P256 => Nid::X9_62_PRIME256V1,
+        Curve::P384 => Nid::SECP384R1,
+        Curve::P521 => Nid::SECP521R1,
+    };
+    let mut ctx = BigNumContext::new()?;
+    let group = EcGroup::from_curve_name(nid)?;
+    let point = EcPoint::from_bytes(&group, public_key, &mut ctx)?;
+    let private_key = BigNum::from_slice(private_key)?;
+    let ec_key = EcKey::from_private_components(&group, &private_key, &point)?;
+    ec_key.check_key()?;
+    Ok(PKey::from_ec_key(ec_key)?)
+}
+
+/// An Ed25519 key from its 32 bytes seed.
+pub fn ed25519_private_key(seed: &[u8]) -> Result<PrivateKey, DriverError> {
+    Ok(PKey::private_key_from_raw_bytes(seed, Id::ED25519)?)
+}
+
+/// Decrypt AES without padding. The key size selects AES-128, AES-192 or AES-256.
+pub fn aes_decrypt(
+    mode: AesMode,
+    key: &[u8],
+    iv: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, DriverError> {
+    let cipher = match (mode, key.len()) {
+        (AesMode::Ctr, 16) => Cipher::aes_128_ctr(),
+        (AesMode::Ctr, 24) => Cipher::aes_192_ctr(),
+        (AesMode::Ctr, 32) => Cipher::aes_256_ctr(),
+        (AesMode::Cbc, 16) => Cipher::aes_128_cbc(),
+        (AesMode::Cbc, 24) => Cipher::aes_192_cbc(),
+        (AesMode::Cbc, 32) => Cipher::aes_256_cbc(),
+        (_, len) => {
+            return Err(DriverError::new(
+                ErrorKind::Auth,
+                &format!("Invalid AES key length: {}.", len),
+            ))
+        }
+    };
+    let mut crypter = Crypter::new(cipher, Mode::Decrypt, key, Some(iv))?;
+    crypter.pad(false);
+    let mut decrypted = vec![0u8; data.len() + cipher.block_size()];
+    let count = crypter.update(data, &mut decrypted)?;
+    let count = count + crypter.finalize(&mut decrypted[count..])?;
+    decrypted.truncate(count);
+    Ok(decrypted)
+}
+
+/// Hash `data` with the hash going with the ECDSA curve.
+#[cfg(feature = "pkcs11")]
+pub fn ecdsa_hash(curve: Curve, data: &[u8]) -> Result<Vec<u8>, DriverError> {
+    let digest = match curve {
+        Curve::P256 => MessageDigest::sha256(),
+        Curve::P384 => MessageDigest::sha384(),
+        Curve::P521 => MessageDigest::sha512(),
+    };
+    Ok(openssl::hash::hash(digest, data)?.to_vec())
+}
+
+fn ecdsa_digest(keypair: &PrivateKey) -> Result<MessageDigest, DriverError> {
+    match keypair.ec_key()?.group().curve_name() {
+        Some(Nid::X9_62_PRIME256V1) => Ok(MessageDigest::sha256()),
+        Some(Nid::SECP384R1) => Ok(MessageDigest::sha384()),
+        Some(Nid::SECP521R1) => Ok(MessageDigest::sha512()),
+        curve => Err(DriverError::new(
+            ErrorKind::Auth,
+            &format!(
+                "Unsupported ECDSA curve: {:?}. Should be P-256, P-384 or P-521.",
+                curve
+            ),
+        )),
+    }
+}
+
+// OpenSSL hands over a fixed size buffer for the passphrase.
+fn copy_passphrase(passphrase: &[u8], buf: &mut [u8]) -> Result<usize, openssl::error::ErrorStack> {
+    if passphrase.len() > buf.len() {
+        return Ok(0);
+    }
+    buf[..passphrase.len()].copy_from_slice(passphrase);
+    Ok(passphrase.len())
+}
@@ -0,0 +1,424 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Keys and signing with pure Rust crates, for the rustls backend.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::keys::{AesMode, Curve};
+
+// Standard
+use std::convert::{TryFrom, TryInto};
+
+// Base64
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+// Ciphers
+use aes::cipher::block_padding::{NoPadding, Pkcs7};
+use aes::cipher::{BlockDecryptMut, KeyIvInit, StreamCipher};
+use aes::{Aes128, Aes192, Aes256};
+use des::TdesEde3;
+use md5::{Digest, Md5};
+
+// Keys
+use ed25519_dalek::Signer;
+use pkcs8::{DecodePrivateKey, EncodePrivateKey, EncryptedPrivateKeyInfo};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::signature::SignatureEncoding;
+use rsa::{BigUint, RsaPrivateKey};
+
+/// A private key to sign with.
+pub enum PrivateKey {
+    Rsa(Box<RsaPrivateKey>),
+    P256(p256::SecretKey),
+    P384(p384::SecretKey),
+    P521(p521::SecretKey),
+    Ed25519(Box<ed25519_dalek::SigningKey>),
+}
+
+/// A block of a PEM file.
+pub struct PemBlock {
+    pub label: String,
+    pub headers: Vec<(String, String)>,
+    pub der: Vec<u8>,
+}
+
+/// The blocks of a PEM file, text outside of them skipped. A block that is not
+/// valid base64 is an error.
+pub fn pem_blocks(contents: &[u8]) -> Result<Vec<PemBlock>, String> {
+    let text = String::from_utf8_lossy(contents);
+    let mut blocks = Vec::new();
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let label = match line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|rest| rest.strip_suffix("-----"))
+        {
+            Some(label) => label.to_string(),
+            None => continue,
+        };
+        let end = format!("-----END {}-----", label);
+        let mut headers = Vec::new();
+        let mut body = String::new();
+        let mut ended = false;
+        for line in lines.by_ref() {
+            if line == end {
+                ended = true;
+                break;
+            }
+            match line.split_once(':') {
+                Some((name, value)) if body.is_empty() => {
+                    headers.push((name.trim().to_string(), value.trim().to_string()))
+                }
+                _ => body.push_str(line),
+            }
+        }
+        if !ended {
+            return Err(format!("missing end of PEM block '{}'", label));
+        }
+        let der = BASE64
+            .decode(&body)
+            .map_err(|_| format!("bad base64 in PEM block '{}'", label))?;
+        blocks.push(PemBlock {
+            label,
+            headers,
+            der,
+        });
+    }
+    Ok(blocks)
+}
+
+pub fn parse_pem_private_key<F>(
+    contents: &[u8],
+    name: &str,
+    passphrase: F,
+) -> Result<PrivateKey, DriverError>
+where
+    F: FnOnce() -> Option<String>,
+{
+    let error = |reason: &str| {
+        DriverError::new(
+            ErrorKind::Auth,
+            &format!("Error loading private key '{}': {}", name, reason),
+        )
+    };
+    let no_passphrase = || {
+        DriverError::new(
+            ErrorKind::Auth,
+            &format!(
+                "Private key '{}' is encrypted and no passphrase was given.",
+                name
+            ),
+        )
+    };
+
+    // As with OpenSSL, other blocks (e.g. certificates) are skipped.
+    let block = pem_blocks(contents)
+        .map_err(|e| error(&e))?
+        .into_iter()
+        .find(|block| block.label.ends_with("PRIVATE KEY"))
+        .ok_or_else(|| error("no PEM private key found"))?;
+
+    match block.label.as_str() {
+        "ENCRYPTED PRIVATE KEY" => {
+            let passphrase = passphrase().ok_or_else(no_passphrase)?;
+            let info = EncryptedPrivateKeyInfo::try_from(block.der.as_slice())
+                .map_err(|e| error(&e.to_string()))?;
+            let document = info.decrypt(passphrase.as_bytes()).map_err(|e| {
+                error(&format!(
+                    "wrong passphrase or unsupported encryption ({})",
+                    e
+                ))
+            })?;
+            pkcs8_private_key(document.as_bytes()).map_err(|e| error(&e))
+        }
+        label @ ("PRIVATE KEY" | "RSA PRIVATE KEY" | "EC PRIVATE KEY") => {
+            let der = match block.headers.iter().find(|(name, _)| name == "DEK-Info") {
+                Some((_, dek_info)) => {
+                    let passphrase = passphrase().ok_or_else(no_passphrase)?;
+                    legacy_decrypt(dek_info, &passphrase, &block.der).map_err(|e| error(&e))?
+                }
+                None => block.der,
+            };
+            match label {
+                "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_der(&der)
+                    .map(|key| PrivateKey::Rsa(Box::new(key)))
+                    .map_err(|e| error(&e.to_string())),
+                "EC PRIVATE KEY" => sec1_private_key(&der).map_err(|e| error(&e)),
+                _ => pkcs8_private_key(&der).map_err(|e| error(&e)),
+            }
+        }
+        label => Err(error(&format!("unsupported PEM block '{}'", label))),
+    }
+}
+
+pub fn sign_challenge(keypair: &PrivateKey, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+    let signature = match keypair {
+        PrivateKey::Rsa(key) => {
+            let signing_key = rsa::pkcs1v15::SigningKey::<sha2::Sha512>::new(*key.clone());
+            signing_key
+                .try_sign(challenge)
+                .map_err(|e| {
+                    DriverError::new(ErrorKind::Auth, &format!("RSA signing failed: {}", e))
+                })?
+                .to_vec()
+        }
+        PrivateKey::P256(key) => {
+            let signature: p256::ecdsa::Signature =
+                p256::ecdsa::SigningKey::from(key).sign(challenge);
+            signature.to_der().as_bytes().to_vec()
+        }
+        PrivateKey::P384(key) => {
+            let signature: p384::ecdsa::Signature =
+                p384::ecdsa::SigningKey::from(key).sign(challenge);
+            signature.to_der().as_bytes().to_vec()
+        }
+        PrivateKey::P521(key) => {
+            let signing_key = p521::ecdsa::SigningKey::from_bytes(&key.to_bytes())
+                .map_err(|e| DriverError::new(ErrorKind::Auth, &e.to_string()))?;
+            let signature: p521::ecdsa::Signature = signing_key.sign(challenge);
+            signature.to_der().as_bytes().to_vec()
+        }
+        PrivateKey::Ed25519(key) => key.sign(challenge).to_bytes().to_vec(),
+    };
+    Ok(signature)
+}
+
+/// An RSA key from its components, as big-endian integers.
+pub fn rsa_private_key(
+    n: &[u8],
+    e: &[u8],
+    d: &[u8],
+    _iqmp: &[u8],
+    p: &[u8],
+    q: &[u8],
+) -> Result<PrivateKey, DriverError> {
+    // The CRT values are computed from the other components.
+    let key = RsaPrivateKey::from_components(
+        BigUint::from_bytes_be(n),
+        BigUint::from_bytes_be(e),
+        BigUint::from_bytes_be(d),
+        vec![BigUint::from_bytes_be(p), BigUint::from_bytes_be(q)],
+    )
+    .map_err(|e| DriverError::new(ErrorKind::Auth, &format!("Invalid RSA key: {}", e)))?;
+    Ok(PrivateKey::Rsa(Box::new(key)))
+}
+
+/// An ECDSA key from its encoded public point and its private scalar.
+pub fn ecdsa_private_key(
+    curve: Curve,
+    public_key: &[u8],
+    private_key: &[u8],
+) -> Result<PrivateKey, DriverError> {
+    let invalid = || DriverError::new(ErrorKind::Auth, "Invalid ECDSA private key.");
+    // The scalar is an mpint: without leading zeros, but with a sign byte.
+    let scalar = |size: usize| {
+        let start = private_key
+            .iter()
+            .position(|&byte| byte != 0)
+            .unwrap_or(private_key.len());
+        let digits = &private_key[start..];
+        if digits.len() > size {
+            return Err(invalid());
+        }
+        let mut bytes = vec![0u8; size - digits.len()];
+        bytes.extend_from_slice(digits);
+        Ok(bytes)
+    };
+    // The public point must be the one of the private scalar.
+    let key = match curve {
+        Curve::P256 => {
+            let key = p256::SecretKey::from_slice(&scalar(32)?).map_err(|_| invalid())?;
+            if key.public_key().to_sec1_bytes().as_ref() != public_key {
+                return Err(invalid());
+            }
+            PrivateKey::P256(key)
+        }
+        Curve::P384 => {
+            let key = p384::SecretKey::from_slice(&scalar(48)?).map_err(|_| invalid())?;
+            if key.public_key().to_sec1_bytes().as_ref() != public_key {
+                return Err(invalid());
+            }
+            PrivateKey::P384(key)
+        }
+        Curve::P521 => {
+            let key = p521::SecretKey::from_slice(&scalar(66)?).map_err(|_| invalid())?;
+            if key.public_key().to_sec1_bytes().as_ref() != public_key {
+                return Err(invalid());
+            }
+            PrivateKey::P521(key)
+        }
+    };
+    Ok(key)
+}
+
+/// An Ed25519 key from its 32 bytes seed.
+pub fn ed25519_private_key(seed: &[u8]) -> Result<PrivateKey, DriverError> {
+    let seed: [u8; 32] = seed
+        .try_into()
+        .map_err(|_| DriverError::new(ErrorKind::Auth, "Invalid Ed25519 private key."))?;
+    Ok(PrivateKey::Ed25519(Box::new(
+        ed25519_dalek::SigningKey::from_bytes(&seed),
+    )))
+}
+
+/// Decrypt AES without padding. The key size selects AES-128, AES-192 or AES-256.
+pub fn aes_decrypt(
+    mode: AesMode,
+    key: &[u8],
+    iv: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, DriverError> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        DriverError::new(ErrorKind::Auth, &format!("AES decryption failed: {}", e))
+    };
+    let mut buf = data.to_vec();
+    macro_rules! decrypt {
+        ($aes:ty) => {
+            match mode {
+                AesMode::Ctr => ctr::Ctr128BE::<$aes>::new_from_slices(key, iv)
+                    .map_err(|e| invalid(&e))?
+                    .apply_keystream(&mut buf),
+                AesMode::Cbc => {
+                    cbc::Decryptor::<$aes>::new_from_slices(key, iv)
+                        .map_err(|e| invalid(&e))?
+                        .decrypt_padded_mut::<NoPadding>(&mut buf)
+                        .map_err(|e| invalid(&e))?;
+                }
+            }
+        };
+    }
+    match key.len() {
+        16 => decrypt!(Aes128),
+        24 => decrypt!(Aes192),
+        32 => decrypt!(Aes256),
+        len => return Err(invalid(&format!("invalid key length {}", len))),
+    }
+    Ok(buf)
+}
+
+/// Hash `data` with the hash going with the ECDSA curve.
+#[cfg(feature = "pkcs11")]
+pub fn ecdsa_hash(curve: Curve, data: &[u8]) -> Result<Vec<u8>, DriverError> {
+    Ok(match curve {
+        Curve::P256 => sha2::Sha256::digest(data).to_vec(),
+        Curve::P384 => sha2::Sha384::digest(data).to_vec(),
+        Curve::P521 => sha2::Sha512::digest(data).to_vec(),
+    })
+}
+
+/// The unencrypted PKCS#8 DER encoding of a key, as rustls loads it.
+pub fn private_key_pkcs8_der(keypair: &PrivateKey) -> Result<Vec<u8>, String> {
+    let document = match keypair {
+        PrivateKey::Rsa(key) => key.to_pkcs8_der(),
+        PrivateKey::P256(key) => key.to_pkcs8_der(),
+        PrivateKey::P384(key) => key.to_pkcs8_der(),
+        PrivateKey::P521(key) => key.to_pkcs8_der(),
+        PrivateKey::Ed25519(key) => key.to_pkcs8_der(),
+    };
+    document
+        .map(|document| document.as_bytes().to_vec())
+        .map_err(|e| e.to_string())
+}
+
+fn pkcs8_private_key(der: &[u8]) -> Result<PrivateKey, String> {
+    if let Ok(key) = RsaPrivateKey::from_pkcs8_der(der) {
+        return Ok(PrivateKey::Rsa(Box::new(key)));
+    }
+    if let Ok(key) = p256::SecretKey::from_pkcs8_der(der) {
+        return Ok(PrivateKey::P256(key));
+    }
+    if let Ok(key) = p384::SecretKey::from_pkcs8_der(der) {
+        return Ok(PrivateKey::P384(key));
+    }
+    if let Ok(key) = p521::SecretKey::from_pkcs8_der(der) {
+        return Ok(PrivateKey::P521(key));
+    }
+    if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_der(der) {
+        return Ok(PrivateKey::Ed25519(Box::new(key)));
+    }
+    Err("unsupported or invalid PKCS#8 private key".to_string())
+}
+
+fn sec1_private_key(der: &[u8]) -> Result<PrivateKey, String> {
+    if let Ok(key) = p256::SecretKey::from_sec1_der(der) {
+        return Ok(PrivateKey::P256(key));
+    }
+    if let Ok(key) = p384::SecretKey::from_sec1_der(der) {
+        return Ok(PrivateKey::P384(key));
+    }
+    if let Ok(key) = p521::SecretKey::from_sec1_der(der) {
+        return Ok(PrivateKey::P521(key));
+    }
+    Err("unsupported curve or invalid EC private key".to_string())
+}
+
+// Traditional OpenSSL encryption: "DEK-Info: <cipher>,<hex IV>", with the key
+// derived by EVP_BytesToKey (MD5, one round, salted with the first 8 bytes of the IV).
+fn legacy_decrypt(dek_info: &str, passphrase: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    let (cipher, iv) = dek_info
+        .split_once(',')
+        .ok_or_else(|| "invalid DEK-Info header".to_string())?;
+    let iv = decode_hex(iv.trim()).ok_or_else(|| "invalid DEK-Info IV".to_string())?;
+    let key_len = match cipher {
+        "AES-128-CBC" => 16,
+        "AES-192-CBC" => 24,
+        "AES-256-CBC" => 32,
+        "DES-EDE3-CBC" => 24,
+        _ => return Err(format!("unsupported PEM encryption '{}'", cipher)),
+    };
+    if iv.len() < 8 {
+        return Err("invalid DEK-Info IV".to_string());
+    }
+
+    let mut key = Vec::with_capacity(key_len + 16);
+    let mut block = Vec::new();
+    while key.len() < key_len {
+        let mut md5 = Md5::new();
+        md5.update(&block);
+        md5.update(passphrase.as_bytes());
+        md5.update(&iv[..8]);
+        block = md5.finalize().to_vec();
+        key.extend_from_slice(&block);
+    }
+    key.truncate(key_len);
+
+    let wrong_passphrase = |_| "bad decrypt, wrong passphrase?".to_string();
+    let mut buf = data.to_vec();
+    let len = match cipher {
+        "AES-128-CBC" => cbc::Decryptor::<Aes128>::new_from_slices(&key, &iv)
+            .map_err(|e| e.to_string())?
+            .decrypt_padded_mut::<Pkcs7>(&mut buf)
+            .map_err(wrong_passphrase)?
+            .len(),
+        "AES-192-CBC" => cbc::Decryptor::<Aes192>::new_from_slices(&key, &iv)
+            .map_err(|e| e.to_string())?
+            .decrypt_padded_mut::<Pkcs7>(&mut buf)
+            .map_err(wrong_passphrase)?
+            .len(),
+        "AES-256-CBC" => cbc::Decryptor::<Aes256>::new_from_slices(&key, &iv)
+            .map_err(|e| e.to_string())?
+            .decrypt_padded_mut::<Pkcs7>(&mut buf)
+            .map_err(wrong_passphrase)?
+            .len(),
+        _ => cbc::Decryptor::<TdesEde3>::new_from_slices(&key, &iv)
+            .map_err(|e| e.to_string())?
+            .decrypt_padded_mut::<Pkcs7>(&mut buf)
+            .map_err(wrong_passphrase)?
+            .len(),
+    };
+    buf.truncate(len);
+    Ok(buf)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
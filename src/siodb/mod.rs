@@ -2,12 +2,21 @@
 // Use of this source code is governed by a license that can be found
 // in the LICENSE file.
 
-// TODO: Connection pool
 // TODO: Prepared statements implementation (when Siodb supports it)
 
 mod errors;
 use errors::debug;
-use errors::DriverError;
+pub use errors::{DriverError, ErrorKind, ServerMessage};
+
+// Protocol
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+mod protocol;
+
+// Async
+#[cfg(feature = "tokio")]
+mod async_conn;
+#[cfg(feature = "tokio")]
+pub use async_conn::{AsyncRows, AsyncSiodbConn};
 
 // ResultSet
 mod results;
@@ -15,126 +24,91 @@ use results::ResultSet;
 use results::Value;
 
 // Standard
-use std::convert::TryInto;
 use std::fmt;
-use std::fs;
-use std::io::BufRead;
-use std::net::Shutdown;
-use std::net::TcpStream;
-use std::os::unix::net::UnixStream;
-use std::str::from_utf8;
-use std::str::FromStr;
-
-// DateTime
-use chrono::prelude::*;
-
-// Url for URI
-use url::Url;
-
-// OpenSSL
-use bufstream::BufStream;
-use native_tls::{TlsConnector, TlsStream};
-use openssl::hash::MessageDigest;
-use openssl::pkey::PKey;
-use openssl::rsa::Rsa;
-use openssl::sign::Signer;
 use std::io::{Read, Write};
+use std::time::Duration;
 
-// Byte order
-use byteorder::{ByteOrder, LittleEndian};
+// Buffering
+use bufstream::BufStream;
 
-// Protobuf
-mod ClientProtocol;
-use ClientProtocol::{
-    BeginSessionRequest, BeginSessionResponse, ClientAuthenticationRequest,
-    ClientAuthenticationResponse, Command, ServerResponse,
+// Credentials
+mod agent;
+mod credentials;
+mod keys;
+mod openssh;
+#[cfg_attr(not(feature = "pkcs11"), allow(dead_code))]
+mod pkcs11;
+pub use credentials::{
+    AgentCredentials, CommandCredentials, CredentialProvider, EnvCredentials, FileCredentials,
+    PemCredentials,
 };
+pub use pkcs11::PinSource;
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::Pkcs11Credentials;
+
+// TLS
+mod tls;
+pub use tls::{SslMode, TlsVersion};
+
+// Hosts
+mod hosts;
+use hosts::HostFailures;
+pub use hosts::Target;
+
+// Timeouts
+mod timeouts;
+pub use timeouts::Timeouts;
+
+// Transport
+mod transport;
+pub use transport::Transport;
+use transport::{connect_socket, Connected, StreamTransport};
+
+// Proxy
+mod proxy;
+pub use proxy::Proxy;
+
+// Options
+mod options;
+pub use options::{Scheme, SiodbConnOptions};
+mod profiles;
+
+// Pool
+mod pool;
+pub use pool::{PoolOptions, PooledConn, SiodbPool};
+
+// Protobuf (generated code)
+#[allow(warnings)]
+mod ClientProtocol;
+use ClientProtocol::{BeginSessionResponse, ClientAuthenticationResponse, ServerResponse};
+#[allow(warnings)]
 mod ColumnDataType;
+#[allow(warnings)]
 mod CommonTypes;
 
-enum ConnBufStream {
-    PlainBufUnixSocket(BufStream<UnixStream>),
-    PlainBufStream(BufStream<TcpStream>),
-    TlsBufStream(BufStream<TlsStream<TcpStream>>),
-}
-
-impl Read for ConnBufStream {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match *self {
-            ConnBufStream::PlainBufUnixSocket(ref mut stream) => stream.read(buf),
-            ConnBufStream::PlainBufStream(ref mut stream) => stream.read(buf),
-            ConnBufStream::TlsBufStream(ref mut stream) => stream.read(buf),
-        }
-    }
-}
-
-impl BufRead for ConnBufStream {
-    fn fill_buf(&mut self) -> std::result::Result<&[u8], std::io::Error> {
-        match *self {
-            ConnBufStream::PlainBufUnixSocket(ref mut stream) => stream.fill_buf(),
-            ConnBufStream::PlainBufStream(ref mut stream) => stream.fill_buf(),
-            ConnBufStream::TlsBufStream(ref mut stream) => stream.fill_buf(),
-        }
-    }
-    fn consume(&mut self, amt: usize) {
-        match *self {
-            ConnBufStream::PlainBufUnixSocket(ref mut stream) => stream.consume(amt),
-            ConnBufStream::PlainBufStream(ref mut stream) => stream.consume(amt),
-            ConnBufStream::TlsBufStream(ref mut stream) => stream.consume(amt),
-        }
-    }
-}
-
-impl Write for ConnBufStream {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match *self {
-            ConnBufStream::PlainBufUnixSocket(ref mut stream) => stream.write(buf),
-            ConnBufStream::PlainBufStream(ref mut stream) => stream.write(buf),
-            ConnBufStream::TlsBufStream(ref mut stream) => stream.write(buf),
-        }
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        match *self {
-            ConnBufStream::PlainBufUnixSocket(ref mut stream) => stream.flush(),
-            ConnBufStream::PlainBufStream(ref mut stream) => stream.flush(),
-            ConnBufStream::TlsBufStream(ref mut stream) => stream.flush(),
-        }
-    }
-}
-
-enum ConnStream {
-    UnixSocketStream(UnixStream),
-    TcpStream(TcpStream),
-}
-
-impl ConnStream {
-    fn shutdown(&mut self) -> std::io::Result<()> {
-        match *self {
-            ConnStream::UnixSocketStream(ref mut stream) => stream.shutdown(Shutdown::Both),
-            ConnStream::TcpStream(ref mut stream) => stream.shutdown(Shutdown::Both),
-        }
-    }
-}
-
 /// A connection to Siodb.
 ///
 /// ## For example:
 ///
-/// ```rust
+/// ```no_run
+///   use siodb::SiodbConn;
+///
 ///   let uri = "siodbs://root@localhost:50000?identity_file=/home/siodb/.ssh/id_rsa";
 ///   let mut siodb_conn = SiodbConn::new(&uri).expect(&format!("Error connecting to URI '{}'", uri));
 /// ```
 pub struct SiodbConn {
-    scheme: String,
+    options: SiodbConnOptions,
     host: String,
     port: u16,
-    user: String,
-    pkfile: String,
-    trace: bool,
-    stream: Option<ConnStream>,
-    buf_stream: Option<ConnBufStream>,
+    transport: Option<BufStream<Box<dyn Transport>>>,
     result_set: Option<ResultSet>,
+    // Id of the last command sent, each command taking the next one.
+    request_id: u64,
+    // An I/O error, an expired timeout or a protocol error left the stream in
+    // an unknown state.
+    broken: bool,
+    // Opened by the driver, which can open it again.
+    reconnectable: bool,
 }
 
 impl fmt::Debug for SiodbConn {
@@ -142,581 +116,667 @@ impl fmt::Debug for SiodbConn {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "scheme: {} | host: {} | port: {} | user: {} | pkfile: {} | trace: {}",
-            self.scheme, self.host, self.port, self.user, self.pkfile, self.trace,
+            "{:?} | host: {} | port: {}",
+            self.options, self.host, self.port,
         )
     }
 }
 
 impl SiodbConn {
-    fn parse_uri(uri_str: &str) -> Result<SiodbConn, DriverError> {
-        let uri = Url::parse(uri_str).expect(&format!("Unable to parse URI"));
-
-        let pairs = uri.query_pairs();
-        let mut pkfile = "~/.ssh/id_rsa".to_string();
-        let mut trace = false;
-        for pair in pairs {
-            match pair.0 {
-                _ if pair.0.to_string() == String::from("identity_file") => {
-                    pkfile = pair.1.to_string()
+    /// Create a new authenticated connection to Siodb from an URI.
+    pub fn new(uri_str: &str) -> Result<SiodbConn, DriverError> {
+        SiodbConn::open(SiodbConnOptions::from_uri(uri_str)?)
+    }
+
+    /// Create a new authenticated connection to Siodb from an URI.
+    ///
+    /// `passphrase_callback` is called with the identity file path when the private key
+    /// is encrypted and its passphrase is neither in the URI (`identity_passphrase`)
+    /// nor in the `SIODB_IDENTITY_PASSPHRASE` environment variable.
+    pub fn new_with_passphrase_callback<F>(
+        uri_str: &str,
+        passphrase_callback: F,
+    ) -> Result<SiodbConn, DriverError>
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        SiodbConn::open(
+            SiodbConnOptions::from_uri(uri_str)?.with_passphrase_callback(passphrase_callback),
+        )
+    }
+
+    /// Create a new authenticated connection to Siodb from an URI, with the user and
+    /// signing done by `credentials`. The user and identity options of the URI are ignored.
+    pub fn new_with_credentials<P>(uri_str: &str, credentials: P) -> Result<SiodbConn, DriverError>
+    where
+        P: CredentialProvider + 'static,
+    {
+        SiodbConn::open(SiodbConnOptions::from_uri(uri_str)?.with_credentials(credentials))
+    }
+
+    /// Create a new authenticated connection to Siodb from an URI, with `timeouts`
+    /// overriding the timeout options of the URI.
+    pub fn new_with_timeouts(uri_str: &str, timeouts: Timeouts) -> Result<SiodbConn, DriverError> {
+        let options = SiodbConnOptions::from_uri(uri_str)?;
+        let timeouts = timeouts.or(options.timeouts);
+        SiodbConn::open(options.with_timeouts(timeouts))
+    }
+
+    /// Create a new authenticated connection to Siodb from options built in code.
+    pub fn new_with_options(options: &SiodbConnOptions) -> Result<SiodbConn, DriverError> {
+        options.validate()?;
+        SiodbConn::open(options.clone())
+    }
+
+    /// Create a new authenticated connection to Siodb over `transport`, a stream
+    /// already connected to Siodb. The host, port and TLS options are ignored,
+    /// and `host()` and `port()` are empty.
+    pub fn new_with_transport<T>(
+        transport: T,
+        options: &SiodbConnOptions,
+    ) -> Result<SiodbConn, DriverError>
+    where
+        T: Transport + 'static,
+    {
+        options.validate()?;
+        debug(options.trace, &format!("options: {:?}", options));
+        let mut siodb_conn = SiodbConn {
+            options: options.clone(),
+            host: String::new(),
+            port: 0,
+            transport: None,
+            result_set: None,
+            request_id: 0,
+            broken: false,
+            reconnectable: false,
+        };
+        siodb_conn.attach(Box::new(transport))?;
+        siodb_conn.start()?;
+        Ok(siodb_conn)
+    }
+
+    /// Create a new authenticated connection to Siodb over a plain `Read + Write`
+    /// stream, which has no timeouts. See `new_with_transport`.
+    pub fn new_with_stream<S>(
+        stream: S,
+        options: &SiodbConnOptions,
+    ) -> Result<SiodbConn, DriverError>
+    where
+        S: Read + Write + Send + 'static,
+    {
+        SiodbConn::new_with_transport(StreamTransport(stream), options)
+    }
+
+    /// The options the connection was opened with.
+    pub fn options(&self) -> &SiodbConnOptions {
+        &self.options
+    }
+    fn open(options: SiodbConnOptions) -> Result<SiodbConn, DriverError> {
+        debug(options.trace, &format!("options: {:?}", options));
+        let mut siodb_conn = SiodbConn {
+            options,
+            host: String::new(),
+            port: 0,
+            transport: None,
+            result_set: None,
+            request_id: 0,
+            broken: false,
+            reconnectable: true,
+        };
+        siodb_conn.open_hosts()?;
+        Ok(siodb_conn)
+    }
+    // Connect to the hosts in turn until one accepts the connection and the user.
+    fn open_hosts(&mut self) -> Result<(), DriverError> {
+        let hosts = self.options.hosts_in_order();
+        let mut failures = HostFailures::new(hosts.len());
+        for (host, port) in hosts {
+            self.host = host;
+            self.port = port;
+            match self.open_host() {
+                Ok(()) => {
+                    self.broken = false;
+                    return Ok(());
                 }
-                _ if pair.0.to_string() == String::from("trace") => {
-                    trace = bool::from_str(&pair.1.to_string()).unwrap_or(trace)
+                Err(error) => {
+                    self.transport = None;
+                    debug(
+                        self.options.trace,
+                        &format!(
+                            "Connection to '{}:{}' failed: {}",
+                            self.host, self.port, error
+                        ),
+                    );
+                    failures.add(&self.host, self.port, error)?;
                 }
-                _ => return Err(DriverError::new(&format!("Unknow option: {}.", &pair.0))),
             }
         }
+        Err(failures.into_error())
+    }
+    fn open_host(&mut self) -> Result<(), DriverError> {
+        self.connect()?;
+        self.start()
+    }
 
-        // Derive values
-        let mut scheme: String = "siodbs".to_string();
-        if uri.scheme().len() > 0 {
-            scheme = uri.scheme().to_string();
-        }
-        if scheme != "siodb" && scheme != "siodbs" && scheme != "siodbu" {
-            return Err(DriverError::new(&format!(
-                "Wrong protocol: '{}'. Should be 'siodb', 'siodbs' or 'siodbu'.",
-                scheme
-            )));
-        }
-        let mut host: String = "localhost".to_string();
-        if scheme == "siodbu".to_string() {
-            host = uri.to_file_path().unwrap().to_str().unwrap().try_into().unwrap();
-        } else {
-        if uri.host().is_some() {
-            host = uri.host().unwrap().to_string();
-        }}
-        let port = uri.port().unwrap_or(50000);
-        let mut user: String = "root".to_string();
-        if uri.username().len() > 0 {
-            user = uri.username().to_string();
-        }
-
-        Ok(SiodbConn {
-            scheme,
-            host,
-            port,
-            user,
-            pkfile,
-            trace,
-            stream: None,
-            buf_stream: None,
-            result_set: None,
-        })
+    /// The host the connection is open to: the one that accepted it for a
+    /// multi-host URI, the socket path for a Unix socket connection.
+    pub fn host(&self) -> &str {
+        &self.host
     }
 
-    /// Create a new authenticated connection to Siodb from an URI.
-    pub fn new(uri_str: &str) -> Result<SiodbConn, DriverError> {
-        let mut siodb_conn = SiodbConn::parse_uri(uri_str).unwrap();
-        debug(siodb_conn.trace, &format!("siodb_conn: {:?}", siodb_conn));
-        siodb_conn.connect()?;
-        siodb_conn.authenticate()?;
-        Ok(siodb_conn)
+    /// The port the connection is open to.
+    pub fn port(&self) -> u16 {
+        self.port
     }
     fn connect(&mut self) -> Result<(), DriverError> {
-        match self.scheme.as_str() {
-            "siodb" => {
-                // TCP connection
-                let stream = TcpStream::connect(format!("{}:{}", self.host, self.port))
-                    .expect(&format!("Cannot connect to '{}:{}'", self.host, self.port));
-                self.stream = Some(ConnStream::TcpStream(stream.try_clone().unwrap()));
-                self.buf_stream = Some(ConnBufStream::PlainBufStream(BufStream::new(stream)));
-            }
-            "siodbs" => {
-                // TLS connection
-                // TODO: handle private key password
-                // TODO: implement certificat validation
-                let mut builder = TlsConnector::builder();
-                builder.danger_accept_invalid_hostnames(true);
-                builder.danger_accept_invalid_certs(true);
-                let tls_connector = builder.build().unwrap();
-                let stream = TcpStream::connect(format!("{}:{}", self.host, self.port))
-                    .expect(&format!("Cannot connect to '{}:{}'", self.host, self.port));
-                self.stream = Some(ConnStream::TcpStream(stream.try_clone().unwrap()));
-                let stream = tls_connector.connect(&self.host, stream).unwrap();
-                self.buf_stream = Some(ConnBufStream::TlsBufStream(BufStream::new(stream)));
-            }
-            "siodbu" => {
+        let transport: Box<dyn Transport> =
+            match connect_socket(&self.options, &self.host, self.port)? {
                 // Unix socket connection
-                let stream = UnixStream::connect(format!("{}", self.host))
-                    .expect(&format!("Cannot connect to socket '{}'", self.host));
-                self.stream = Some(ConnStream::UnixSocketStream(stream.try_clone().unwrap()));
-                self.buf_stream = Some(ConnBufStream::PlainBufUnixSocket(BufStream::new(stream)));
-            }
-            _ => {
-                return Err(DriverError::new(&format!("Protocol unknown.")));
-            }
-        }
+                Connected::Unix(stream) => Box::new(stream),
+                Connected::Tcp(stream) => {
+                    // The connect timeout also bounds the TLS handshake.
+                    stream.set_read_timeout(self.options.timeouts.connect)?;
+                    stream.set_write_timeout(self.options.timeouts.connect)?;
+                    let tls_options = self.options.tls_options();
+                    if tls_options.sslmode == SslMode::Disable {
+                        // TCP connection
+                        Box::new(stream)
+                    } else {
+                        // TLS connection
+                        tls::connect(&tls_options, &self.host, self.port, stream)?
+                    }
+                }
+            };
+        self.attach(transport)
+    }
 
+    // Use the transport for the session. The connect timeout bounds the
+    // authentication, until start() sets the others.
+    fn attach(&mut self, transport: Box<dyn Transport>) -> Result<(), DriverError> {
+        transport.set_read_timeout(self.options.timeouts.connect)?;
+        transport.set_write_timeout(self.options.timeouts.connect)?;
+        self.transport = Some(BufStream::with_capacities(
+            self.options.read_buffer_size,
+            self.options.write_buffer_size,
+            transport,
+        ));
         Ok(())
     }
 
-    /// Close the connection with Siodb.
-    pub fn close(&mut self) -> Result<(), DriverError> {
-        self.stream
-            .as_mut()
-            .unwrap()
-            .shutdown()
-            .expect(&format!("Error while closing connection."));
+    // Authenticate and switch to the read and write timeouts.
+    fn start(&mut self) -> Result<(), DriverError> {
+        self.authenticate()?;
+        self.set_read_timeout(self.options.timeouts.read)?;
+        self.set_write_timeout(self.options.timeouts.write)
+    }
+
+    /// Set the time to wait for data from Siodb. `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), DriverError> {
+        let transport = self.transport.as_ref().ok_or_else(closed_error)?;
+        transport.get_ref().set_read_timeout(timeout)?;
+        self.options.timeouts.read = timeout;
+        Ok(())
+    }
 
+    /// Set the time to wait for Siodb to accept data. `None` waits forever.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<(), DriverError> {
+        let transport = self.transport.as_ref().ok_or_else(closed_error)?;
+        transport.get_ref().set_write_timeout(timeout)?;
+        self.options.timeouts.write = timeout;
         Ok(())
     }
+
+    /// Close the connection with Siodb.
+    pub fn close(&mut self) -> Result<(), DriverError> {
+        self.broken = false;
+        let mut transport = self.transport.take().ok_or_else(closed_error)?;
+        transport.get_mut().shutdown().map_err(|e| {
+            DriverError::new(
+                ErrorKind::Io,
+                &format!("Error while closing connection: {}", e),
+            )
+            .with_source(e)
+        })
+    }
     fn authenticate(&mut self) -> Result<(), DriverError> {
+        let credentials = self.options.credential_provider()?;
+
         // Begin session request
-        let mut begin_session_request = BeginSessionRequest::new();
-        begin_session_request.set_user_name(self.user.as_str().to_string());
+        let begin_session_request = protocol::begin_session_request(credentials.user_name()?);
         debug(
-            self.trace,
+            self.options.trace,
             &format!("begin_session_request: {:?}", begin_session_request),
         );
-        self.write_message(5, &begin_session_request)?;
+        self.write_message(protocol::BEGIN_SESSION_REQUEST, &begin_session_request)?;
 
         // Read Session response
-        let _begin_session_response = self.read_message::<BeginSessionResponse>(6).unwrap()?;
-
-        if !_begin_session_response.get_session_started() {
-            return Err(DriverError::new(&format!("Siodb session not started.")));
-        }
+        let begin_session_response =
+            self.read_message::<BeginSessionResponse>(protocol::BEGIN_SESSION_RESPONSE)?;
+        protocol::check_session_started(&begin_session_response)?;
 
         // Hash and Sign challenge
-        let pkey = &self.pkfile;
-        let contents =
-            fs::read_to_string(pkey).expect(&format!("Error reading private key '{}'", pkey));
-        let keypair = Rsa::private_key_from_pem(contents.as_bytes())
-            .expect(&format!("Error loading private key"));
-        let keypair =
-            PKey::from_rsa(keypair).expect(&format!("Error loading private {} key", "RSA"));
-        let mut signer = Signer::new(MessageDigest::sha512(), &keypair)
-            .expect(&format!("Error creating signer"));
-        let signature = signer
-            .sign_oneshot_to_vec(_begin_session_response.get_challenge())
-            .expect(&format!("Error signing challenge"));
+        let signature = credentials.sign_challenge(begin_session_response.get_challenge())?;
 
         // Start authentication
-        let mut client_authentication_request = ClientAuthenticationRequest::new();
-        client_authentication_request.set_signature(signature);
+        let client_authentication_request = protocol::client_authentication_request(signature);
         debug(
-            self.trace,
+            self.options.trace,
             &format!(
                 "client_authentication_request: {:?}",
                 client_authentication_request
             ),
         );
-        self.write_message(7, &client_authentication_request)?;
+        self.write_message(
+            protocol::CLIENT_AUTHENTICATION_REQUEST,
+            &client_authentication_request,
+        )?;
 
         // Read Session response
-        let _client_authentication_response = self
-            .read_message::<ClientAuthenticationResponse>(8)
-            .unwrap()?;
-
-        if !_client_authentication_response.get_authenticated() {
-            return Err(DriverError::new(&format!("Siodb session not started.")));
-        }
-
-        Ok(())
+        let client_authentication_response = self.read_message::<ClientAuthenticationResponse>(
+            protocol::CLIENT_AUTHENTICATION_RESPONSE,
+        )?;
+        protocol::check_authenticated(&client_authentication_response)
     }
     fn write_message(
         &mut self,
         message_type: u32,
         message: &dyn protobuf::Message,
     ) -> Result<(), DriverError> {
-        let mut output_stream = self.buf_stream.as_mut().unwrap();
-        let mut coded_output_stream = protobuf::CodedOutputStream::new(&mut output_stream);
-
-        coded_output_stream
-            .write_raw_varint32(message_type)
-            .expect(&format!("write_message | Codec error"));
-
-        coded_output_stream
-            .write_raw_varint32(message.compute_size())
-            .expect(&format!("write_message | Codec error"));
-        &message
-            .write_to_with_cached_sizes(&mut coded_output_stream)
-            .expect(&format!("write_message | Codec error"));
-        coded_output_stream
-            .flush()
-            .expect(&format!("write_message | Codec error"));
-
-        self.buf_stream
-            .as_mut()
-            .unwrap()
-            .flush()
-            .expect(&format!("write_message | Codec error"));
-
+        self.queue_message(message_type, message)?;
+        self.flush_messages()
+    }
+    // Write a message to the buffer, sent to Siodb once full or flushed.
+    fn queue_message(
+        &mut self,
+        message_type: u32,
+        message: &dyn protobuf::Message,
+    ) -> Result<(), DriverError> {
+        let result = self.write_raw_message(message_type, message);
+        self.track_broken(result)
+    }
+    fn write_raw_message(
+        &mut self,
+        message_type: u32,
+        message: &dyn protobuf::Message,
+    ) -> Result<(), DriverError> {
+        let bytes = protocol::encode_message(message_type, message)?;
+        let output_stream = self.transport.as_mut().ok_or_else(closed_error)?;
+        output_stream.write_all(&bytes)?;
         Ok(())
     }
-    fn read_message<M: protobuf::Message>(
+    fn flush_messages(&mut self) -> Result<(), DriverError> {
+        let result = match self.transport.as_mut() {
+            Some(output_stream) => output_stream.flush().map_err(DriverError::from),
+            None => Err(closed_error()),
+        };
+        self.track_broken(result)
+    }
+    fn read_message<M: protobuf::Message>(&mut self, message_type: u32) -> Result<M, DriverError> {
+        let result = self.read_raw_message(message_type);
+        self.track_broken(result)
+    }
+    fn read_raw_message<M: protobuf::Message>(
         &mut self,
         message_type: u32,
-    ) -> Result<protobuf::ProtobufResult<M>, DriverError> {
-        let mut input_stream = self.buf_stream.as_mut().unwrap();
-        let mut coded_input_stream =
-            protobuf::CodedInputStream::from_buffered_reader(&mut input_stream);
-
-        let message_type_received = coded_input_stream
-            .read_raw_varint32()
-            .expect(&format!("read_message | Codec error"));
-        debug(self.trace, &format!("message_type: {:?}", message_type));
-        if message_type != message_type_received {
-            return Err(DriverError::new(&format!(
-                "read_message | wrong message type received from Siodb: {}. Expected: {}.",
-                message_type_received, message_type
-            )));
+    ) -> Result<M, DriverError> {
+        let input_stream = self.transport.as_mut().ok_or_else(closed_error)?;
+        let mut coded_input_stream = protobuf::CodedInputStream::from_buffered_reader(input_stream);
+
+        let message_type_received = coded_input_stream.read_raw_varint32()?;
+        debug(
+            self.options.trace,
+            &format!("message_type: {:?}", message_type),
+        );
+        let length = coded_input_stream.read_raw_varint32()?;
+        let bytes = coded_input_stream.read_raw_bytes(length)?;
+        protocol::parse_message(message_type, message_type_received, &bytes)
+    }
+    // An I/O error or an expired timeout may leave a message half sent or half read,
+    // and a protocol error shows the stream is out of step.
+    fn track_broken<T>(&mut self, result: Result<T, DriverError>) -> Result<T, DriverError> {
+        if let Err(error) = &result {
+            if matches!(
+                error.kind(),
+                ErrorKind::Io | ErrorKind::Timeout | ErrorKind::Protocol
+            ) {
+                self.broken = true;
+            }
+        }
+        result
+    }
+    // Before a statement is sent, a broken connection is opened again with
+    // auto_reconnect, and refused otherwise.
+    fn check_connection(&mut self) -> Result<(), DriverError> {
+        let auto_reconnect = self.options.auto_reconnect && self.reconnectable;
+        match &self.transport {
+            None if !self.broken => return Err(closed_error()),
+            Some(transport)
+                if !self.broken && auto_reconnect && transport.get_ref().is_closed() =>
+            {
+                debug(self.options.trace, "The connection was closed by Siodb.");
+                self.broken = true;
+            }
+            _ => {}
+        }
+        if !self.broken {
+            return Ok(());
+        }
+        if !auto_reconnect {
+            return Err(broken_error());
+        }
+        debug(self.options.trace, "Reconnecting.");
+        if let Some(mut transport) = self.transport.take() {
+            let _ = transport.get_mut().shutdown();
+        }
+        self.result_set = None;
+        self.open_hosts()
+    }
+    // Open, not broken and without rows or responses left to read: fit for the
+    // next statement.
+    fn is_clean(&self) -> bool {
+        self.transport.is_some() && !self.broken && !self.has_unread_results()
+    }
+    fn has_unread_results(&self) -> bool {
+        self.result_set
+            .as_ref()
+            .is_some_and(|result_set| !result_set.end_of_row || result_set.has_more_responses())
+    }
+
+    /// Check the connection with a round-trip to Siodb, running `SELECT 1`. With
+    /// auto_reconnect, a connection found broken is opened again first, dropping
+    /// the result of the previous statement.
+    pub fn ping(&mut self) -> Result<(), DriverError> {
+        self.check_connection()?;
+        if self.has_unread_results() {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "ping | There is still data in the buffer.",
+            ));
         }
-        let message = coded_input_stream
-            .read_message()
-            .expect(&format!("read_message | Codec error"));
+        // Keep the result of the previous statement.
+        let result_set = self.result_set.take();
+        let result = self
+            .send_statement(PING_STATEMENT.to_string())
+            .and_then(|()| {
+                while self.next()? {}
+                Ok(())
+            });
+        self.result_set = result_set;
+        result
+    }
 
-        Ok(Ok(message))
+    /// Whether `ping()` succeeds.
+    pub fn is_valid(&mut self) -> bool {
+        self.ping().is_ok()
     }
-    /// Execute a statement in a connection.
+
+    /// Execute a statement, or a script of several statements, in a connection.
+    /// The connection is then on the result of the first statement; see `next_result`.
     pub fn execute(&mut self, sql: String) -> Result<(), DriverError> {
-        if self.result_set.is_some() && !self.result_set.as_mut().unwrap().end_of_row {
-            return Err(DriverError::new(&format!(
-                "execute | There is still data in the buffer."
-            )));
+        self.check_connection()?;
+        if self.has_unread_results() {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "execute | There is still data in the buffer.",
+            ));
         }
+        self.send_statement(sql)
+    }
 
+    // Send a statement on a checked connection and read its first response.
+    fn send_statement(&mut self, sql: String) -> Result<(), DriverError> {
         // Send command
-        let mut command = Command::new();
-        command.set_request_id(1);
-        command.set_text(sql);
-        debug(self.trace, &format!("command: {:?}", command));
-        self.write_message(1, &command)?;
+        self.request_id += 1;
+        let command = protocol::command(self.request_id, sql);
+        debug(self.options.trace, &format!("command: {:?}", command));
+        self.write_message(protocol::COMMAND, &command)?;
 
         // Read server response
-        self.result_set = Some(ResultSet::new(
-            self.read_message::<ServerResponse>(2).unwrap()?,
-        )?);
+        self.read_response(self.request_id, 0)
+    }
+
+    /// Move to the result of the next statement of a script, skipping the rows left
+    /// to read. Return `false` once the results of all its statements were read.
+    /// Like `execute`, a statement that failed in Siodb returns an
+    /// `ErrorKind::Server` error, and the results that follow can still be read.
+    pub fn next_result(&mut self) -> Result<bool, DriverError> {
+        let response_id = match &self.result_set {
+            Some(result_set) if result_set.has_more_responses() => {
+                result_set.server_response.get_response_id() + 1
+            }
+            _ => return Ok(false),
+        };
+        if self.broken {
+            return Err(broken_error());
+        }
+        while self.next()? {}
+        self.read_response(self.request_id, response_id)?;
+        Ok(true)
+    }
+
+    // Read the response `response_id` to the command `request_id` and make it the
+    // current result.
+    fn read_response(&mut self, request_id: u64, response_id: u32) -> Result<(), DriverError> {
+        let server_response = self.read_message::<ServerResponse>(protocol::SERVER_RESPONSE)?;
+        let checked = protocol::check_response(request_id, response_id, &server_response);
+        self.track_broken(checked)?;
+        let result_set = ResultSet::new(server_response, self.options.trace);
         debug(
-            self.trace,
-            &format!(
-                "ServerResponse: {:?}",
-                self.result_set.as_ref().unwrap().server_response
-            ),
+            self.options.trace,
+            &format!("ServerResponse: {:?}", result_set.server_response),
         );
 
         // Check if error arrives from Siodb server
-        if self
-            .result_set
-            .as_ref()
-            .unwrap()
-            .server_response
-            .message
-            .len()
-            > 0
-        {
-            let mut error_messages = String::new();
-            for column in &self.result_set.as_ref().unwrap().server_response.message {
-                error_messages = error_messages + &column.text.to_string();
+        let error = result_set.server_error();
+        self.result_set = Some(result_set);
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Execute statements in a pipeline: the commands are sent back-to-back and
+    /// their responses read afterwards, saving a round-trip per statement. Return
+    /// the result of each statement: its number of affected rows, or the error
+    /// Siodb returned for it. The rows of queries are skipped. Any other error,
+    /// e.g. an I/O error, stops the batch and breaks the connection: it is the
+    /// last result returned, and the statements after it have none. The batch
+    /// returns an error only when it cannot start.
+    pub fn execute_batch(
+        &mut self,
+        statements: Vec<String>,
+    ) -> Result<Vec<Result<u64, DriverError>>, DriverError> {
+        self.check_connection()?;
+        if self.has_unread_results() {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "execute_batch | There is still data in the buffer.",
+            ));
+        }
+
+        let first_request_id = self.request_id + 1;
+        let statement_count = statements.len();
+        let mut statements = statements.into_iter();
+        let mut results = Vec::with_capacity(statement_count);
+        let mut sent = 0;
+        while results.len() < statement_count {
+            // Keep a bounded number of commands in flight, so that Siodb never
+            // blocks sending responses while the driver is still sending commands.
+            let in_flight = sent - results.len();
+            let request_id = first_request_id + results.len() as u64;
+            let result = self
+                .send_batch_commands(statements.by_ref().take(BATCH_WINDOW - in_flight))
+                .and_then(|count| {
+                    sent += count;
+                    self.read_batch_result(request_id)
+                });
+            match result {
+                Ok(result) => results.push(result),
+                Err(error) => {
+                    results.push(Err(error));
+                    break;
+                }
             }
-            return Err(DriverError::new(&format!(
-                "execute | Error message(s) {}.",
-                error_messages
-            )));
         }
+        Ok(results)
+    }
 
-        // Check dataset presence
-        let column_count = self
-            .result_set
-            .as_ref()
-            .unwrap()
-            .server_response
-            .get_column_description()
-            .len();
-
-        if column_count > 0 {
-            self.result_set.as_mut().unwrap().end_of_row = false;
-            debug(
-                self.trace,
-                &format!(
-                    "Dataset present in the the server's response with {} colmuns.",
-                    self.result_set
-                        .as_ref()
-                        .unwrap()
-                        .server_response
-                        .get_column_description()
-                        .len()
-                ),
-            );
-
-            // Check if nullbitmask present
-            for column in &self
+    // Send the commands of a batch and return how many were sent.
+    fn send_batch_commands<I>(&mut self, statements: I) -> Result<usize, DriverError>
+    where
+        I: Iterator<Item = String>,
+    {
+        let mut count = 0;
+        for sql in statements {
+            self.request_id += 1;
+            let command = protocol::command(self.request_id, sql);
+            debug(self.options.trace, &format!("command: {:?}", command));
+            self.queue_message(protocol::COMMAND, &command)?;
+            count += 1;
+        }
+        self.flush_messages()?;
+        Ok(count)
+    }
+
+    // Read the responses to the command `request_id` of a batch and skip their rows:
+    // the affected rows of its statements, or the first error Siodb returned.
+    fn read_batch_result(
+        &mut self,
+        request_id: u64,
+    ) -> Result<Result<u64, DriverError>, DriverError> {
+        let mut affected_row_count = 0;
+        let mut statement_error = None;
+        let mut response_id = 0;
+        loop {
+            match self.read_response(request_id, response_id) {
+                Ok(()) => affected_row_count += self.get_affected_row_count(),
+                Err(error) if error.kind() == ErrorKind::Server => {
+                    statement_error.get_or_insert(error);
+                }
+                Err(error) => return Err(error),
+            }
+            while self.next()? {}
+            if !self
                 .result_set
                 .as_ref()
-                .unwrap()
-                .server_response
-                .column_description
+                .is_some_and(|result_set| result_set.has_more_responses())
             {
-                if column.is_null {
-                    self.result_set.as_mut().unwrap().null_bit_mask_present = true;
-                    debug(self.trace, &format!("null_bit_mask_present: true."));
-                    // Get nul bitmask byte size
-                    if column_count % 8 == 0 {
-                        self.result_set.as_mut().unwrap().null_bit_mask_byte_size =
-                            (column_count / 8).try_into().unwrap();
-                    } else {
-                        self.result_set.as_mut().unwrap().null_bit_mask_byte_size =
-                            (column_count / 8 + 1).try_into().unwrap();
-                    }
-                    debug(
-                        self.trace,
-                        &format!(
-                            "null_bit_mask_byte_size: {}.",
-                            self.result_set.as_mut().unwrap().null_bit_mask_byte_size
-                        ),
-                    );
-                    break;
-                }
+                break;
             }
+            response_id += 1;
         }
-
-        Ok(())
+        Ok(match statement_error {
+            Some(error) => Err(error),
+            None => Ok(affected_row_count),
+        })
     }
 
     /// Execute a query in a connection, return the first row and discard the others.
-    pub fn query_row(&mut self, sql: String) -> Option<Vec<Option<Value>>> {
+    pub fn query_row(&mut self, sql: String) -> Result<Option<Vec<Option<Value>>>, DriverError> {
         let mut row: Option<Vec<Option<Value>>> = None;
-        self.execute(sql).unwrap();
-        if self.next().unwrap() {
+        self.execute(sql)?;
+        if self.next()? {
             row = Some(self.scan().to_vec());
         }
         // Skip others rows if any.
-        while self.next().unwrap() {}
-        row
+        while self.next()? {}
+        Ok(row)
     }
     /// Execute a query in a connection.
     pub fn query(&mut self, sql: String) -> Result<(), DriverError> {
         self.execute(sql)
     }
     /// Read the next row from the result set.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Result<bool, DriverError> {
-        let mut row = Vec::<Option<Value>>::new();
-        let mut input_stream = self.buf_stream.as_mut().unwrap();
-        let mut coded_input_stream =
-            protobuf::CodedInputStream::from_buffered_reader(&mut input_stream);
+        let result = self.read_row();
+        self.track_broken(result)
+    }
+    fn read_row(&mut self) -> Result<bool, DriverError> {
+        let result_set = self
+            .result_set
+            .as_mut()
+            .ok_or_else(|| DriverError::new(ErrorKind::Config, "next | No statement executed."))?;
 
-        debug(self.trace, &format!("ResultSet.next() | ---"));
+        debug(self.options.trace, "ResultSet.next() | ---");
 
-        if self.result_set.as_ref().unwrap().end_of_row {
+        if result_set.end_of_row {
             return Ok(false);
         }
 
-        let row_length = coded_input_stream
-            .read_raw_varint32()
-            .expect(&format!("Codec error"));
-        debug(self.trace, &format!("Row bytes row_length: {}", row_length));
+        let input_stream = self.transport.as_mut().ok_or_else(closed_error)?;
+        let mut coded_input_stream = protobuf::CodedInputStream::from_buffered_reader(input_stream);
+
+        let row_length = coded_input_stream.read_raw_varint32()?;
+        debug(
+            self.options.trace,
+            &format!("Row bytes row_length: {}", row_length),
+        );
         if row_length == 0 {
-            self.result_set.as_mut().unwrap().end_of_row = true;
+            result_set.end_of_row = true;
             return Ok(false);
         } else {
-            self.result_set.as_mut().unwrap().row_count += 1;
-        }
-
-        // Read null Bitmask to figure out null value which are not streamed.
-        let mut bit_mask: Vec<u8> = Vec::new();
-        if self.result_set.as_ref().unwrap().null_bit_mask_present {
-            bit_mask = coded_input_stream
-                .read_raw_bytes(self.result_set.as_ref().unwrap().null_bit_mask_byte_size as u32)
-                .unwrap();
-            debug(
-                self.trace,
-                &format!("ResultSet.next() | Bitmask value: {:?}.", bit_mask),
-            );
+            result_set.row_count += 1;
         }
 
-        // Read Row data
-        let mut is_null: u8 = 0;
-        for (idx, column) in self
-            .result_set
-            .as_ref()
-            .unwrap()
-            .server_response
-            .column_description
-            .iter()
-            .enumerate()
-        {
-            if self.result_set.as_ref().unwrap().null_bit_mask_present {
-                let mask = 1 << (idx % 8);
-                is_null = (bit_mask[idx / 8] & mask) >> (idx % 8);
-                debug(
-                    self.trace,
-                    &format!(
-                        "ResultSet.next() | Is that cell (id: {:?} ) null?: {:?}.",
-                        idx, is_null
-                    ),
-                );
-            }
-
-            if is_null == 1 {
-                row.push(None)
-            } else {
-                debug(
-                    self.trace,
-                    &format!("read_data | data type: {:?}.", column.field_type),
-                );
-                match column.field_type {
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT8 => row.push(Some(
-                        Value::Int8(coded_input_stream.read_raw_bytes(1).unwrap()[0] as i8),
-                    )),
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT8 => row.push(Some(
-                        Value::Uint8(coded_input_stream.read_raw_bytes(1).unwrap()[0] as u8),
-                    )),
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT16 => {
-                        row.push(Some(Value::Int16(LittleEndian::read_i16(
-                            &coded_input_stream.read_raw_bytes(2).unwrap(),
-                        ))))
-                    }
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT16 => {
-                        row.push(Some(Value::Uint16(LittleEndian::read_u16(
-                            &coded_input_stream.read_raw_bytes(2).unwrap(),
-                        ))))
-                    }
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT32 => row.push(Some(
-                        Value::Int32(coded_input_stream.read_raw_varint32().unwrap() as i32),
-                    )),
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT32 => row.push(Some(
-                        Value::Uint32(coded_input_stream.read_raw_varint32().unwrap()),
-                    )),
-
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_FLOAT => {
-                        row.push(Some(Value::Float(coded_input_stream.read_float().unwrap())))
-                    }
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_DOUBLE => row.push(Some(
-                        Value::Double(coded_input_stream.read_double().unwrap()),
-                    )),
-
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT64 => row.push(Some(
-                        Value::Int64(coded_input_stream.read_raw_varint64().unwrap() as i64),
-                    )),
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT64 => row.push(Some(
-                        Value::Uint64(coded_input_stream.read_raw_varint64().unwrap()),
-                    )),
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_TEXT => {
-                        let data_length = coded_input_stream.read_raw_varint32().unwrap();
-                        row.push(Some(Value::Text(
-                            from_utf8(&coded_input_stream.read_raw_bytes(data_length).unwrap())
-                                .unwrap()
-                                .to_string(),
-                        )));
-                    }
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_BINARY => {
-                        let data_length = coded_input_stream.read_raw_varint32().unwrap();
-                        row.push(Some(Value::Binary(
-                            coded_input_stream.read_raw_bytes(data_length).unwrap(),
-                        )));
-                    }
-                    ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_TIMESTAMP => {
-                        let has_time_part: u8;
-                        let year: i32;
-                        let month: u8;
-                        let day_of_week: u8;
-                        let day_of_month: u8;
-                        let mut hours: u8 = 0;
-                        let mut minutes: u8 = 0;
-                        let mut seconds: u8 = 0;
-                        let mut nano: u32 = 0;
-                        // Get date part, 4 first bytes
-                        let date = coded_input_stream.read_raw_bytes(4).unwrap();
-                        debug(
-                            self.trace,
-                            &format!(
-                                "Binary timestamp: {:08b} {:08b} {:08b} {:08b} ",
-                                date[0], date[1], date[2], date[3]
-                            ),
-                        );
-                        has_time_part = date[0] & 0b0000_0001;
-                        day_of_week = (date[0] & 0b0000_1110) >> 1;
-                        day_of_month =
-                            (((date[0] & 0b1111_0000) >> 4) + ((date[1] & 0b0000_0001) << 4)) + 1;
-                        month = ((date[1] & 0b0001_1110) >> 1) + 1;
-                        let year_bytes = [
-                            0b0000_0000,
-                            (date[3] & 0b1110_0000) >> 5,
-                            ((date[2] & 0b1110_0000) >> 5) + ((date[3] & 0b0001_1111) << 3),
-                            ((date[1] & 0b1110_0000) >> 5) + ((date[2] & 0b0001_1111) << 3),
-                        ];
-                        year = i32::from_be_bytes(year_bytes);
-                        debug(
-                            self.trace,
-                            &format!(
-                                "hasTimePart: {:?} | dayOfWeek: {:?} | dayOfMonth: {:?} | month: {:?} | year: {:?} ",
-                                has_time_part, day_of_week, day_of_month, month, year
-                            ),
-                        );
-                        if has_time_part == 1 {
-                            // Get time part, 6 last bytes
-                            let time = coded_input_stream.read_raw_bytes(6).unwrap();
-                            let nano_bytes = [
-                                ((time[3] & 0b0111_1110) >> 1),
-                                ((time[2] & 0b1111_1110) >> 1) + ((time[3] & 0b0000_0001) << 7),
-                                ((time[1] & 0b1111_1110) >> 1) + ((time[2] & 0b0000_0001) << 7),
-                                ((time[0] & 0b1111_1110) >> 1) + ((time[1] & 0b0000_0001) << 7),
-                            ];
-                            nano = u32::from_be_bytes(nano_bytes);
-                            seconds =
-                                ((time[3] & 0b1000_0000) >> 7) + ((time[4] & 0b0001_1111) << 1);
-                            minutes =
-                                ((time[4] & 0b1110_0000) >> 5) + ((time[5] & 0b0000_0111) << 3);
-                            hours = (time[5] & 0b1111_1000) >> 3;
-                            debug(
-                                self.trace,
-                                &format!(
-                                    "hours: {:?} | minutes: {:?} | seconds: {:?} | nano: {:?} | nano_bytes: {:?}",
-                                    hours, minutes, seconds as u32, nano, nano_bytes
-                                ),
-                            );
-                        }
-                        row.push(Some(Value::Timestamp(
-                            Utc.ymd(year, month.into(), day_of_month.into())
-                                .and_hms_nano(hours.into(), minutes.into(), seconds.into(), nano),
-                        )));
-                    }
-                    _ => {
-                        return Err(DriverError::new(&format!(
-                            "read_data | Unknow data type: {:?}.",
-                            column.field_type
-                        )))
-                    }
-                }
-            }
-        }
-
-        self.result_set.as_mut().unwrap().current_row = Some(row);
+        let row = coded_input_stream.read_raw_bytes(row_length)?;
+        let row = protocol::decode_row(&row, result_set, self.options.trace)?;
+        result_set.current_row = Some(row);
 
         Ok(true)
     }
 
     /// Return last row fetched from next().
+    /// The row is empty before the first call to next().
     pub fn scan(&self) -> &Vec<Option<Value>> {
         self.result_set
             .as_ref()
-            .unwrap()
-            .current_row
-            .as_ref()
-            .unwrap()
+            .and_then(|result_set| result_set.current_row.as_ref())
+            .unwrap_or(&EMPTY_ROW)
     }
 
     /// Return the total number of rows read to far from next().
     pub fn get_row_count(&mut self) -> u64 {
-        self.result_set.as_ref().unwrap().row_count
+        self.result_set
+            .as_ref()
+            .map_or(0, |result_set| result_set.row_count)
     }
 
     /// Return the number of affected rows from the previous statement.
     pub fn get_affected_row_count(&mut self) -> u64 {
-        if self
-            .result_set
-            .as_ref()
-            .unwrap()
-            .server_response
-            .get_has_affected_row_count()
-        {
-            self.result_set
-                .as_ref()
-                .unwrap()
-                .server_response
-                .get_affected_row_count()
-        } else {
-            0
+        match &self.result_set {
+            Some(result_set) if result_set.server_response.get_has_affected_row_count() => {
+                result_set.server_response.get_affected_row_count()
+            }
+            _ => 0,
         }
     }
+
+    /// Return the status messages Siodb sent with the result of the previous statement.
+    pub fn get_server_messages(&self) -> Vec<ServerMessage> {
+        self.result_set
+            .as_ref()
+            .map_or_else(Vec::new, |result_set| result_set.server_messages())
+    }
+}
+
+// The statement of ping(), run by the pool on every checkout: a constant row, with
+// no table to read and no result growing with the server's catalog.
+const PING_STATEMENT: &str = "SELECT 1";
+
+// Most commands of a batch sent ahead of their responses.
+const BATCH_WINDOW: usize = 64;
+
+// What scan() returns before the first row.
+static EMPTY_ROW: Vec<Option<Value>> = Vec::new();
+
+fn closed_error() -> DriverError {
+    DriverError::new(ErrorKind::Io, "The connection is closed.")
+}
+
+fn broken_error() -> DriverError {
+    DriverError::new(
+        ErrorKind::Io,
+        "The connection is broken by a previous error.",
+    )
 }
@@ -0,0 +1,835 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::agent;
+use crate::siodb::credentials::{AgentCredentials, CredentialProvider, FileCredentials};
+use crate::siodb::errors::{debug, DriverError, ErrorKind};
+use crate::siodb::hosts::{bare_host, parse_host, split_hosts, Target, DEFAULT_PORT};
+use crate::siodb::keys::PassphraseCallback;
+#[cfg(feature = "pkcs11")]
+use crate::siodb::pkcs11::Pkcs11Credentials;
+use crate::siodb::pkcs11::{PinSource, Pkcs11Options};
+use crate::siodb::profiles::expand_home;
+use crate::siodb::proxy::Proxy;
+use crate::siodb::timeouts::{format_timeout, non_zero, parse_timeout, Timeouts};
+use crate::siodb::tls::{format_fingerprint, parse_fingerprint, SslMode, TlsOptions, TlsVersion};
+
+// Standard
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Url for URI
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use url::Url;
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_USER: &str = "root";
+const DEFAULT_IDENTITY_FILE: &str = "~/.ssh/id_rsa";
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// How the driver reaches Siodb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Plain TCP (`siodb://`).
+    Tcp,
+    /// TLS over TCP (`siodbs://`).
+    Tls,
+    /// Unix socket (`siodbu:`).
+    Unix,
+}
+
+impl FromStr for Scheme {
+    type Err = DriverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "siodb" => Ok(Scheme::Tcp),
+            "siodbs" => Ok(Scheme::Tls),
+            "siodbu" => Ok(Scheme::Unix),
+            _ => Err(DriverError::new(
+                ErrorKind::Config,
+                &format!(
+                    "Wrong protocol: '{}'. Should be 'siodb', 'siodbs' or 'siodbu'.",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scheme::Tcp => write!(f, "siodb"),
+            Scheme::Tls => write!(f, "siodbs"),
+            Scheme::Unix => write!(f, "siodbu"),
+        }
+    }
+}
+
+/// Settings of a connection to Siodb, built in code or parsed from an URI.
+///
+/// Printed with `Display`, the options are an URI with the passphrases and the
+/// proxy password hidden. `to_uri()` keeps them, so that `from_uri()` reads the same options back, except
+/// for the credential provider, the passphrase callback and a PKCS#11 PIN value,
+/// which have no URI form.
+///
+/// ## For example:
+///
+/// ```no_run
+///   use siodb::{SiodbConn, SiodbConnOptions};
+///
+///   let options = SiodbConnOptions::new()
+///       .with_host("localhost", 50000)
+///       .with_user("root")
+///       .with_identity_file("/home/siodb/.ssh/id_rsa");
+///   let mut siodb_conn = SiodbConn::new_with_options(&options).expect("Error connecting");
+/// ```
+#[derive(Clone)]
+pub struct SiodbConnOptions {
+    pub(crate) scheme: Scheme,
+    pub(crate) hosts: Vec<(String, u16)>,
+    pub(crate) socket_path: Option<String>,
+    pub(crate) target: Target,
+    pub(crate) proxy: Option<Proxy>,
+    pub(crate) user: String,
+    pub(crate) identity_file: String,
+    pub(crate) identity_passphrase: Option<String>,
+    pub(crate) passphrase_callback: Option<PassphraseCallback>,
+    pub(crate) agent: Option<bool>,
+    pub(crate) agent_socket: Option<String>,
+    pub(crate) pkcs11_module: Option<String>,
+    pub(crate) pkcs11_slot: Option<u64>,
+    pub(crate) pkcs11_key_label: Option<String>,
+    pub(crate) pkcs11_pin_source: Option<PinSource>,
+    pub(crate) credentials: Option<Arc<dyn CredentialProvider>>,
+    pub(crate) sslmode: Option<SslMode>,
+    pub(crate) ssl_min_protocol_version: Option<TlsVersion>,
+    pub(crate) tls_server_name: Option<String>,
+    pub(crate) cert_sha256: Vec<[u8; 32]>,
+    pub(crate) ca_file: Option<String>,
+    pub(crate) ca_dir: Option<String>,
+    pub(crate) client_cert: Option<String>,
+    pub(crate) client_key: Option<String>,
+    pub(crate) client_key_passphrase: Option<String>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) nodelay: bool,
+    pub(crate) read_buffer_size: usize,
+    pub(crate) write_buffer_size: usize,
+    pub(crate) auto_reconnect: bool,
+    pub(crate) trace: bool,
+}
+
+impl Default for SiodbConnOptions {
+    fn default() -> Self {
+        SiodbConnOptions {
+            scheme: Scheme::Tls,
+            hosts: vec![(DEFAULT_HOST.to_string(), DEFAULT_PORT)],
+            socket_path: None,
+            target: Target::Any,
+            proxy: None,
+            user: DEFAULT_USER.to_string(),
+            identity_file: DEFAULT_IDENTITY_FILE.to_string(),
+            identity_passphrase: None,
+            passphrase_callback: None,
+            agent: None,
+            agent_socket: None,
+            pkcs11_module: None,
+            pkcs11_slot: None,
+            pkcs11_key_label: None,
+            pkcs11_pin_source: None,
+            credentials: None,
+            sslmode: None,
+            ssl_min_protocol_version: None,
+            tls_server_name: None,
+            cert_sha256: Vec::new(),
+            ca_file: None,
+            ca_dir: None,
+            client_cert: None,
+            client_key: None,
+            client_key_passphrase: None,
+            timeouts: Timeouts::new(),
+            keepalive: None,
+            nodelay: false,
+            read_buffer_size: DEFAULT_BUFFER_SIZE,
+            write_buffer_size: DEFAULT_BUFFER_SIZE,
+            auto_reconnect: false,
+            trace: false,
+        }
+    }
+}
+
+impl SiodbConnOptions {
+    /// TLS connection to `root@localhost:50000` with the identity file `~/.ssh/id_rsa`.
+    pub fn new() -> SiodbConnOptions {
+        SiodbConnOptions::default()
+    }
+
+    /// Parse the options of an URI.
+    pub fn from_uri(uri_str: &str) -> Result<SiodbConnOptions, DriverError> {
+        let (uri_str, other_hosts) = split_hosts(uri_str);
+        let uri = Url::parse(&uri_str).map_err(|e| {
+            DriverError::new(ErrorKind::Config, &format!("Unable to parse URI: {}", e))
+        })?;
+
+        let mut options = SiodbConnOptions::new();
+        for (name, value) in uri.query_pairs() {
+            options.set_option(&name, &value)?;
+        }
+
+        options.scheme = Scheme::from_str(uri.scheme())?;
+        if options.scheme == Scheme::Unix {
+            options.socket_path = Some(
+                uri.to_file_path()
+                    .ok()
+                    .and_then(|path| path.to_str().map(|path| path.to_string()))
+                    .ok_or_else(|| {
+                        DriverError::new(ErrorKind::Config, "Wrong Unix socket path.")
+                    })?,
+            );
+        } else {
+            let host = uri.host_str().map_or(DEFAULT_HOST, bare_host).to_string();
+            options.hosts = vec![(host, uri.port().unwrap_or(DEFAULT_PORT))];
+            for entry in other_hosts {
+                options
+                    .hosts
+                    .push(parse_host(uri.scheme(), &entry, DEFAULT_PORT)?);
+            }
+        }
+        if !uri.username().is_empty() {
+            options.user = percent_decode_str(uri.username())
+                .decode_utf8()
+                .map_err(|_| DriverError::new(ErrorKind::Config, "Wrong user name."))?
+                .to_string();
+        }
+
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// The URI of the options, passphrases included.
+    pub fn to_uri(&self) -> String {
+        self.uri(false)
+    }
+
+    pub fn with_scheme(mut self, scheme: Scheme) -> SiodbConnOptions {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Host to connect to over TCP, replacing the hosts set so far.
+    pub fn with_host(mut self, host: &str, port: u16) -> SiodbConnOptions {
+        self.hosts = vec![(bare_host(host).to_string(), port)];
+        self
+    }
+
+    /// Another host to fail over to.
+    pub fn add_host(mut self, host: &str, port: u16) -> SiodbConnOptions {
+        self.hosts.push((bare_host(host).to_string(), port));
+        self
+    }
+
+    /// How the hosts are tried when there are several.
+    pub fn with_target(mut self, target: Target) -> SiodbConnOptions {
+        self.target = target;
+        self
+    }
+
+    /// Connect through the Unix socket at `path`.
+    pub fn with_unix_socket(mut self, path: &str) -> SiodbConnOptions {
+        self.scheme = Scheme::Unix;
+        self.socket_path = Some(path.to_string());
+        self
+    }
+
+    /// Open the TCP connections through `proxy`.
+    pub fn with_proxy(mut self, proxy: Proxy) -> SiodbConnOptions {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn with_user(mut self, user: &str) -> SiodbConnOptions {
+        self.user = user.to_string();
+        self
+    }
+
+    pub fn with_identity_file(mut self, path: &str) -> SiodbConnOptions {
+        self.identity_file = path.to_string();
+        self
+    }
+
+    pub fn with_identity_passphrase(mut self, passphrase: &str) -> SiodbConnOptions {
+        self.identity_passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    /// Callback asked for the passphrase of an encrypted identity file, with its path.
+    pub fn with_passphrase_callback<F>(mut self, passphrase_callback: F) -> SiodbConnOptions
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.passphrase_callback = Some(Arc::new(passphrase_callback));
+        self
+    }
+
+    /// `true` to always sign with the ssh-agent, `false` to never use it.
+    pub fn with_agent(mut self, agent: bool) -> SiodbConnOptions {
+        self.agent = Some(agent);
+        self
+    }
+
+    pub fn with_agent_socket(mut self, path: &str) -> SiodbConnOptions {
+        self.agent_socket = Some(path.to_string());
+        self
+    }
+
+    /// Sign with the key labelled `key_label` of a token of the PKCS#11 `module`.
+    pub fn with_pkcs11(mut self, module: &str, key_label: &str) -> SiodbConnOptions {
+        self.pkcs11_module = Some(module.to_string());
+        self.pkcs11_key_label = Some(key_label.to_string());
+        self
+    }
+
+    pub fn with_pkcs11_slot(mut self, slot: u64) -> SiodbConnOptions {
+        self.pkcs11_slot = Some(slot);
+        self
+    }
+
+    pub fn with_pkcs11_pin_source(mut self, pin_source: PinSource) -> SiodbConnOptions {
+        self.pkcs11_pin_source = Some(pin_source);
+        self
+    }
+
+    /// User and signing done by `credentials` instead of the identity settings.
+    pub fn with_credentials<P>(mut self, credentials: P) -> SiodbConnOptions
+    where
+        P: CredentialProvider + 'static,
+    {
+        self.credentials = Some(Arc::new(credentials));
+        self
+    }
+
+    pub fn with_sslmode(mut self, sslmode: SslMode) -> SiodbConnOptions {
+        self.sslmode = Some(sslmode);
+        self
+    }
+
+    /// Refuse TLS versions older than `version`.
+    pub fn with_ssl_min_protocol_version(mut self, version: TlsVersion) -> SiodbConnOptions {
+        self.ssl_min_protocol_version = Some(version);
+        self
+    }
+
+    /// Name sent with SNI and checked against the server certificate, instead of the host.
+    pub fn with_tls_server_name(mut self, server_name: &str) -> SiodbConnOptions {
+        self.tls_server_name = Some(server_name.to_string());
+        self
+    }
+
+    /// Also accept a server certificate with this SHA-256 fingerprint. Once a
+    /// fingerprint is pinned, certificates with none of them are refused.
+    pub fn add_cert_sha256(mut self, fingerprint: [u8; 32]) -> SiodbConnOptions {
+        self.cert_sha256.push(fingerprint);
+        self
+    }
+
+    pub fn with_ca_file(mut self, path: &str) -> SiodbConnOptions {
+        self.ca_file = Some(path.to_string());
+        self
+    }
+
+    /// Trust the PEM certificates of the files of a directory.
+    pub fn with_ca_dir(mut self, path: &str) -> SiodbConnOptions {
+        self.ca_dir = Some(path.to_string());
+        self
+    }
+
+    pub fn with_client_cert(mut self, path: &str) -> SiodbConnOptions {
+        self.client_cert = Some(path.to_string());
+        self
+    }
+
+    pub fn with_client_key(mut self, path: &str) -> SiodbConnOptions {
+        self.client_key = Some(path.to_string());
+        self
+    }
+
+    pub fn with_client_key_passphrase(mut self, passphrase: &str) -> SiodbConnOptions {
+        self.client_key_passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> SiodbConnOptions {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Probe idle TCP connections after `interval` of silence, and every `interval`
+    /// after that, so that NAT and firewalls keep them open. A zero interval leaves
+    /// keepalive off, as 0 does in the URI.
+    pub fn with_keepalive(mut self, interval: Duration) -> SiodbConnOptions {
+        self.keepalive = non_zero(interval);
+        self
+    }
+
+    /// Set TCP_NODELAY, sending small writes at once.
+    pub fn with_nodelay(mut self, nodelay: bool) -> SiodbConnOptions {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Capacities in bytes of the read and write buffers of the connection, 8 KiB by default.
+    pub fn with_buffer_sizes(mut self, read: usize, write: usize) -> SiodbConnOptions {
+        self.read_buffer_size = read;
+        self.write_buffer_size = write;
+        self
+    }
+
+    /// Reconnect and authenticate again before the next statement when the connection
+    /// was found broken. A statement already sent is never sent again.
+    pub fn with_auto_reconnect(mut self, auto_reconnect: bool) -> SiodbConnOptions {
+        self.auto_reconnect = auto_reconnect;
+        self
+    }
+
+    pub fn with_trace(mut self, trace: bool) -> SiodbConnOptions {
+        self.trace = trace;
+        self
+    }
+
+    /// Set an option named as in URIs.
+    pub(crate) fn set_option(&mut self, name: &str, value: &str) -> Result<(), DriverError> {
+        let text = value.to_string();
+        match name {
+            "user" => self.user = text,
+            "identity_file" => self.identity_file = text,
+            "identity_passphrase" => self.identity_passphrase = Some(text),
+            "agent" => self.agent = Some(parse_bool(name, value)?),
+            "agent_socket" => self.agent_socket = Some(text),
+            "sslmode" => self.sslmode = Some(SslMode::from_str(value)?),
+            "ssl_min_protocol_version" => {
+                self.ssl_min_protocol_version = Some(TlsVersion::from_str(value)?)
+            }
+            "tls_server_name" => self.tls_server_name = Some(text),
+            "cert_sha256" => {
+                for fingerprint in value.split(',') {
+                    self.cert_sha256
+                        .push(parse_fingerprint(fingerprint.trim())?);
+                }
+            }
+            "ca_file" => self.ca_file = Some(text),
+            "ca_dir" => self.ca_dir = Some(text),
+            "client_cert" => self.client_cert = Some(text),
+            "client_key" => self.client_key = Some(text),
+            "client_key_passphrase" => self.client_key_passphrase = Some(text),
+            "pkcs11_module" => self.pkcs11_module = Some(text),
+            "pkcs11_slot" => {
+                self.pkcs11_slot = Some(u64::from_str(value).map_err(|_| {
+                    DriverError::new(
+                        ErrorKind::Config,
+                        &format!("Wrong pkcs11_slot: '{}'.", value),
+                    )
+                })?)
+            }
+            "pkcs11_key_label" => self.pkcs11_key_label = Some(text),
+            "pkcs11_pin_source" => self.pkcs11_pin_source = Some(PinSource::from_str(value)?),
+            "connect_timeout" => self.timeouts.connect = parse_timeout(name, value)?,
+            "read_timeout" => self.timeouts.read = parse_timeout(name, value)?,
+            "write_timeout" => self.timeouts.write = parse_timeout(name, value)?,
+            "target" => self.target = Target::from_str(value)?,
+            "proxy" => self.proxy = Some(Proxy::from_str(value)?),
+            "keepalive" => self.keepalive = parse_timeout(name, value)?,
+            "nodelay" => self.nodelay = parse_bool(name, value)?,
+            "read_buffer_size" => self.read_buffer_size = parse_buffer_size(name, value)?,
+            "write_buffer_size" => self.write_buffer_size = parse_buffer_size(name, value)?,
+            "auto_reconnect" => self.auto_reconnect = parse_bool(name, value)?,
+            "trace" => self.trace = bool::from_str(value).unwrap_or(self.trace),
+            _ => {
+                return Err(DriverError::new(
+                    ErrorKind::Config,
+                    &format!("Unknow option: {}.", name),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the settings go together.
+    pub(crate) fn validate(&self) -> Result<(), DriverError> {
+        if self.scheme == Scheme::Unix {
+            if self.socket_path.is_none() {
+                return Err(DriverError::new(
+                    ErrorKind::Config,
+                    "The Unix socket path is missing.",
+                ));
+            }
+            match self.sslmode {
+                Some(sslmode) if sslmode != SslMode::Disable => {
+                    return Err(DriverError::new(
+                        ErrorKind::Config,
+                        &format!(
+                            "sslmode '{}' is not supported for Unix socket connections.",
+                            sslmode
+                        ),
+                    ))
+                }
+                _ => {}
+            }
+            if self.proxy.is_some() {
+                return Err(DriverError::new(
+                    ErrorKind::Config,
+                    "A proxy is not supported for Unix socket connections.",
+                ));
+            }
+        } else if self.hosts.is_empty() {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "No host to connect to.",
+            ));
+        }
+        if self.read_buffer_size == 0 || self.write_buffer_size == 0 {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "Buffer sizes must not be 0.",
+            ));
+        }
+        self.pkcs11_options()?;
+        Ok(())
+    }
+
+    /// The TLS protection: the scheme gives the default, sslmode can override it.
+    pub(crate) fn tls_options(&self) -> TlsOptions {
+        TlsOptions {
+            sslmode: self.sslmode.unwrap_or(match self.scheme {
+                Scheme::Tls => SslMode::VerifyFull,
+                Scheme::Tcp | Scheme::Unix => SslMode::Disable,
+            }),
+            min_version: self.ssl_min_protocol_version,
+            server_name: self.tls_server_name.clone(),
+            cert_sha256: self.cert_sha256.clone(),
+            ca_file: self.ca_file.clone(),
+            ca_dir: self.ca_dir.clone(),
+            client_cert: self.client_cert.clone(),
+            client_key: self.client_key.clone(),
+            client_key_passphrase: self.client_key_passphrase.clone(),
+        }
+    }
+
+    pub(crate) fn pkcs11_options(&self) -> Result<Option<Pkcs11Options>, DriverError> {
+        match (&self.pkcs11_module, &self.pkcs11_key_label) {
+            (Some(module), Some(key_label)) => Ok(Some(Pkcs11Options {
+                module: module.clone(),
+                slot: self.pkcs11_slot,
+                key_label: key_label.clone(),
+                pin_source: self.pkcs11_pin_source.clone().unwrap_or_default(),
+            })),
+            (Some(_), None) => Err(DriverError::new(
+                ErrorKind::Config,
+                "pkcs11_module requires pkcs11_key_label.",
+            )),
+            (None, None) if self.pkcs11_slot.is_none() && self.pkcs11_pin_source.is_none() => {
+                Ok(None)
+            }
+            (None, _) => Err(DriverError::new(
+                ErrorKind::Config,
+                "PKCS#11 options require pkcs11_module.",
+            )),
+        }
+    }
+
+    /// The hosts to connect to in turn, or the socket path of a Unix socket connection.
+    pub(crate) fn hosts_in_order(&self) -> Vec<(String, u16)> {
+        match (self.scheme, &self.socket_path) {
+            (Scheme::Unix, Some(socket_path)) => vec![(socket_path.clone(), 0)],
+            _ => self.target.order(self.hosts.clone()),
+        }
+    }
+
+    /// The credential provider set in code, or the one described by the URI options.
+    pub(crate) fn credential_provider(&self) -> Result<Arc<dyn CredentialProvider>, DriverError> {
+        match &self.credentials {
+            Some(credentials) => Ok(credentials.clone()),
+            None => self.uri_credentials(),
+        }
+    }
+
+    // Credentials described by the URI options. The ssh-agent is used when asked for,
+    // or when there is no identity file to read but an agent is running.
+    fn uri_credentials(&self) -> Result<Arc<dyn CredentialProvider>, DriverError> {
+        if let Some(pkcs11) = self.pkcs11_options()? {
+            return self.pkcs11_credentials(&pkcs11);
+        }
+
+        let identity_file = expand_home(&self.identity_file);
+        let auth_sock = env::var(agent::AUTH_SOCK_ENV).ok();
+        let agent_socket = match self.agent {
+            Some(false) => None,
+            _ if self.agent_socket.is_some() => self.agent_socket.clone(),
+            Some(true) => Some(auth_sock.ok_or_else(|| {
+                DriverError::new(
+                    ErrorKind::Config,
+                    "agent=true but SSH_AUTH_SOCK is not set.",
+                )
+            })?),
+            None if !Path::new(&identity_file).exists() => auth_sock,
+            None => None,
+        };
+
+        if let Some(agent_socket) = agent_socket {
+            debug(
+                self.trace,
+                &format!("Signing with ssh-agent '{}'.", agent_socket),
+            );
+            let mut credentials = AgentCredentials::new(&self.user).with_socket(&agent_socket);
+            if let Some(public_key_file) = agent::public_key_file(&identity_file) {
+                credentials = credentials.with_public_key_file(&public_key_file);
+            }
+            return Ok(Arc::new(credentials));
+        }
+
+        let mut credentials = FileCredentials::new(&self.user, &identity_file)
+            .with_shared_passphrase_callback(self.passphrase_callback.clone());
+        if let Some(identity_passphrase) = &self.identity_passphrase {
+            credentials = credentials.with_passphrase(identity_passphrase);
+        }
+        Ok(Arc::new(credentials))
+    }
+    #[cfg(feature = "pkcs11")]
+    fn pkcs11_credentials(
+        &self,
+        pkcs11: &Pkcs11Options,
+    ) -> Result<Arc<dyn CredentialProvider>, DriverError> {
+        debug(
+            self.trace,
+            &format!("Signing with PKCS#11 module '{}'.", pkcs11.module),
+        );
+        Ok(Arc::new(Pkcs11Credentials::from_options(
+            &self.user,
+            pkcs11.clone(),
+        )))
+    }
+    #[cfg(not(feature = "pkcs11"))]
+    fn pkcs11_credentials(
+        &self,
+        _pkcs11: &Pkcs11Options,
+    ) -> Result<Arc<dyn CredentialProvider>, DriverError> {
+        Err(DriverError::new(
+            ErrorKind::Config,
+            "PKCS#11 signing requires the 'pkcs11' feature of the siodb crate.",
+        ))
+    }
+
+    fn uri(&self, hide_secrets: bool) -> String {
+        let secret = |value: &str| {
+            if hide_secrets {
+                "***".to_string()
+            } else {
+                value.to_string()
+            }
+        };
+        let mut pairs: Vec<(&str, String)> = Vec::new();
+        if self.identity_file != DEFAULT_IDENTITY_FILE {
+            pairs.push(("identity_file", self.identity_file.clone()));
+        }
+        if let Some(passphrase) = &self.identity_passphrase {
+            pairs.push(("identity_passphrase", secret(passphrase)));
+        }
+        if let Some(agent) = self.agent {
+            pairs.push(("agent", agent.to_string()));
+        }
+        if let Some(agent_socket) = &self.agent_socket {
+            pairs.push(("agent_socket", agent_socket.clone()));
+        }
+        if let Some(sslmode) = self.sslmode {
+            pairs.push(("sslmode", sslmode.to_string()));
+        }
+        if let Some(version) = self.ssl_min_protocol_version {
+            pairs.push(("ssl_min_protocol_version", version.to_string()));
+        }
+        if let Some(server_name) = &self.tls_server_name {
+            pairs.push(("tls_server_name", server_name.clone()));
+        }
+        if !self.cert_sha256.is_empty() {
+            let fingerprints: Vec<String> =
+                self.cert_sha256.iter().map(format_fingerprint).collect();
+            pairs.push(("cert_sha256", fingerprints.join(",")));
+        }
+        if let Some(ca_file) = &self.ca_file {
+            pairs.push(("ca_file", ca_file.clone()));
+        }
+        if let Some(ca_dir) = &self.ca_dir {
+            pairs.push(("ca_dir", ca_dir.clone()));
+        }
+        if let Some(client_cert) = &self.client_cert {
+            pairs.push(("client_cert", client_cert.clone()));
+        }
+        if let Some(client_key) = &self.client_key {
+            pairs.push(("client_key", client_key.clone()));
+        }
+        if let Some(passphrase) = &self.client_key_passphrase {
+            pairs.push(("client_key_passphrase", secret(passphrase)));
+        }
+        if let Some(module) = &self.pkcs11_module {
+            pairs.push(("pkcs11_module", module.clone()));
+        }
+        if let Some(slot) = self.pkcs11_slot {
+            pairs.push(("pkcs11_slot", slot.to_string()));
+        }
+        if let Some(key_label) = &self.pkcs11_key_label {
+            pairs.push(("pkcs11_key_label", key_label.clone()));
+        }
+        match &self.pkcs11_pin_source {
+            Some(PinSource::Value(_)) | None => {}
+            Some(pin_source) => pairs.push(("pkcs11_pin_source", pin_source.to_string())),
+        }
+        if let Some(timeout) = self.timeouts.connect {
+            pairs.push(("connect_timeout", format_timeout(timeout)));
+        }
+        if let Some(timeout) = self.timeouts.read {
+            pairs.push(("read_timeout", format_timeout(timeout)));
+        }
+        if let Some(timeout) = self.timeouts.write {
+            pairs.push(("write_timeout", format_timeout(timeout)));
+        }
+        if self.target != Target::Any {
+            pairs.push(("target", self.target.to_string()));
+        }
+        if let Some(proxy) = &self.proxy {
+            pairs.push(("proxy", proxy.url(hide_secrets)));
+        }
+        if let Some(keepalive) = self.keepalive {
+            pairs.push(("keepalive", format_timeout(keepalive)));
+        }
+        if self.nodelay {
+            pairs.push(("nodelay", "true".to_string()));
+        }
+        if self.read_buffer_size != DEFAULT_BUFFER_SIZE {
+            pairs.push(("read_buffer_size", self.read_buffer_size.to_string()));
+        }
+        if self.write_buffer_size != DEFAULT_BUFFER_SIZE {
+            pairs.push(("write_buffer_size", self.write_buffer_size.to_string()));
+        }
+        if self.auto_reconnect {
+            pairs.push(("auto_reconnect", "true".to_string()));
+        }
+        if self.trace {
+            pairs.push(("trace", "true".to_string()));
+        }
+
+        let mut uri = match self.scheme {
+            Scheme::Unix => format!(
+                "siodbu:{}",
+                utf8_percent_encode(self.socket_path.as_deref().unwrap_or_default(), PATH)
+            ),
+            scheme => {
+                let hosts: Vec<String> = self
+                    .hosts
+                    .iter()
+                    .map(|(host, port)| {
+                        if host.contains(':') {
+                            format!("[{}]:{}", host, port)
+                        } else {
+                            format!("{}:{}", host, port)
+                        }
+                    })
+                    .collect();
+                format!(
+                    "{}://{}@{}",
+                    scheme,
+                    utf8_percent_encode(&self.user, COMPONENT),
+                    hosts.join(",")
+                )
+            }
+        };
+        if self.scheme == Scheme::Unix && self.user != DEFAULT_USER {
+            // A Unix socket URI has no authority to hold the user.
+            pairs.push(("user", self.user.clone()));
+        }
+        if !pairs.is_empty() {
+            let pairs: Vec<String> = pairs
+                .iter()
+                .map(|(name, value)| {
+                    format!("{}={}", name, utf8_percent_encode(value, QUERY_VALUE))
+                })
+                .collect();
+            uri.push('?');
+            uri.push_str(&pairs.join("&"));
+        }
+        uri
+    }
+}
+
+impl FromStr for SiodbConnOptions {
+    type Err = DriverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SiodbConnOptions::from_uri(s)
+    }
+}
+
+impl fmt::Display for SiodbConnOptions {
+    // Printed URIs must never show a passphrase.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.uri(true))
+    }
+}
+
+impl fmt::Debug for SiodbConnOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "uri: {} | pkcs11_pin_source: {:?} | passphrase_callback: {} | credentials: {}",
+            self,
+            self.pkcs11_pin_source,
+            self.passphrase_callback.is_some(),
+            if self.credentials.is_some() {
+                "custom"
+            } else {
+                "uri"
+            },
+        )
+    }
+}
+
+// A boolean, spelled 'true' or 'false'.
+fn parse_bool(option: &str, value: &str) -> Result<bool, DriverError> {
+    bool::from_str(value).map_err(|_| {
+        DriverError::new(
+            ErrorKind::Config,
+            &format!(
+                "Wrong {}: '{}'. Should be 'true' or 'false'.",
+                option, value
+            ),
+        )
+    })
+}
+
+// A buffer size in bytes, more than 0.
+fn parse_buffer_size(option: &str, value: &str) -> Result<usize, DriverError> {
+    match usize::from_str(value) {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(DriverError::new(
+            ErrorKind::Config,
+            &format!(
+                "Wrong {}: '{}'. Should be a number of bytes.",
+                option, value
+            ),
+        )),
+    }
+}
+
+// Characters left as is in the parts of a printed URI.
+pub(crate) const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+const PATH: &AsciiSet = &COMPONENT.remove(b'/');
+const QUERY_VALUE: &AsciiSet = &COMPONENT.remove(b'/').remove(b':').remove(b'*');
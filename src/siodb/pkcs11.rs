@@ -0,0 +1,355 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Signing of the session challenge with a key kept in a PKCS#11 token (HSM, smart card,
+// SoftHSM). The signing itself needs the `pkcs11` cargo feature.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+
+// Standard
+use std::env;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+/// Environment variable read for the token PIN when no PIN source is given.
+pub const PIN_ENV: &str = "SIODB_PKCS11_PIN";
+
+/// Where the PIN of a PKCS#11 token comes from.
+#[derive(Clone, PartialEq)]
+pub enum PinSource {
+    /// An environment variable (`env:NAME` in URIs).
+    Env(String),
+    /// The first line of a file (`file:PATH` in URIs).
+    File(String),
+    /// The PIN itself. Only settable from code, never from an URI.
+    Value(String),
+}
+
+impl PinSource {
+    fn read(&self) -> Result<String, DriverError> {
+        match self {
+            PinSource::Env(variable) => env::var(variable).map_err(|e| {
+                DriverError::new(
+                    ErrorKind::Auth,
+                    &format!(
+                        "Error reading PKCS#11 PIN from environment variable '{}': {}",
+                        variable, e
+                    ),
+                )
+                .with_source(e)
+            }),
+            PinSource::File(path) => fs::read_to_string(path)
+                .map(|contents| contents.lines().next().unwrap_or("").to_string())
+                .map_err(|e| {
+                    DriverError::new(
+                        ErrorKind::Auth,
+                        &format!("Error reading PKCS#11 PIN file '{}': {}", path, e),
+                    )
+                    .with_source(e)
+                }),
+            PinSource::Value(pin) => Ok(pin.clone()),
+        }
+    }
+}
+
+impl Default for PinSource {
+    fn default() -> Self {
+        PinSource::Env(PIN_ENV.to_string())
+    }
+}
+
+impl FromStr for PinSource {
+    type Err = DriverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(variable) = s.strip_prefix("env:") {
+            Ok(PinSource::Env(variable.to_string()))
+        } else if let Some(path) = s.strip_prefix("file:") {
+            Ok(PinSource::File(path.to_string()))
+        } else {
+            Err(DriverError::new(
+                ErrorKind::Config,
+                &format!(
+                    "Wrong pkcs11_pin_source: '{}'. Should be 'env:<variable>' or 'file:<path>'.",
+                    s
+                ),
+            ))
+        }
+    }
+}
+
+impl fmt::Display for PinSource {
+    // As in URIs. The PIN must never end up in a trace.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PinSource::Env(variable) => write!(f, "env:{}", variable),
+            PinSource::File(path) => write!(f, "file:{}", path),
+            PinSource::Value(_) => write!(f, "***"),
+        }
+    }
+}
+
+impl fmt::Debug for PinSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Token and key to sign with.
+#[derive(Clone, Debug)]
+pub struct Pkcs11Options {
+    /// Path of the PKCS#11 module (shared library).
+    pub module: String,
+    /// Slot of the token. Defaults to the first slot holding an initialized token.
+    pub slot: Option<u64>,
+    /// Label (`CKA_LABEL`) of the private key.
+    pub key_label: String,
+    pub pin_source: PinSource,
+}
+
+#[cfg(feature = "pkcs11")]
+pub use self::token::Pkcs11Credentials;
+
+#[cfg(feature = "pkcs11")]
+mod token {
+    // Siodb
+    use super::{PinSource, Pkcs11Options};
+    use crate::siodb::credentials::CredentialProvider;
+    use crate::siodb::errors::{DriverError, ErrorKind};
+    use crate::siodb::keys::{backend, ecdsa_signature_der, Curve};
+
+    // Standard
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::sync::{Mutex, OnceLock};
+
+    // PKCS#11
+    use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11};
+    use cryptoki::error::{Error, RvError};
+    use cryptoki::mechanism::eddsa::{EddsaParams, EddsaSignatureScheme};
+    use cryptoki::mechanism::Mechanism;
+    use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle};
+    use cryptoki::session::{Session, UserType};
+    use cryptoki::slot::Slot;
+    use cryptoki::types::AuthPin;
+
+    // DER encoded OIDs of the CKA_EC_PARAMS of the supported curves.
+    const OID_P256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+    const OID_P384: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+    const OID_P521: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x23];
+
+    // A module must only be initialized once per process, so contexts are kept.
+    static MODULES: OnceLock<Mutex<HashMap<String, Pkcs11>>> = OnceLock::new();
+
+    /// Credentials signing with a private key kept in a PKCS#11 token, so the key
+    /// never leaves the HSM.
+    pub struct Pkcs11Credentials {
+        user: String,
+        options: Pkcs11Options,
+    }
+
+    impl Pkcs11Credentials {
+        /// Use the key labelled `key_label` of the first token of `module`, with the PIN
+        /// of the `SIODB_PKCS11_PIN` environment variable.
+        pub fn new(user: &str, module: &str, key_label: &str) -> Pkcs11Credentials {
+            Pkcs11Credentials {
+                user: user.to_string(),
+                options: Pkcs11Options {
+                    module: module.to_string(),
+                    slot: None,
+                    key_label: key_label.to_string(),
+                    pin_source: PinSource::default(),
+                },
+            }
+        }
+
+        pub(crate) fn from_options(user: &str, options: Pkcs11Options) -> Pkcs11Credentials {
+            Pkcs11Credentials {
+                user: user.to_string(),
+                options,
+            }
+        }
+
+        /// Slot of the token.
+        pub fn with_slot(mut self, slot: u64) -> Pkcs11Credentials {
+            self.options.slot = Some(slot);
+            self
+        }
+
+        /// Where the token PIN comes from.
+        pub fn with_pin_source(mut self, pin_source: PinSource) -> Pkcs11Credentials {
+            self.options.pin_source = pin_source;
+            self
+        }
+
+        /// The token PIN.
+        pub fn with_pin(self, pin: &str) -> Pkcs11Credentials {
+            self.with_pin_source(PinSource::Value(pin.to_string()))
+        }
+
+        fn slot(&self, context: &Pkcs11) -> Result<Slot, DriverError> {
+            match self.options.slot {
+                Some(slot) => Slot::try_from(slot).map_err(|e| self.error(e)),
+                None => context
+                    .get_slots_with_initialized_token()
+                    .map_err(|e| self.error(e))?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| {
+                        DriverError::new(
+                            ErrorKind::Auth,
+                            &format!(
+                                "No initialized token in PKCS#11 module '{}'.",
+                                self.options.module
+                            ),
+                        )
+                    }),
+            }
+        }
+
+        fn find_key(&self, session: &Session) -> Result<ObjectHandle, DriverError> {
+            let template = [
+                Attribute::Class(ObjectClass::PRIVATE_KEY),
+                Attribute::Label(self.options.key_label.as_bytes().to_vec()),
+            ];
+            let keys = session.find_objects(&template).map_err(|e| self.error(e))?;
+            match keys.as_slice() {
+                [key] => Ok(*key),
+                [] => Err(DriverError::new(
+                    ErrorKind::Auth,
+                    &format!(
+                        "No private key labelled '{}' in PKCS#11 module '{}'.",
+                        self.options.key_label, self.options.module
+                    ),
+                )),
+                _ => Err(DriverError::new(
+                    ErrorKind::Auth,
+                    &format!(
+                        "Several private keys labelled '{}' in PKCS#11 module '{}'.",
+                        self.options.key_label, self.options.module
+                    ),
+                )),
+            }
+        }
+
+        // Same algorithms as for key files: RSA with SHA-512, ECDSA with the hash
+        // matching the curve size, Ed25519 as is.
+        fn sign(
+            &self,
+            session: &Session,
+            key: ObjectHandle,
+            challenge: &[u8],
+        ) -> Result<Vec<u8>, DriverError> {
+            let key_type = match session
+                .get_attributes(key, &[AttributeType::KeyType])
+                .map_err(|e| self.error(e))?
+                .as_slice()
+            {
+                [Attribute::KeyType(key_type)] => *key_type,
+                _ => {
+                    return Err(DriverError::new(
+                        ErrorKind::Auth,
+                        "PKCS#11 key type is not readable.",
+                    ))
+                }
+            };
+
+            if key_type == KeyType::RSA {
+                session
+                    .sign(&Mechanism::Sha512RsaPkcs, key, challenge)
+                    .map_err(|e| self.error(e))
+            } else if key_type == KeyType::EC {
+                let digest = backend::ecdsa_hash(self.ecdsa_curve(session, key)?, challenge)?;
+                let signature = session
+                    .sign(&Mechanism::Ecdsa, key, &digest)
+                    .map_err(|e| self.error(e))?;
+                // PKCS#11 returns r and s concatenated, Siodb takes DER.
+                let (r, s) = signature.split_at(signature.len() / 2);
+                Ok(ecdsa_signature_der(r, s))
+            } else if key_type == KeyType::EC_EDWARDS {
+                let mechanism = Mechanism::Eddsa(EddsaParams::new(EddsaSignatureScheme::Pure));
+                session
+                    .sign(&mechanism, key, challenge)
+                    .map_err(|e| self.error(e))
+            } else {
+                Err(DriverError::new(
+                    ErrorKind::Auth,
+                    &format!("Unsupported PKCS#11 key type: {}.", key_type),
+                ))
+            }
+        }
+
+        fn ecdsa_curve(&self, session: &Session, key: ObjectHandle) -> Result<Curve, DriverError> {
+            let attributes = session
+                .get_attributes(key, &[AttributeType::EcParams])
+                .map_err(|e| self.error(e))?;
+            match attributes.as_slice() {
+                [Attribute::EcParams(oid)] if oid.as_slice() == OID_P256 => Ok(Curve::P256),
+                [Attribute::EcParams(oid)] if oid.as_slice() == OID_P384 => Ok(Curve::P384),
+                [Attribute::EcParams(oid)] if oid.as_slice() == OID_P521 => Ok(Curve::P521),
+                _ => Err(DriverError::new(
+                    ErrorKind::Auth,
+                    "Unsupported ECDSA curve of PKCS#11 key. Should be P-256, P-384 or P-521.",
+                )),
+            }
+        }
+
+        fn error(&self, err: Error) -> DriverError {
+            DriverError::new(
+                ErrorKind::Auth,
+                &format!("PKCS#11 module '{}': {}", self.options.module, err),
+            )
+            .with_source(err)
+        }
+    }
+
+    impl CredentialProvider for Pkcs11Credentials {
+        fn user_name(&self) -> Result<String, DriverError> {
+            Ok(self.user.clone())
+        }
+
+        fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+            let context = load_module(&self.options.module)?;
+            let session = context
+                .open_ro_session(self.slot(&context)?)
+                .map_err(|e| self.error(e))?;
+            let pin = AuthPin::new(self.options.pin_source.read()?.into());
+            match session.login(UserType::User, Some(&pin)) {
+                // The login is shared by all sessions of the process.
+                Ok(()) | Err(Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => {}
+                Err(e) => return Err(self.error(e)),
+            }
+            let key = self.find_key(&session)?;
+            self.sign(&session, key, challenge)
+        }
+    }
+
+    fn load_module(module: &str) -> Result<Pkcs11, DriverError> {
+        let mut modules = MODULES
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(context) = modules.get(module) {
+            return Ok(context.clone());
+        }
+
+        let error = |e: Error| {
+            DriverError::new(
+                ErrorKind::Auth,
+                &format!("Error loading PKCS#11 module '{}': {}", module, e),
+            )
+            .with_source(e)
+        };
+        let context = Pkcs11::new(module).map_err(error)?;
+        match context.initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK)) {
+            // Someone else in the process already uses the module.
+            Ok(()) | Err(Error::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => {}
+            Err(e) => return Err(error(e)),
+        }
+        modules.insert(module.to_string(), context.clone());
+        Ok(context)
+    }
+}
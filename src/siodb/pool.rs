@@ -0,0 +1,468 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::errors::{debug, DriverError, ErrorKind};
+use crate::siodb::{SiodbConn, SiodbConnOptions};
+
+// Standard
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_SIZE: usize = 10;
+// The longest the reaper sleeps between two rounds.
+const MAX_REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Sizes and timeouts of a pool. Unset timeouts never expire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolOptions {
+    pub(crate) min_size: usize,
+    pub(crate) max_size: usize,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) max_lifetime: Option<Duration>,
+    pub(crate) checkout_timeout: Option<Duration>,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            min_size: 0,
+            max_size: DEFAULT_MAX_SIZE,
+            idle_timeout: None,
+            max_lifetime: None,
+            checkout_timeout: None,
+        }
+    }
+}
+
+impl PoolOptions {
+    /// No connection kept open, at most 10 connections.
+    pub fn new() -> PoolOptions {
+        PoolOptions::default()
+    }
+
+    /// Connections opened with the pool and kept open while idle.
+    pub fn with_min_size(mut self, min_size: usize) -> PoolOptions {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Connections open at most, in use or idle.
+    pub fn with_max_size(mut self, max_size: usize) -> PoolOptions {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Time after which an idle connection beyond the minimum size is closed.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> PoolOptions {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Time after which a connection is closed rather than handed out again.
+    pub fn with_max_lifetime(mut self, lifetime: Duration) -> PoolOptions {
+        self.max_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Time `get()` waits for a connection when all of them are in use.
+    pub fn with_checkout_timeout(mut self, timeout: Duration) -> PoolOptions {
+        self.checkout_timeout = Some(timeout);
+        self
+    }
+
+    fn validate(&self) -> Result<(), DriverError> {
+        if self.max_size == 0 {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "The maximum size of the pool must not be 0.",
+            ));
+        }
+        if self.min_size > self.max_size {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                &format!(
+                    "The minimum size of the pool ({}) is above its maximum size ({}).",
+                    self.min_size, self.max_size
+                ),
+            ));
+        }
+        // A zero duration would keep the reaper from ever sleeping.
+        for (name, duration) in &[
+            ("idle timeout", self.idle_timeout),
+            ("maximum lifetime", self.max_lifetime),
+        ] {
+            if duration.is_some_and(|duration| duration.is_zero()) {
+                return Err(DriverError::new(
+                    ErrorKind::Config,
+                    &format!("The {} of the pool must not be 0.", name),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A thread-safe pool of connections to Siodb, cheap to clone.
+///
+/// `get()` hands out an idle connection once `ping()` succeeded on it, or opens a
+/// new one below the maximum size. Otherwise the callers wait in turn, first come
+/// first served. Dropping the `PooledConn` puts the connection back, unless it is
+/// broken, closed or has unread rows: such connections are closed instead.
+///
+/// ## For example:
+///
+/// ```no_run
+///   use siodb::{PoolOptions, SiodbPool};
+///   use std::time::Duration;
+///
+///   let pool_options = PoolOptions::new()
+///       .with_max_size(4)
+///       .with_checkout_timeout(Duration::from_secs(5));
+///   let pool = SiodbPool::new("siodbs://root@localhost:50000", pool_options)
+///       .expect("Error opening the pool");
+///   let mut siodb_conn = pool.get().expect("No connection available");
+///   siodb_conn.execute("CREATE DATABASE test_db".to_string()).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct SiodbPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    conn_options: SiodbConnOptions,
+    options: PoolOptions,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    idle: VecDeque<Idle>,
+    // Connections in use, idle or being opened.
+    open: usize,
+    // Tickets of the callers of get(), in arrival order.
+    waiters: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+struct Idle {
+    conn: SiodbConn,
+    created: Instant,
+    since: Instant,
+}
+
+impl fmt::Debug for SiodbPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} | {:?} | size: {} | idle_count: {}",
+            self.shared.conn_options,
+            self.shared.options,
+            self.size(),
+            self.idle_count()
+        )
+    }
+}
+
+impl SiodbPool {
+    /// Create a pool of connections to Siodb from an URI, and open its minimum size.
+    pub fn new(uri_str: &str, options: PoolOptions) -> Result<SiodbPool, DriverError> {
+        SiodbPool::new_with_options(&SiodbConnOptions::from_uri(uri_str)?, options)
+    }
+
+    /// Create a pool of connections to Siodb from options built in code, and open
+    /// its minimum size.
+    pub fn new_with_options(
+        conn_options: &SiodbConnOptions,
+        options: PoolOptions,
+    ) -> Result<SiodbPool, DriverError> {
+        conn_options.validate()?;
+        options.validate()?;
+        let pool = SiodbPool {
+            shared: Arc::new(Shared {
+                conn_options: conn_options.clone(),
+                options,
+                state: Mutex::new(State {
+                    idle: VecDeque::new(),
+                    open: 0,
+                    waiters: VecDeque::new(),
+                    next_ticket: 0,
+                }),
+                changed: Condvar::new(),
+            }),
+        };
+        for _ in 0..options.min_size {
+            let conn = SiodbConn::open(conn_options.clone())?;
+            let now = Instant::now();
+            let mut state = pool.shared.lock();
+            state.open += 1;
+            state.idle.push_back(Idle {
+                conn,
+                created: now,
+                since: now,
+            });
+        }
+        if let Some(interval) = options.reap_interval() {
+            let shared = Arc::downgrade(&pool.shared);
+            thread::spawn(move || reap(shared, interval));
+        }
+        Ok(pool)
+    }
+
+    /// Check a connection out of the pool, waiting for one up to the checkout timeout.
+    pub fn get(&self) -> Result<PooledConn, DriverError> {
+        let shared = &self.shared;
+        let deadline = shared
+            .options
+            .checkout_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let mut state = shared.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiters.push_back(ticket);
+        loop {
+            if state.waiters.front() == Some(&ticket) {
+                if let Some(idle) = state.idle.pop_back() {
+                    state.waiters.pop_front();
+                    shared.changed.notify_all();
+                    drop(state);
+                    if let Some(conn) = shared.check(idle) {
+                        return Ok(PooledConn::new(shared, conn));
+                    }
+                    // Try the next connection ahead of the others.
+                    state = shared.lock();
+                    state.waiters.push_front(ticket);
+                    continue;
+                }
+                if state.open < shared.options.max_size {
+                    state.open += 1;
+                    state.waiters.pop_front();
+                    shared.changed.notify_all();
+                    drop(state);
+                    return match SiodbConn::open(shared.conn_options.clone()) {
+                        Ok(conn) => Ok(PooledConn::new(
+                            shared,
+                            Idle {
+                                conn,
+                                created: Instant::now(),
+                                since: Instant::now(),
+                            },
+                        )),
+                        Err(error) => {
+                            shared.release();
+                            Err(error)
+                        }
+                    };
+                }
+            }
+            state = match deadline {
+                None => shared
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.waiters.retain(|&waiter| waiter != ticket);
+                        shared.changed.notify_all();
+                        return Err(DriverError::new(
+                            ErrorKind::Timeout,
+                            &format!(
+                                "No connection of the pool available after {:?}.",
+                                shared.options.checkout_timeout.unwrap_or_default()
+                            ),
+                        ));
+                    }
+                    shared
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+
+    /// The number of connections open, in use or idle.
+    pub fn size(&self) -> usize {
+        self.shared.lock().open
+    }
+
+    /// The number of idle connections.
+    pub fn idle_count(&self) -> usize {
+        self.shared.lock().idle.len()
+    }
+}
+
+impl PoolOptions {
+    // The reaper wakes up often enough to close connections soon after they expire.
+    fn reap_interval(&self) -> Option<Duration> {
+        let interval = match (self.idle_timeout, self.max_lifetime) {
+            (None, None) if self.min_size == 0 => return None,
+            (None, None) => MAX_REAP_INTERVAL,
+            (Some(timeout), None) | (None, Some(timeout)) => timeout,
+            (Some(idle_timeout), Some(max_lifetime)) => idle_timeout.min(max_lifetime),
+        };
+        Some(interval.min(MAX_REAP_INTERVAL))
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panic in a caller cannot leave the state half updated.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // A connection closed by the pool frees a place.
+    fn release(&self) {
+        self.lock().open -= 1;
+        self.changed.notify_all();
+    }
+
+    fn expired(&self, idle: &Idle, now: Instant) -> bool {
+        self.options
+            .max_lifetime
+            .is_some_and(|lifetime| now.duration_since(idle.created) >= lifetime)
+    }
+
+    // An idle connection goes out of the pool if still alive.
+    fn check(&self, mut idle: Idle) -> Option<Idle> {
+        let error = if self.expired(&idle, Instant::now()) {
+            "max lifetime reached".to_string()
+        } else {
+            match idle.conn.ping() {
+                Ok(()) => return Some(idle),
+                Err(error) => error.to_string(),
+            }
+        };
+        debug(
+            self.conn_options.trace,
+            &format!("Pooled connection discarded: {}", error),
+        );
+        let _ = idle.conn.close();
+        self.release();
+        None
+    }
+
+    fn put_back(&self, mut idle: Idle) {
+        let now = Instant::now();
+        if !idle.conn.is_clean() || self.expired(&idle, now) {
+            debug(
+                self.conn_options.trace,
+                "Pooled connection closed: broken, dirty or too old.",
+            );
+            let _ = idle.conn.close();
+            self.release();
+            return;
+        }
+        idle.since = now;
+        self.lock().idle.push_back(idle);
+        self.changed.notify_all();
+    }
+}
+
+// Close the idle connections past their idle timeout or lifetime, and open new
+// ones up to the minimum size, until the pool is dropped.
+fn reap(shared: Weak<Shared>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        {
+            let mut state = shared.lock();
+            let mut index = 0;
+            while index < state.idle.len() {
+                let idle = &state.idle[index];
+                let idle_expired = shared.options.idle_timeout.is_some_and(|timeout| {
+                    state.open - expired.len() > shared.options.min_size
+                        && now.duration_since(idle.since) >= timeout
+                });
+                if idle_expired || shared.expired(idle, now) {
+                    expired.extend(state.idle.remove(index));
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        for mut idle in expired {
+            let _ = idle.conn.close();
+            shared.release();
+        }
+        loop {
+            {
+                let mut state = shared.lock();
+                if state.open >= shared.options.min_size {
+                    break;
+                }
+                state.open += 1;
+            }
+            match SiodbConn::open(shared.conn_options.clone()) {
+                Ok(conn) => shared.put_back(Idle {
+                    conn,
+                    created: Instant::now(),
+                    since: Instant::now(),
+                }),
+                Err(error) => {
+                    debug(
+                        shared.conn_options.trace,
+                        &format!("Pool connection failed: {}", error),
+                    );
+                    shared.release();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// A connection checked out of a `SiodbPool`, put back when dropped.
+pub struct PooledConn {
+    shared: Arc<Shared>,
+    idle: Option<Idle>,
+}
+
+impl PooledConn {
+    fn new(shared: &Arc<Shared>, idle: Idle) -> PooledConn {
+        PooledConn {
+            shared: shared.clone(),
+            idle: Some(idle),
+        }
+    }
+}
+
+impl Deref for PooledConn {
+    type Target = SiodbConn;
+
+    fn deref(&self) -> &SiodbConn {
+        &self.idle.as_ref().expect("pooled connection").conn
+    }
+}
+
+impl DerefMut for PooledConn {
+    fn deref_mut(&mut self) -> &mut SiodbConn {
+        &mut self.idle.as_mut().expect("pooled connection").conn
+    }
+}
+
+impl fmt::Debug for PooledConn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.deref(), f)
+    }
+}
+
+impl Drop for PooledConn {
+    fn drop(&mut self) {
+        if let Some(idle) = self.idle.take() {
+            self.shared.put_back(idle);
+        }
+    }
+}
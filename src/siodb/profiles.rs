@@ -0,0 +1,191 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Connection settings from named profiles and environment variables, in the
+// spirit of libpq's pg_service.conf and PG* variables.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::hosts::{parse_host, DEFAULT_PORT};
+use crate::siodb::options::{Scheme, SiodbConnOptions};
+
+// Standard
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// TOML
+use toml::{Table, Value};
+
+/// Environment variable holding the path of the connections file.
+pub const CONNECTIONS_FILE_ENV: &str = "SIODB_CONNECTIONS_FILE";
+/// Environment variable holding the name of the profile to use.
+pub const PROFILE_ENV: &str = "SIODB_PROFILE";
+/// Environment variable holding the URI to connect to.
+pub const URI_ENV: &str = "SIODB_URI";
+/// Environment variable holding the host, or the comma-separated `host[:port]` list.
+pub const HOST_ENV: &str = "SIODB_HOST";
+/// Environment variable holding the port.
+pub const PORT_ENV: &str = "SIODB_PORT";
+/// Environment variable holding the Siodb user.
+pub const USER_ENV: &str = "SIODB_USER";
+/// Environment variable holding the path of the identity file.
+pub const IDENTITY_FILE_ENV: &str = "SIODB_IDENTITY_FILE";
+
+impl SiodbConnOptions {
+    /// Options of the profile `name` of the connections file: the file of
+    /// `SIODB_CONNECTIONS_FILE`, or `~/.siodb/connections.toml`.
+    ///
+    /// A profile is a TOML table of URI options, plus `uri` for the base settings,
+    /// `scheme`, `host` (a comma-separated `host[:port]` list), `port` and `socket_path`.
+    pub fn from_profile(name: &str) -> Result<SiodbConnOptions, DriverError> {
+        let path = connections_file()?;
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            DriverError::new(
+                ErrorKind::Config,
+                &format!("Error reading connections file '{}': {}", path.display(), e),
+            )
+            .with_source(e)
+        })?;
+        let profiles: Table = contents.parse().map_err(|e| {
+            DriverError::new(
+                ErrorKind::Config,
+                &format!("Invalid connections file '{}': {}", path.display(), e),
+            )
+            .with_source(e)
+        })?;
+        let profile = profiles
+            .get(name)
+            .and_then(Value::as_table)
+            .ok_or_else(|| {
+                DriverError::new(
+                    ErrorKind::Config,
+                    &format!("No connection profile '{}' in '{}'.", name, path.display()),
+                )
+            })?;
+        from_table(name, profile)
+    }
+
+    /// Options from the environment. `SIODB_URI`, or else the profile named by
+    /// `SIODB_PROFILE`, gives the base settings, then `SIODB_HOST`, `SIODB_PORT`,
+    /// `SIODB_USER` and `SIODB_IDENTITY_FILE` override theirs.
+    pub fn from_env() -> Result<SiodbConnOptions, DriverError> {
+        let mut options = match (env::var(URI_ENV).ok(), env::var(PROFILE_ENV).ok()) {
+            (Some(uri), _) => SiodbConnOptions::from_uri(&uri)?,
+            (None, Some(profile)) => SiodbConnOptions::from_profile(&profile)?,
+            (None, None) => SiodbConnOptions::new(),
+        };
+        set_hosts(
+            &mut options,
+            env::var(HOST_ENV).ok().as_deref(),
+            env::var(PORT_ENV).ok().as_deref(),
+        )?;
+        if let Ok(user) = env::var(USER_ENV) {
+            options.user = user;
+        }
+        if let Ok(identity_file) = env::var(IDENTITY_FILE_ENV) {
+            options.identity_file = identity_file;
+        }
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+/// Replace a leading `~` of a path with the home directory.
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home, rest)
+        }
+        _ => path.to_string(),
+    }
+}
+
+fn connections_file() -> Result<PathBuf, DriverError> {
+    if let Ok(path) = env::var(CONNECTIONS_FILE_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    let home = env::var("HOME").map_err(|_| {
+        DriverError::new(
+            ErrorKind::Config,
+            "HOME is not set, the connections file cannot be found.",
+        )
+    })?;
+    Ok(PathBuf::from(home).join(".siodb").join("connections.toml"))
+}
+
+fn from_table(name: &str, profile: &Table) -> Result<SiodbConnOptions, DriverError> {
+    let text = |key: &str| -> Result<Option<String>, DriverError> {
+        match profile.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(Value::Integer(value)) => Ok(Some(value.to_string())),
+            Some(Value::Float(value)) => Ok(Some(value.to_string())),
+            Some(Value::Boolean(value)) => Ok(Some(value.to_string())),
+            Some(_) => Err(DriverError::new(
+                ErrorKind::Config,
+                &format!("Wrong value of '{}' in connection profile '{}'.", key, name),
+            )),
+        }
+    };
+
+    let mut options = match text("uri")? {
+        Some(uri) => SiodbConnOptions::from_uri(&uri)?,
+        None => SiodbConnOptions::new(),
+    };
+    if let Some(scheme) = text("scheme")? {
+        options.scheme = Scheme::from_str(&scheme)?;
+    }
+    if let Some(socket_path) = text("socket_path")? {
+        options = options.with_unix_socket(&socket_path);
+    }
+    set_hosts(
+        &mut options,
+        text("host")?.as_deref(),
+        text("port")?.as_deref(),
+    )?;
+    for key in profile.keys() {
+        match key.as_str() {
+            "uri" | "scheme" | "socket_path" | "host" | "port" => {}
+            _ => {
+                if let Some(value) = text(key)? {
+                    options.set_option(key, &value)?;
+                }
+            }
+        }
+    }
+    options.validate()?;
+    Ok(options)
+}
+
+// Hosts without a port get `port`, which otherwise applies to the current hosts.
+fn set_hosts(
+    options: &mut SiodbConnOptions,
+    hosts: Option<&str>,
+    port: Option<&str>,
+) -> Result<(), DriverError> {
+    let port = match port {
+        Some(port) => Some(u16::from_str(port.trim()).map_err(|_| {
+            DriverError::new(ErrorKind::Config, &format!("Wrong port: '{}'.", port))
+        })?),
+        None => None,
+    };
+    match hosts {
+        Some(hosts) => {
+            options.hosts = hosts
+                .split(',')
+                .map(|entry| parse_host("siodb", entry.trim(), port.unwrap_or(DEFAULT_PORT)))
+                .collect::<Result<_, _>>()?;
+        }
+        None => {
+            if let Some(port) = port {
+                for host in options.hosts.iter_mut() {
+                    host.1 = port;
+                }
+            }
+        }
+    }
+    Ok(())
+}
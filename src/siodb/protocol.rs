@@ -0,0 +1,415 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Framing and decoding of the client protocol, shared by the blocking and the
+// async connections.
+
+// Siodb
+use crate::siodb::errors::{debug, DriverError, ErrorKind, ServerMessage};
+use crate::siodb::results::{ResultSet, Value};
+use crate::siodb::ClientProtocol::{
+    BeginSessionRequest, BeginSessionResponse, ClientAuthenticationRequest,
+    ClientAuthenticationResponse, Command, ServerResponse,
+};
+use crate::siodb::ColumnDataType;
+use crate::siodb::CommonTypes::StatusMessage;
+
+// Standard
+use std::ops::Range;
+
+// DateTime
+use chrono::prelude::*;
+
+// Byte order
+use byteorder::{ByteOrder, LittleEndian};
+
+// Message types
+pub const COMMAND: u32 = 1;
+pub const SERVER_RESPONSE: u32 = 2;
+pub const BEGIN_SESSION_REQUEST: u32 = 5;
+pub const BEGIN_SESSION_RESPONSE: u32 = 6;
+pub const CLIENT_AUTHENTICATION_REQUEST: u32 = 7;
+pub const CLIENT_AUTHENTICATION_RESPONSE: u32 = 8;
+
+/// A message as sent to Siodb: its type, its length and its bytes.
+pub fn encode_message(
+    message_type: u32,
+    message: &dyn protobuf::Message,
+) -> Result<Vec<u8>, DriverError> {
+    let mut bytes = Vec::new();
+    let mut coded_output_stream = protobuf::CodedOutputStream::vec(&mut bytes);
+    coded_output_stream.write_raw_varint32(message_type)?;
+    coded_output_stream.write_raw_varint32(message.compute_size())?;
+    message.write_to_with_cached_sizes(&mut coded_output_stream)?;
+    coded_output_stream.flush()?;
+    drop(coded_output_stream);
+    Ok(bytes)
+}
+
+/// Parse the bytes of a message received with `message_type_received`.
+pub fn parse_message<M: protobuf::Message>(
+    message_type: u32,
+    message_type_received: u32,
+    bytes: &[u8],
+) -> Result<M, DriverError> {
+    if message_type != message_type_received {
+        return Err(DriverError::new(
+            ErrorKind::Protocol,
+            &format!(
+                "read_message | wrong message type received from Siodb: {}. Expected: {}.",
+                message_type_received, message_type
+            ),
+        ));
+    }
+    Ok(protobuf::parse_from_bytes(bytes)?)
+}
+
+/// Decode the varint at the start of `bytes`: its value and its length, or `None`
+/// until all its bytes are there. Like protobuf, longer varints are truncated.
+pub fn decode_varint32(bytes: &[u8]) -> Result<Option<(u32, usize)>, DriverError> {
+    let mut value: u64 = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        if index == 10 {
+            break;
+        }
+        if index < 5 {
+            value |= u64::from(byte & 0x7f) << (7 * index);
+        }
+        if byte & 0x80 == 0 {
+            return Ok(Some((value as u32, index + 1)));
+        }
+    }
+    if bytes.len() < 10 {
+        return Ok(None);
+    }
+    Err(DriverError::new(
+        ErrorKind::Protocol,
+        "Malformed varint received from Siodb.",
+    ))
+}
+
+/// Split the message at the start of `bytes`: its type and the range of its bytes,
+/// or `None` until all of them are there.
+pub fn split_message(bytes: &[u8]) -> Result<Option<(u32, Range<usize>)>, DriverError> {
+    let (message_type, type_length) = match decode_varint32(bytes)? {
+        Some(varint) => varint,
+        None => return Ok(None),
+    };
+    let (length, length_length) = match decode_varint32(&bytes[type_length..])? {
+        Some(varint) => varint,
+        None => return Ok(None),
+    };
+    let start = type_length + length_length;
+    let end = start + length as usize;
+    if bytes.len() < end {
+        return Ok(None);
+    }
+    Ok(Some((message_type, start..end)))
+}
+
+/// Split the row at the start of `bytes`: the range of its bytes, empty at the
+/// end of the result set, or `None` until all of them are there.
+pub fn split_row(bytes: &[u8]) -> Result<Option<Range<usize>>, DriverError> {
+    let (length, length_length) = match decode_varint32(bytes)? {
+        Some(varint) => varint,
+        None => return Ok(None),
+    };
+    let end = length_length + length as usize;
+    if bytes.len() < end {
+        return Ok(None);
+    }
+    Ok(Some(length_length..end))
+}
+
+pub fn command(request_id: u64, sql: String) -> Command {
+    let mut command = Command::new();
+    command.set_request_id(request_id);
+    command.set_text(sql);
+    command
+}
+
+/// Check that `response` answers the command sent with `request_id`, and comes
+/// as its response `response_id`: anything else means the stream is out of step
+/// with the commands.
+pub fn check_response(
+    request_id: u64,
+    response_id: u32,
+    response: &ServerResponse,
+) -> Result<(), DriverError> {
+    if response.get_request_id() != request_id {
+        return Err(DriverError::new(
+            ErrorKind::Protocol,
+            &format!(
+                "execute | wrong request id received from Siodb: {}. Expected: {}.",
+                response.get_request_id(),
+                request_id
+            ),
+        ));
+    }
+    if response.get_response_id() != response_id {
+        return Err(DriverError::new(
+            ErrorKind::Protocol,
+            &format!(
+                "execute | wrong response id received from Siodb: {}. Expected: {}.",
+                response.get_response_id(),
+                response_id
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn begin_session_request(user_name: String) -> BeginSessionRequest {
+    let mut begin_session_request = BeginSessionRequest::new();
+    begin_session_request.set_user_name(user_name);
+    begin_session_request
+}
+
+pub fn check_session_started(response: &BeginSessionResponse) -> Result<(), DriverError> {
+    if response.get_session_started() {
+        return Ok(());
+    }
+    Err(auth_refused(response.has_message(), response.get_message()))
+}
+
+pub fn client_authentication_request(signature: Vec<u8>) -> ClientAuthenticationRequest {
+    let mut client_authentication_request = ClientAuthenticationRequest::new();
+    client_authentication_request.set_signature(signature);
+    client_authentication_request
+}
+
+pub fn check_authenticated(response: &ClientAuthenticationResponse) -> Result<(), DriverError> {
+    if response.get_authenticated() {
+        return Ok(());
+    }
+    Err(auth_refused(response.has_message(), response.get_message()))
+}
+
+fn auth_refused(has_message: bool, message: &StatusMessage) -> DriverError {
+    let error = DriverError::new(ErrorKind::Auth, "Siodb session not started.");
+    if has_message {
+        return error.with_server_messages(vec![ServerMessage::from(message)]);
+    }
+    error
+}
+
+/// Decode the values of a row, `row` holding the bytes announced by its length.
+pub fn decode_row(
+    row: &[u8],
+    result_set: &ResultSet,
+    trace: bool,
+) -> Result<Vec<Option<Value>>, DriverError> {
+    let mut coded_input_stream = protobuf::CodedInputStream::from_bytes(row);
+    let values = decode_values(&mut coded_input_stream, result_set, trace).and_then(|values| {
+        if coded_input_stream.eof()? {
+            Ok(values)
+        } else {
+            Err(DriverError::new(
+                ErrorKind::Decode,
+                &format!(
+                    "read_data | Row of {} bytes longer than its values.",
+                    row.len()
+                ),
+            ))
+        }
+    });
+    values.map_err(|error| match error.kind() {
+        ErrorKind::Io => DriverError::new(
+            ErrorKind::Decode,
+            &format!(
+                "read_data | Row of {} bytes shorter than its values.",
+                row.len()
+            ),
+        )
+        .with_source(error),
+        _ => error,
+    })
+}
+
+fn decode_values(
+    coded_input_stream: &mut protobuf::CodedInputStream,
+    result_set: &ResultSet,
+    trace: bool,
+) -> Result<Vec<Option<Value>>, DriverError> {
+    let mut row = Vec::<Option<Value>>::new();
+    // Read null Bitmask to figure out null value which are not streamed.
+    let mut bit_mask: Vec<u8> = Vec::new();
+    if result_set.null_bit_mask_present {
+        bit_mask = coded_input_stream.read_raw_bytes(result_set.null_bit_mask_byte_size)?;
+        debug(
+            trace,
+            &format!("ResultSet.next() | Bitmask value: {:?}.", bit_mask),
+        );
+    }
+
+    // Read Row data
+    let mut is_null: u8 = 0;
+    for (idx, column) in result_set
+        .server_response
+        .column_description
+        .iter()
+        .enumerate()
+    {
+        if result_set.null_bit_mask_present {
+            let mask = 1 << (idx % 8);
+            is_null = (bit_mask[idx / 8] & mask) >> (idx % 8);
+            debug(
+                trace,
+                &format!(
+                    "ResultSet.next() | Is that cell (id: {:?} ) null?: {:?}.",
+                    idx, is_null
+                ),
+            );
+        }
+
+        if is_null == 1 {
+            row.push(None)
+        } else {
+            debug(
+                trace,
+                &format!("read_data | data type: {:?}.", column.field_type),
+            );
+            match column.field_type {
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT8 => row.push(Some(
+                    Value::Int8(coded_input_stream.read_raw_bytes(1)?[0] as i8),
+                )),
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT8 => {
+                    row.push(Some(Value::Uint8(coded_input_stream.read_raw_bytes(1)?[0])))
+                }
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT16 => {
+                    row.push(Some(Value::Int16(LittleEndian::read_i16(
+                        &coded_input_stream.read_raw_bytes(2)?,
+                    ))))
+                }
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT16 => {
+                    row.push(Some(Value::Uint16(LittleEndian::read_u16(
+                        &coded_input_stream.read_raw_bytes(2)?,
+                    ))))
+                }
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT32 => row.push(Some(
+                    Value::Int32(coded_input_stream.read_raw_varint32()? as i32),
+                )),
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT32 => {
+                    row.push(Some(Value::Uint32(coded_input_stream.read_raw_varint32()?)))
+                }
+
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_FLOAT => {
+                    row.push(Some(Value::Float(coded_input_stream.read_float()?)))
+                }
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_DOUBLE => {
+                    row.push(Some(Value::Double(coded_input_stream.read_double()?)))
+                }
+
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_INT64 => row.push(Some(
+                    Value::Int64(coded_input_stream.read_raw_varint64()? as i64),
+                )),
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_UINT64 => {
+                    row.push(Some(Value::Uint64(coded_input_stream.read_raw_varint64()?)))
+                }
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_TEXT => {
+                    let data_length = coded_input_stream.read_raw_varint32()?;
+                    let text = String::from_utf8(coded_input_stream.read_raw_bytes(data_length)?)
+                        .map_err(|e| {
+                        DriverError::new(
+                            ErrorKind::Decode,
+                            &format!("read_data | Invalid UTF-8 in TEXT column {}: {}", idx, e),
+                        )
+                        .with_source(e)
+                    })?;
+                    row.push(Some(Value::Text(text)));
+                }
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_BINARY => {
+                    let data_length = coded_input_stream.read_raw_varint32()?;
+                    row.push(Some(Value::Binary(
+                        coded_input_stream.read_raw_bytes(data_length)?,
+                    )));
+                }
+                ColumnDataType::ColumnDataType::COLUMN_DATA_TYPE_TIMESTAMP => {
+                    let mut hours: u8 = 0;
+                    let mut minutes: u8 = 0;
+                    let mut seconds: u8 = 0;
+                    let mut nano: u32 = 0;
+                    // Get date part, 4 first bytes
+                    let date = coded_input_stream.read_raw_bytes(4)?;
+                    debug(
+                        trace,
+                        &format!(
+                            "Binary timestamp: {:08b} {:08b} {:08b} {:08b} ",
+                            date[0], date[1], date[2], date[3]
+                        ),
+                    );
+                    let has_time_part = date[0] & 0b0000_0001;
+                    let day_of_week = (date[0] & 0b0000_1110) >> 1;
+                    let day_of_month =
+                        (((date[0] & 0b1111_0000) >> 4) + ((date[1] & 0b0000_0001) << 4)) + 1;
+                    let month = ((date[1] & 0b0001_1110) >> 1) + 1;
+                    let year_bytes = [
+                        0b0000_0000,
+                        (date[3] & 0b1110_0000) >> 5,
+                        ((date[2] & 0b1110_0000) >> 5) + ((date[3] & 0b0001_1111) << 3),
+                        ((date[1] & 0b1110_0000) >> 5) + ((date[2] & 0b0001_1111) << 3),
+                    ];
+                    let year = i32::from_be_bytes(year_bytes);
+                    debug(
+                        trace,
+                        &format!(
+                            "hasTimePart: {:?} | dayOfWeek: {:?} | dayOfMonth: {:?} | month: {:?} | year: {:?} ",
+                            has_time_part, day_of_week, day_of_month, month, year
+                        ),
+                    );
+                    if has_time_part == 1 {
+                        // Get time part, 6 last bytes
+                        let time = coded_input_stream.read_raw_bytes(6)?;
+                        let nano_bytes = [
+                            ((time[3] & 0b0111_1110) >> 1),
+                            ((time[2] & 0b1111_1110) >> 1) + ((time[3] & 0b0000_0001) << 7),
+                            ((time[1] & 0b1111_1110) >> 1) + ((time[2] & 0b0000_0001) << 7),
+                            ((time[0] & 0b1111_1110) >> 1) + ((time[1] & 0b0000_0001) << 7),
+                        ];
+                        nano = u32::from_be_bytes(nano_bytes);
+                        seconds = ((time[3] & 0b1000_0000) >> 7) + ((time[4] & 0b0001_1111) << 1);
+                        minutes = ((time[4] & 0b1110_0000) >> 5) + ((time[5] & 0b0000_0111) << 3);
+                        hours = (time[5] & 0b1111_1000) >> 3;
+                        debug(
+                            trace,
+                            &format!(
+                                "hours: {:?} | minutes: {:?} | seconds: {:?} | nano: {:?} | nano_bytes: {:?}",
+                                hours, minutes, seconds as u32, nano, nano_bytes
+                            ),
+                        );
+                    }
+                    let timestamp = Utc
+                        .ymd_opt(year, month.into(), day_of_month.into())
+                        .single()
+                        .and_then(|date| {
+                            date.and_hms_nano_opt(
+                                hours.into(),
+                                minutes.into(),
+                                seconds.into(),
+                                nano,
+                            )
+                        })
+                        .ok_or_else(|| {
+                            DriverError::new(
+                                ErrorKind::Decode,
+                                &format!(
+                                    "read_data | Invalid timestamp in column {}: {}-{}-{} {}:{}:{}.{}",
+                                    idx, year, month, day_of_month, hours, minutes, seconds, nano
+                                ),
+                            )
+                        })?;
+                    row.push(Some(Value::Timestamp(timestamp)));
+                }
+                _ => {
+                    return Err(DriverError::new(
+                        ErrorKind::Decode,
+                        &format!("read_data | Unknow data type: {:?}.", column.field_type),
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(row)
+}
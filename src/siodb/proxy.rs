@@ -0,0 +1,344 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::hosts::bare_host;
+use crate::siodb::options::COMPONENT;
+use crate::siodb::transport::connect_tcp;
+
+// Standard
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Base64
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+// Url for URI
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use url::Url;
+
+const SOCKS5_DEFAULT_PORT: u16 = 1080;
+const HTTP_DEFAULT_PORT: u16 = 8080;
+// Longest HTTP CONNECT response header read before giving up.
+const MAX_HTTP_HEADER: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+/// A proxy the TCP connection to Siodb is opened through, before TLS.
+///
+/// Parsed from `socks5://[user:password@]host[:port]` (port 1080 by default)
+/// or `http://[user:password@]host[:port]` (port 8080 by default). With SOCKS5,
+/// the proxy resolves the host name of Siodb.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Proxy {
+    kind: ProxyKind,
+    host: String,
+    port: u16,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+impl Proxy {
+    /// A SOCKS5 proxy.
+    pub fn socks5(host: &str, port: u16) -> Proxy {
+        Proxy::new(ProxyKind::Socks5, host, port)
+    }
+
+    /// An HTTP proxy accepting the CONNECT method.
+    pub fn http(host: &str, port: u16) -> Proxy {
+        Proxy::new(ProxyKind::Http, host, port)
+    }
+
+    fn new(kind: ProxyKind, host: &str, port: u16) -> Proxy {
+        Proxy {
+            kind,
+            host: bare_host(host).to_string(),
+            port,
+            user: None,
+            password: None,
+        }
+    }
+
+    /// Authenticate with the proxy: SOCKS5 username/password or HTTP Basic.
+    pub fn with_auth(mut self, user: &str, password: &str) -> Proxy {
+        self.user = Some(user.to_string());
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// The URL of the proxy, with its password unless `hide_secrets`.
+    pub(crate) fn url(&self, hide_secrets: bool) -> String {
+        let scheme = match self.kind {
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Http => "http",
+        };
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        let auth = match (&self.user, &self.password) {
+            (Some(user), Some(password)) => format!(
+                "{}:{}@",
+                utf8_percent_encode(user, COMPONENT),
+                if hide_secrets {
+                    "***".to_string()
+                } else {
+                    utf8_percent_encode(password, COMPONENT).to_string()
+                }
+            ),
+            (Some(user), None) => format!("{}@", utf8_percent_encode(user, COMPONENT)),
+            (None, _) => String::new(),
+        };
+        format!("{}://{}{}:{}", scheme, auth, host, self.port)
+    }
+
+    /// Open a TCP connection to `host:port` through the proxy. `timeout` bounds
+    /// the connection to the proxy and each read and write of the proxy handshake.
+    pub(crate) fn connect(
+        &self,
+        host: &str,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> io::Result<TcpStream> {
+        let mut stream = connect_tcp(&self.host, self.port, timeout)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        match self.kind {
+            ProxyKind::Socks5 => self.socks5_connect(&mut stream, host, port)?,
+            ProxyKind::Http => self.http_connect(&mut stream, host, port)?,
+        }
+        Ok(stream)
+    }
+
+    // RFC 1928, with the username/password authentication of RFC 1929.
+    fn socks5_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        let credentials = match (&self.user, &self.password) {
+            (Some(user), password) => Some((user.as_str(), password.as_deref().unwrap_or(""))),
+            (None, _) => None,
+        };
+        if credentials.is_some() {
+            stream.write_all(&[5, 2, 0, 2])?;
+        } else {
+            stream.write_all(&[5, 1, 0])?;
+        }
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != 5 {
+            return Err(proxy_error("the proxy is not a SOCKS5 proxy"));
+        }
+        match (reply[1], credentials) {
+            (0, _) => {}
+            (2, Some((user, password))) => {
+                if user.len() > 255 || password.len() > 255 {
+                    return Err(proxy_error("proxy user or password longer than 255 bytes"));
+                }
+                let mut request = vec![1, user.len() as u8];
+                request.extend_from_slice(user.as_bytes());
+                request.push(password.len() as u8);
+                request.extend_from_slice(password.as_bytes());
+                stream.write_all(&request)?;
+                stream.read_exact(&mut reply)?;
+                if reply[1] != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "the proxy rejected the user or password",
+                    ));
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "the proxy accepts none of the offered authentication methods",
+                ))
+            }
+        }
+
+        let mut request = vec![5, 1, 0];
+        match IpAddr::from_str(host) {
+            Ok(IpAddr::V4(address)) => {
+                request.push(1);
+                request.extend_from_slice(&address.octets());
+            }
+            Ok(IpAddr::V6(address)) => {
+                request.push(4);
+                request.extend_from_slice(&address.octets());
+            }
+            Err(_) => {
+                if host.len() > 255 {
+                    return Err(proxy_error("host name longer than 255 bytes"));
+                }
+                request.push(3);
+                request.push(host.len() as u8);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request)?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "the proxy refused the connection: {}",
+                    socks5_reply(reply[1])
+                ),
+            ));
+        }
+        // Skip the address the proxy bound.
+        let address_len = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len)?;
+                len[0] as usize
+            }
+            _ => return Err(proxy_error("unknown address type in the SOCKS5 reply")),
+        };
+        let mut bound = vec![0u8; address_len + 2];
+        stream.read_exact(&mut bound)?;
+        Ok(())
+    }
+
+    fn http_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        let authority = if host.contains(':') {
+            format!("[{}]:{}", host, port)
+        } else {
+            format!("{}:{}", host, port)
+        };
+        let mut request = format!(
+            "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\nProxy-Connection: Keep-Alive\r\n",
+            authority
+        );
+        if let Some(user) = &self.user {
+            let credentials = format!("{}:{}", user, self.password.as_deref().unwrap_or(""));
+            request.push_str(&format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                BASE64.encode(credentials.as_bytes())
+            ));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        // Read byte by byte: what follows the header belongs to Siodb.
+        let mut header = Vec::new();
+        let mut byte = [0u8; 1];
+        while !header.ends_with(b"\r\n\r\n") {
+            if header.len() >= MAX_HTTP_HEADER {
+                return Err(proxy_error("the proxy response header is too long"));
+            }
+            stream.read_exact(&mut byte)?;
+            header.push(byte[0]);
+        }
+        let header = String::from_utf8_lossy(&header);
+        let status_line = header.lines().next().unwrap_or_default();
+        let mut fields = status_line.split_whitespace();
+        let status = match (fields.next(), fields.next()) {
+            (Some(version), Some(status)) if version.starts_with("HTTP/1.") => status,
+            _ => return Err(proxy_error("the proxy is not an HTTP proxy")),
+        };
+        match status {
+            "200" => Ok(()),
+            "407" => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("the proxy requires authentication: {}", status_line),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("the proxy refused the connection: {}", status_line),
+            )),
+        }
+    }
+}
+
+impl FromStr for Proxy {
+    type Err = DriverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wrong_proxy = || {
+            DriverError::new(
+                ErrorKind::Config,
+                &format!(
+                    "Wrong proxy: '{}'. Should be 'socks5://host:port' or 'http://host:port'.",
+                    s
+                ),
+            )
+        };
+        let url = Url::parse(s).map_err(|_| wrong_proxy())?;
+        let (kind, default_port) = match url.scheme() {
+            "socks5" => (ProxyKind::Socks5, SOCKS5_DEFAULT_PORT),
+            "http" => (ProxyKind::Http, HTTP_DEFAULT_PORT),
+            _ => return Err(wrong_proxy()),
+        };
+        let host = match url.host_str() {
+            Some(host) if !host.is_empty() && matches!(url.path(), "" | "/") => host,
+            _ => return Err(wrong_proxy()),
+        };
+        // Url forgets a port of 80 for http, which is not the default port here.
+        let port = match url.port_or_known_default() {
+            Some(port) if has_port(s) => port,
+            _ => default_port,
+        };
+        let decode = |text: &str| percent_decode_str(text).decode_utf8_lossy().to_string();
+        let mut proxy = Proxy::new(kind, host, port);
+        if !url.username().is_empty() {
+            proxy.user = Some(decode(url.username()));
+            proxy.password = url.password().map(decode);
+        }
+        Ok(proxy)
+    }
+}
+
+// Whether the authority of a URL ends with a port, as in `host:80` or `[::1]:80`.
+fn has_port(url: &str) -> bool {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = &rest[..rest.find(['/', '?', '#']).unwrap_or(rest.len())];
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    host[host.rfind(']').unwrap_or(0)..].contains(':')
+}
+
+impl fmt::Display for Proxy {
+    // The password is never printed.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.url(true))
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.url(true))
+    }
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
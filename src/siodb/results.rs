@@ -3,7 +3,7 @@
 // in the LICENSE file.
 
 // Siodb
-use crate::siodb::errors::DriverError;
+use crate::siodb::errors::{debug, DriverError, ServerMessage};
 
 // Protobuf
 use crate::siodb::ClientProtocol::ServerResponse;
@@ -17,22 +17,81 @@ use chrono::prelude::*;
 pub struct ResultSet {
     pub server_response: ServerResponse,
     pub null_bit_mask_present: bool,
-    pub null_bit_mask_byte_size: u8,
+    pub null_bit_mask_byte_size: u32,
     pub end_of_row: bool,
     pub row_count: u64,
     pub current_row: Option<Vec<Option<Value>>>,
 }
 
 impl ResultSet {
-    pub fn new(server_response: ServerResponse) -> Result<ResultSet, DriverError> {
-        Ok(ResultSet {
-            server_response: server_response,
+    /// The result of a statement. Rows follow the response when it describes
+    /// columns and has no error message.
+    pub fn new(server_response: ServerResponse, trace: bool) -> ResultSet {
+        let mut result_set = ResultSet {
+            server_response,
             null_bit_mask_present: false,
             null_bit_mask_byte_size: 0,
             end_of_row: true,
             current_row: None,
             row_count: 0,
-        })
+        };
+
+        // Check dataset presence
+        let column_count = result_set.server_response.get_column_description().len();
+
+        if column_count > 0 && result_set.server_response.message.is_empty() {
+            result_set.end_of_row = false;
+            debug(
+                trace,
+                &format!(
+                    "Dataset present in the the server's response with {} colmuns.",
+                    column_count
+                ),
+            );
+
+            // Check if nullbitmask present
+            if result_set
+                .server_response
+                .column_description
+                .iter()
+                .any(|column| column.is_null)
+            {
+                result_set.null_bit_mask_present = true;
+                debug(trace, "null_bit_mask_present: true.");
+                // Get nul bitmask byte size
+                result_set.null_bit_mask_byte_size = column_count.div_ceil(8) as u32;
+                debug(
+                    trace,
+                    &format!(
+                        "null_bit_mask_byte_size: {}.",
+                        result_set.null_bit_mask_byte_size
+                    ),
+                );
+            }
+        }
+        result_set
+    }
+
+    /// Whether Siodb sends the responses of more statements of the command.
+    pub fn has_more_responses(&self) -> bool {
+        self.server_response.get_response_id() + 1 < self.server_response.get_response_count()
+    }
+
+    /// The status messages Siodb sent with the response.
+    pub fn server_messages(&self) -> Vec<ServerMessage> {
+        self.server_response
+            .message
+            .iter()
+            .map(ServerMessage::from)
+            .collect()
+    }
+
+    /// The error of the statement when Siodb returned status messages.
+    pub fn server_error(&self) -> Option<DriverError> {
+        if self.server_response.message.is_empty() {
+            return None;
+        }
+        Some(DriverError::server(self.server_messages()))
     }
 }
 
@@ -69,7 +128,7 @@ pub enum Value {
 impl fmt::Display for Value {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &*self {
+        match self {
             Value::Int8(c) => write!(f, "{}", c),
             Value::Uint8(c) => write!(f, "{}", c),
             Value::Int16(c) => write!(f, "{}", c),
@@ -90,7 +149,7 @@ impl fmt::Display for Value {
 impl fmt::Debug for Value {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &*self {
+        match self {
             Value::Int8(c) => write!(f, "Value: ->{:?}<-", c),
             Value::Uint8(c) => write!(f, "Value: ->{:?}<-", c),
             Value::Int16(c) => write!(f, "Value: ->{:?}<-", c),
@@ -110,20 +169,20 @@ impl fmt::Debug for Value {
 
 impl Clone for Value {
     fn clone(&self) -> Value {
-        match &*self {
-            Value::Int8(c) => return Value::Int8(c.clone()),
-            Value::Uint8(c) => return Value::Uint8(c.clone()),
-            Value::Int16(c) => return Value::Int16(c.clone()),
-            Value::Uint16(c) => return Value::Uint16(c.clone()),
-            Value::Int32(c) => return Value::Int32(c.clone()),
-            Value::Uint32(c) => return Value::Uint32(c.clone()),
-            Value::Int64(c) => return Value::Int64(c.clone()),
-            Value::Uint64(c) => return Value::Uint64(c.clone()),
-            Value::Float(c) => return Value::Float(c.clone()),
-            Value::Double(c) => return Value::Double(c.clone()),
-            Value::Text(c) => return Value::Text(c.clone()),
-            Value::Binary(c) => return Value::Binary(c.clone()),
-            Value::Timestamp(c) => return Value::Timestamp(c.clone()),
+        match self {
+            Value::Int8(c) => Value::Int8(*c),
+            Value::Uint8(c) => Value::Uint8(*c),
+            Value::Int16(c) => Value::Int16(*c),
+            Value::Uint16(c) => Value::Uint16(*c),
+            Value::Int32(c) => Value::Int32(*c),
+            Value::Uint32(c) => Value::Uint32(*c),
+            Value::Int64(c) => Value::Int64(*c),
+            Value::Uint64(c) => Value::Uint64(*c),
+            Value::Float(c) => Value::Float(*c),
+            Value::Double(c) => Value::Double(*c),
+            Value::Text(c) => Value::Text(c.clone()),
+            Value::Binary(c) => Value::Binary(c.clone()),
+            Value::Timestamp(c) => Value::Timestamp(*c),
         }
     }
 }
@@ -0,0 +1,105 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+
+// Standard
+use std::time::Duration;
+
+/// Timeouts of a connection. Unset timeouts wait forever, and a zero timeout leaves
+/// it unset, as 0 does in the URI.
+///
+/// An expired timeout is returned as an `ErrorKind::Timeout` error. The statement
+/// it interrupted is left half read, so the connection should then be closed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    pub(crate) connect: Option<Duration>,
+    pub(crate) read: Option<Duration>,
+    pub(crate) write: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new() -> Timeouts {
+        Timeouts::default()
+    }
+
+    /// Time to open the socket. It also bounds every read and write of the TLS
+    /// handshake and of the authentication.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Timeouts {
+        self.connect = non_zero(timeout);
+        self
+    }
+
+    /// Time to wait for data from Siodb once the connection is authenticated.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Timeouts {
+        self.read = non_zero(timeout);
+        self
+    }
+
+    /// Time to wait for Siodb to accept data once the connection is authenticated.
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Timeouts {
+        self.write = non_zero(timeout);
+        self
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write
+    }
+
+    /// These timeouts, with the unset ones taken from `defaults`.
+    pub(crate) fn or(self, defaults: Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(defaults.connect),
+            read: self.read.or(defaults.read),
+            write: self.write.or(defaults.write),
+        }
+    }
+}
+
+/// A zero duration means no timeout, never an immediate one.
+pub fn non_zero(timeout: Duration) -> Option<Duration> {
+    Some(timeout).filter(|timeout| !timeout.is_zero())
+}
+
+/// Parse the value of a timeout URI option: a number of seconds, 0 for no timeout.
+pub fn parse_timeout(option: &str, value: &str) -> Result<Option<Duration>, DriverError> {
+    let wrong_value = || {
+        DriverError::new(
+            ErrorKind::Config,
+            &format!(
+                "Wrong {}: '{}'. Should be a number of seconds.",
+                option, value
+            ),
+        )
+    };
+    // Parsed as a decimal, not a float, so that printed timeouts read back exactly.
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if seconds.is_empty() || !digits(seconds) || !digits(fraction) || fraction.len() > 9 {
+        return Err(wrong_value());
+    }
+    let seconds = seconds.parse::<u64>().map_err(|_| wrong_value())?;
+    let nanos = format!("{:0<9}", fraction)
+        .parse::<u32>()
+        .map_err(|_| wrong_value())?;
+    Ok(non_zero(Duration::new(seconds, nanos)))
+}
+
+/// Print a timeout the way `parse_timeout` reads it.
+pub fn format_timeout(timeout: Duration) -> String {
+    if timeout.subsec_nanos() == 0 {
+        return timeout.as_secs().to_string();
+    }
+    let nanos = format!("{:09}", timeout.subsec_nanos());
+    format!("{}.{}", timeout.as_secs(), nanos.trim_end_matches('0'))
+}
@@ -0,0 +1,391 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::keys;
+use crate::siodb::keys::PrivateKey;
+#[cfg(feature = "tokio")]
+use crate::siodb::transport::AsyncStream;
+use crate::siodb::Transport;
+
+// Standard
+use std::fmt;
+use std::fs;
+use std::net::TcpStream;
+use std::str::FromStr;
+#[cfg(feature = "tokio")]
+use std::time::Duration;
+
+// Backend: OpenSSL when both features are enabled.
+#[cfg(feature = "openssl")]
+mod with_openssl;
+#[cfg(feature = "openssl")]
+use with_openssl as backend;
+#[cfg(all(feature = "rustls", not(feature = "openssl")))]
+mod with_rustls;
+#[cfg(all(feature = "rustls", not(feature = "openssl")))]
+use with_rustls as backend;
+
+/// How a TCP connection to Siodb is protected, in the spirit of libpq's `sslmode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SslMode {
+    /// Plain TCP, no TLS at all.
+    Disable,
+    /// TLS without any certificate verification. Only for local development.
+    Require,
+    /// TLS, the server certificate must chain to a trusted CA.
+    VerifyCa,
+    /// TLS, the server certificate must chain to a trusted CA and match the host name.
+    VerifyFull,
+}
+
+impl FromStr for SslMode {
+    type Err = DriverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(SslMode::Disable),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(DriverError::new(ErrorKind::Config, &format!(
+                "Wrong sslmode: '{}'. Should be 'disable', 'require', 'verify-ca' or 'verify-full'.",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SslMode::Disable => write!(f, "disable"),
+            SslMode::Require => write!(f, "require"),
+            SslMode::VerifyCa => write!(f, "verify-ca"),
+            SslMode::VerifyFull => write!(f, "verify-full"),
+        }
+    }
+}
+
+/// Lowest TLS version a connection accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = DriverError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "TLSv1.2" => Ok(TlsVersion::Tls12),
+            "TLSv1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(DriverError::new(
+                ErrorKind::Config,
+                &format!(
+                    "Wrong ssl_min_protocol_version: '{}'. Should be 'TLSv1.2' or 'TLSv1.3'.",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsVersion::Tls12 => write!(f, "TLSv1.2"),
+            TlsVersion::Tls13 => write!(f, "TLSv1.3"),
+        }
+    }
+}
+
+/// TLS settings of a connection.
+#[derive(Clone)]
+pub struct TlsOptions {
+    pub sslmode: SslMode,
+    pub min_version: Option<TlsVersion>,
+    pub server_name: Option<String>,
+    pub cert_sha256: Vec<[u8; 32]>,
+    pub ca_file: Option<String>,
+    pub ca_dir: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub client_key_passphrase: Option<String>,
+}
+
+impl fmt::Debug for TlsOptions {
+    // The passphrase must never end up in a trace.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cert_sha256: Vec<String> = self.cert_sha256.iter().map(format_fingerprint).collect();
+        write!(
+            f,
+            "sslmode: {} | min_version: {:?} | server_name: {:?} | cert_sha256: {:?} | ca_file: {:?} | ca_dir: {:?} | client_cert: {:?} | client_key: {:?} | client_key_passphrase: {}",
+            self.sslmode,
+            self.min_version,
+            self.server_name,
+            cert_sha256,
+            self.ca_file,
+            self.ca_dir,
+            self.client_cert,
+            self.client_key,
+            if self.client_key_passphrase.is_some() { "***" } else { "None" },
+        )
+    }
+}
+
+/// Parse a SHA-256 certificate fingerprint: 64 hexadecimal digits, optionally
+/// separated by colons as `openssl x509 -fingerprint -sha256` prints them.
+pub fn parse_fingerprint(text: &str) -> Result<[u8; 32], DriverError> {
+    let wrong_fingerprint = || {
+        DriverError::new(
+            ErrorKind::Config,
+            &format!(
+                "Wrong cert_sha256: '{}'. Should be 64 hexadecimal digits.",
+                text
+            ),
+        )
+    };
+    let digits: Vec<u8> = text.bytes().filter(|&byte| byte != b':').collect();
+    if digits.len() != 64 {
+        return Err(wrong_fingerprint());
+    }
+    let mut fingerprint = [0u8; 32];
+    for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| wrong_fingerprint())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| wrong_fingerprint())?;
+    }
+    Ok(fingerprint)
+}
+
+/// A SHA-256 fingerprint as colon separated uppercase hexadecimal digits.
+pub fn format_fingerprint(fingerprint: &[u8; 32]) -> String {
+    let digits: Vec<String> = fingerprint
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    digits.join(":")
+}
+
+/// Run the TLS handshake with Siodb over `stream`, with the backend selected
+/// by the `openssl` or `rustls` cargo feature.
+pub fn connect(
+    options: &TlsOptions,
+    host: &str,
+    port: u16,
+    stream: TcpStream,
+) -> Result<Box<dyn Transport>, DriverError> {
+    backend::connect(options, host, port, stream)
+}
+
+/// Run the TLS handshake of an async connection over `stream`, bounded by `timeout`,
+/// with the backend selected by the `tokio-openssl` or `tokio-rustls` cargo feature.
+#[cfg(any(
+    feature = "tokio-openssl",
+    all(feature = "tokio-rustls", not(feature = "openssl"))
+))]
+pub async fn connect_async(
+    options: &TlsOptions,
+    host: &str,
+    port: u16,
+    stream: tokio::net::TcpStream,
+    timeout: Option<Duration>,
+) -> Result<Box<dyn AsyncStream>, DriverError> {
+    let handshake = backend::connect_async(options, host, port, stream);
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, handshake)
+            .await
+            .unwrap_or_else(|_| Err(handshake_timed_out(host, port))),
+        None => handshake.await,
+    }
+}
+
+#[cfg(all(
+    feature = "tokio",
+    not(any(
+        feature = "tokio-openssl",
+        all(feature = "tokio-rustls", not(feature = "openssl"))
+    ))
+))]
+pub async fn connect_async(
+    _options: &TlsOptions,
+    _host: &str,
+    _port: u16,
+    _stream: tokio::net::TcpStream,
+    _timeout: Option<Duration>,
+) -> Result<Box<dyn AsyncStream>, DriverError> {
+    Err(DriverError::new(
+        ErrorKind::Config,
+        "TLS with the async client requires the 'tokio-openssl' feature of the siodb crate, or 'tokio-rustls' without 'openssl'.",
+    ))
+}
+
+/// The name the server is asked for with SNI and its certificate checked against:
+/// server_name if set, the host otherwise.
+pub fn server_name<'a>(options: &'a TlsOptions, host: &'a str) -> &'a str {
+    match &options.server_name {
+        Some(server_name) => server_name,
+        None => host,
+    }
+}
+
+// With pinned fingerprints, the server certificate must have one of them, whatever
+// the sslmode.
+pub fn check_pins(
+    options: &TlsOptions,
+    host: &str,
+    port: u16,
+    fingerprint: Option<[u8; 32]>,
+) -> Result<(), DriverError> {
+    if options.cert_sha256.is_empty() {
+        return Ok(());
+    }
+    match fingerprint {
+        Some(fingerprint) if options.cert_sha256.contains(&fingerprint) => Ok(()),
+        Some(fingerprint) => Err(DriverError::new(
+            ErrorKind::Tls,
+            &format!(
+                "The certificate of '{}:{}' matches no pinned fingerprint: SHA-256 {}.",
+                host,
+                port,
+                format_fingerprint(&fingerprint)
+            ),
+        )),
+        None => Err(DriverError::new(
+            ErrorKind::Tls,
+            &format!("'{}:{}' sent no certificate to check the pins.", host, port),
+        )),
+    }
+}
+
+/// The client certificate and its key.
+pub enum ClientIdentity {
+    /// A PKCS#12 archive holding certificate and key.
+    Pkcs12 {
+        archive: Vec<u8>,
+        passphrase: String,
+    },
+    /// A PEM certificate chain and its key.
+    Pem { chain: Vec<u8>, key: PrivateKey },
+}
+
+// The client certificate is either a PKCS#12 archive holding certificate and key,
+// or a PEM certificate chain with its key in client_key (or in the same file).
+pub fn client_identity(options: &TlsOptions) -> Result<Option<ClientIdentity>, DriverError> {
+    let client_cert = match &options.client_cert {
+        Some(client_cert) => client_cert,
+        None if options.client_key.is_some() => {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "client_key requires client_cert.",
+            ))
+        }
+        None => return Ok(None),
+    };
+    let client_key = options.client_key.as_deref();
+    let passphrase = options.client_key_passphrase.as_deref();
+
+    let cert_contents = fs::read(client_cert).map_err(|e| {
+        DriverError::new(
+            ErrorKind::Tls,
+            &format!("Error reading client certificate '{}': {}", client_cert, e),
+        )
+        .with_source(e)
+    })?;
+
+    if !cert_contents.starts_with(b"-----BEGIN") {
+        if client_key.is_some() {
+            return Err(DriverError::new(
+                ErrorKind::Config,
+                "client_key must not be set with a PKCS#12 client certificate.",
+            ));
+        }
+        return Ok(Some(ClientIdentity::Pkcs12 {
+            archive: cert_contents,
+            passphrase: passphrase.unwrap_or("").to_string(),
+        }));
+    }
+
+    let key_contents = match client_key {
+        Some(client_key) => fs::read(client_key).map_err(|e| {
+            DriverError::new(
+                ErrorKind::Tls,
+                &format!("Error reading client key '{}': {}", client_key, e),
+            )
+            .with_source(e)
+        })?,
+        None => cert_contents.clone(),
+    };
+    let key = keys::parse_private_key(&key_contents, client_key.unwrap_or(client_cert), || {
+        passphrase.map(|passphrase| passphrase.to_string())
+    })?;
+    Ok(Some(ClientIdentity::Pem {
+        chain: cert_contents,
+        key,
+    }))
+}
+
+// A CA bundle may contain several PEM certificates.
+pub fn read_ca_file(ca_file: &str) -> Result<Vec<u8>, DriverError> {
+    fs::read(ca_file).map_err(|e| {
+        DriverError::new(
+            ErrorKind::Tls,
+            &format!("Error reading CA file '{}': {}", ca_file, e),
+        )
+        .with_source(e)
+    })
+}
+
+// The files of a CA directory, as `c_rehash` prepares them. Subdirectories are skipped.
+pub fn read_ca_dir(ca_dir: &str) -> Result<Vec<Vec<u8>>, DriverError> {
+    let error = |e: std::io::Error| {
+        DriverError::new(
+            ErrorKind::Tls,
+            &format!("Error reading CA directory '{}': {}", ca_dir, e),
+        )
+        .with_source(e)
+    };
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(ca_dir).map_err(error)? {
+        let path = entry.map_err(error)?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| fs::read(path).map_err(error))
+        .collect()
+}
+
+pub fn no_ca_certificate(ca_file: &str) -> DriverError {
+    DriverError::new(
+        ErrorKind::Tls,
+        &format!("No certificate found in CA file '{}'.", ca_file),
+    )
+}
+
+pub fn no_ca_dir_certificate(ca_dir: &str) -> DriverError {
+    DriverError::new(
+        ErrorKind::Tls,
+        &format!("No certificate found in CA directory '{}'.", ca_dir),
+    )
+}
+
+pub fn handshake_timed_out(host: &str, port: u16) -> DriverError {
+    DriverError::new(
+        ErrorKind::Timeout,
+        &format!("TLS handshake with '{}:{}' timed out.", host, port),
+    )
+}
+
+pub fn handshake_failed(host: &str, port: u16, reason: &dyn fmt::Display) -> DriverError {
+    DriverError::new(
+        ErrorKind::Tls,
+        &format!("TLS handshake with '{}:{}' failed: {}", host, port, reason),
+    )
+}
@@ -0,0 +1,228 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// TLS with native-tls.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::tls::{
+    check_pins, client_identity, handshake_failed, handshake_timed_out, no_ca_certificate,
+    no_ca_dir_certificate, read_ca_dir, read_ca_file, server_name, ClientIdentity, SslMode,
+    TlsOptions, TlsVersion,
+};
+#[cfg(feature = "tokio-openssl")]
+use crate::siodb::transport::AsyncStream;
+use crate::siodb::Transport;
+
+// Standard
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+// TLS
+use native_tls::{Certificate, HandshakeError, Identity, Protocol, TlsConnector, TlsStream};
+use openssl::error::ErrorStack;
+use openssl::sha::sha256;
+use openssl::x509::X509;
+
+pub fn connect(
+    options: &TlsOptions,
+    host: &str,
+    port: u16,
+    stream: TcpStream,
+) -> Result<Box<dyn Transport>, DriverError> {
+    let connector = build_connector(options)?;
+    let stream = connector
+        .connect(server_name(options, host), stream)
+        .map_err(|e| match e {
+            // A blocking socket only stops mid-handshake when a timeout expires.
+            HandshakeError::WouldBlock(_) => handshake_timed_out(host, port),
+            HandshakeError::Failure(e) => handshake_failed(host, port, &e).with_source(e),
+        })?;
+
+    if !options.cert_sha256.is_empty() {
+        check_pins(options, host, port, fingerprint(&stream, host, port)?)?;
+    }
+    Ok(Box::new(stream))
+}
+
+/// Run the TLS handshake of an async connection over `stream`.
+#[cfg(feature = "tokio-openssl")]
+pub async fn connect_async(
+    options: &TlsOptions,
+    host: &str,
+    port: u16,
+    stream: tokio::net::TcpStream,
+) -> Result<Box<dyn AsyncStream>, DriverError> {
+    let connector = tokio_native_tls::TlsConnector::from(build_connector(options)?);
+    let stream = connector
+        .connect(server_name(options, host), stream)
+        .await
+        .map_err(|e| handshake_failed(host, port, &e).with_source(e))?;
+
+    if !options.cert_sha256.is_empty() {
+        check_pins(
+            options,
+            host,
+            port,
+            fingerprint(stream.get_ref(), host, port)?,
+        )?;
+    }
+    Ok(Box::new(stream))
+}
+
+// SHA-256 of the server certificate.
+fn fingerprint<S: io::Read + io::Write>(
+    stream: &TlsStream<S>,
+    host: &str,
+    port: u16,
+) -> Result<Option<[u8; 32]>, DriverError> {
+    let certificate = stream
+        .peer_certificate()
+        .map_err(|e| handshake_failed(host, port, &e).with_source(e))?;
+    match certificate {
+        Some(certificate) => {
+            Ok(Some(sha256(&certificate.to_der().map_err(|e| {
+                handshake_failed(host, port, &e).with_source(e)
+            })?)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Build the TLS connector from the connection's TLS settings.
+fn build_connector(options: &TlsOptions) -> Result<TlsConnector, DriverError> {
+    let mut builder = TlsConnector::builder();
+    match options.sslmode {
+        SslMode::Require => {
+            builder.danger_accept_invalid_hostnames(true);
+            builder.danger_accept_invalid_certs(true);
+        }
+        SslMode::VerifyCa => {
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        SslMode::VerifyFull | SslMode::Disable => {}
+    }
+    builder.min_protocol_version(options.min_version.map(|version| match version {
+        TlsVersion::Tls12 => Protocol::Tlsv12,
+        TlsVersion::Tls13 => Protocol::Tlsv13,
+    }));
+
+    if let Some(ca_file) = &options.ca_file {
+        for certificate in load_ca_file(ca_file)? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(ca_dir) = &options.ca_dir {
+        for certificate in load_ca_dir(ca_dir)? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+
+    if let Some(identity) = client_identity(options)? {
+        builder.identity(load_identity(
+            identity,
+            options.client_cert.as_deref().unwrap_or_default(),
+        )?);
+    }
+
+    builder.build().map_err(|e| {
+        DriverError::new(
+            ErrorKind::Tls,
+            &format!("Unable to create TLS connector: {}", e),
+        )
+        .with_source(e)
+    })
+}
+
+fn load_identity(identity: ClientIdentity, client_cert: &str) -> Result<Identity, DriverError> {
+    match identity {
+        ClientIdentity::Pkcs12 {
+            archive,
+            passphrase,
+        } => Identity::from_pkcs12(&archive, &passphrase).map_err(|e| {
+            DriverError::new(
+                ErrorKind::Tls,
+                &format!(
+                    "Error loading PKCS#12 client certificate '{}': {}",
+                    client_cert, e
+                ),
+            )
+            .with_source(e)
+        }),
+        // native-tls only takes unencrypted PKCS#8 keys.
+        ClientIdentity::Pem { chain, key } => Identity::from_pkcs8(
+            &chain,
+            &key.private_key_to_pem_pkcs8().map_err(openssl_error)?,
+        )
+        .map_err(|e| {
+            DriverError::new(
+                ErrorKind::Tls,
+                &format!("Error loading client certificate '{}': {}", client_cert, e),
+            )
+            .with_source(e)
+        }),
+    }
+}
+
+fn load_ca_file(ca_file: &str) -> Result<Vec<Certificate>, DriverError> {
+    let stack = X509::stack_from_pem(&read_ca_file(ca_file)?).map_err(openssl_error)?;
+    if stack.is_empty() {
+        return Err(no_ca_certificate(ca_file));
+    }
+    to_certificates(stack, ca_file)
+}
+
+// Files without PEM certificates are skipped, as OpenSSL does.
+fn load_ca_dir(ca_dir: &str) -> Result<Vec<Certificate>, DriverError> {
+    let stack: Vec<X509> = read_ca_dir(ca_dir)?
+        .iter()
+        .filter_map(|contents| X509::stack_from_pem(contents).ok())
+        .flatten()
+        .collect();
+    if stack.is_empty() {
+        return Err(no_ca_dir_certificate(ca_dir));
+    }
+    to_certificates(stack, ca_dir)
+}
+
+fn to_certificates(stack: Vec<X509>, name: &str) -> Result<Vec<Certificate>, DriverError> {
+    let mut certificates = Vec::with_capacity(stack.len());
+    for x509 in stack {
+        let certificate =
+            Certificate::from_der(&x509.to_der().map_err(openssl_error)?).map_err(|e| {
+                DriverError::new(
+                    ErrorKind::Tls,
+                    &format!("Invalid certificate in '{}': {}", name, e),
+                )
+                .with_source(e)
+            })?;
+        certificates.push(certificate);
+    }
+    Ok(certificates)
+}
+
+fn openssl_error(err: ErrorStack) -> DriverError {
+    DriverError::new(ErrorKind::Tls, &err.to_string()).with_source(err)
+}
+
+impl<S: Transport> Transport for TlsStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
+    }
+
+    // A failed close_notify is no reason to leave the socket open.
+    fn shutdown(&mut self) -> io::Result<()> {
+        let _ = TlsStream::shutdown(self);
+        self.get_mut().shutdown()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.get_ref().is_closed()
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// TLS with rustls, its certificate verification done by webpki.
+
+// Siodb
+use crate::siodb::errors::{DriverError, ErrorKind};
+use crate::siodb::keys::backend::{pem_blocks, private_key_pkcs8_der, sign_challenge, PrivateKey};
+use crate::siodb::tls::{
+    check_pins, client_identity, handshake_failed, handshake_timed_out, no_ca_certificate,
+    no_ca_dir_certificate, read_ca_dir, read_ca_file, server_name, ClientIdentity, SslMode,
+    TlsOptions, TlsVersion,
+};
+#[cfg(feature = "tokio-rustls")]
+use crate::siodb::transport::AsyncStream;
+use crate::siodb::Transport;
+
+// Standard
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+// TLS
+use p12_keystore::KeyStore;
+use pkcs8::{DecodePrivateKey, EncodePublicKey};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, SubjectPublicKeyInfoDer,
+    UnixTime,
+};
+use rustls::sign::{CertifiedKey, Signer, SigningKey, SingleCertAndKey};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, InconsistentKeys,
+    RootCertStore, SignatureAlgorithm, SignatureScheme, StreamOwned,
+};
+use sha2::{Digest, Sha256};
+
+pub fn connect(
+    options: &TlsOptions,
+    host: &str,
+    port: u16,
+    stream: TcpStream,
+) -> Result<Box<dyn Transport>, DriverError> {
+    let config = build_config(options)?;
+    let connection = ClientConnection::new(Arc::new(config), dns_name(options, host)?)
+        .map_err(|e| handshake_failed(host, port, &e).with_source(e))?;
+
+    // Complete the handshake now, for its errors to be told apart from the session's.
+    let mut stream = StreamOwned::new(connection, stream);
+    while stream.conn.is_handshaking() {
+        stream
+            .conn
+            .complete_io(&mut stream.sock)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    handshake_timed_out(host, port)
+                }
+                _ => handshake_failed(host, port, &e).with_source(e),
+            })?;
+    }
+
+    check_pins(options, host, port, fingerprint(&stream.conn))?;
+    Ok(Box::new(stream))
+}
+
+/// Run the TLS handshake of an async connection over `stream`.
+#[cfg(feature = "tokio-rustls")]
+pub async fn connect_async(
+    options: &TlsOptions,
+    host: &str,
+    port: u16,
+    stream: tokio::net::TcpStream,
+) -> Result<Box<dyn AsyncStream>, DriverError> {
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(build_config(options)?));
+    let stream = connector
+        .connect(dns_name(options, host)?, stream)
+        .await
+        .map_err(|e| handshake_failed(host, port, &e).with_source(e))?;
+
+    check_pins(options, host, port, fingerprint(stream.get_ref().1))?;
+    Ok(Box::new(stream))
+}
+
+fn dns_name(options: &TlsOptions, host: &str) -> Result<ServerName<'static>, DriverError> {
+    let name = server_name(options, host);
+    Ok(ServerName::try_from(name)
+        .map_err(|e| {
+            DriverError::new(
+                ErrorKind::Tls,
+                &format!("Invalid TLS server name '{}': {}", name, e),
+            )
+        })?
+        .to_owned())
+}
+
+// SHA-256 of the server certificate.
+fn fingerprint(connection: &ClientConnection) -> Option<[u8; 32]> {
+    connection
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .map(|certificate| Sha256::digest(certificate).into())
+}
+
+fn build_config(options: &TlsOptions) -> Result<ClientConfig, DriverError> {
+    let provider = Arc::new(ring::default_provider());
+    let versions = match options.min_version {
+        Some(TlsVersion::Tls13) => &[&rustls::version::TLS13][..],
+        Some(TlsVersion::Tls12) | None => rustls::DEFAULT_VERSIONS,
+    };
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(versions)
+        .map_err(tls_error)?;
+
+    let roots = Arc::new(root_store(options)?);
+    let builder = match options.sslmode {
+        SslMode::Require => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider.clone()))),
+        SslMode::VerifyCa | SslMode::VerifyFull | SslMode::Disable => {
+            let verifier = WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+                .build()
+                .map_err(|e| {
+                    DriverError::new(
+                        ErrorKind::Tls,
+                        &format!("Unable to create TLS connector: {}", e),
+                    )
+                    .with_source(e)
+                })?;
+            if options.sslmode == SslMode::VerifyCa {
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(AnyHostName(verifier)))
+            } else {
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(verifier)
+            }
+        }
+    };
+
+    let client_cert = options.client_cert.as_deref().unwrap_or_default();
+    match client_identity(options)? {
+        None => Ok(builder.with_no_client_auth()),
+        Some(identity) => {
+            let (chain, key) = load_identity(identity, client_cert)?;
+            let certified_key = certified_key(chain, key, &provider).map_err(|e| {
+                DriverError::new(
+                    ErrorKind::Tls,
+                    &format!("Error loading client certificate '{}': {}", client_cert, e),
+                )
+                .with_source(e)
+            })?;
+            Ok(builder.with_client_cert_resolver(Arc::new(SingleCertAndKey::from(certified_key))))
+        }
+    }
+}
+
+// The system's trust store, and the certificates of ca_file and ca_dir.
+fn root_store(options: &TlsOptions) -> Result<RootCertStore, DriverError> {
+    let mut roots = RootCertStore::empty();
+    // Unreadable system certificates only leave fewer roots to trust.
+    let native = rustls_native_certs::load_native_certs();
+    roots.add_parsable_certificates(native.certs);
+
+    if let Some(ca_file) = &options.ca_file {
+        let certificates = pem_certificates(&read_ca_file(ca_file)?, ca_file)?;
+        if certificates.is_empty() {
+            return Err(no_ca_certificate(ca_file));
+        }
+        for certificate in certificates {
+            roots.add(certificate).map_err(|e| {
+                DriverError::new(
+                    ErrorKind::Tls,
+                    &format!("Invalid certificate in '{}': {}", ca_file, e),
+                )
+                .with_source(e)
+            })?;
+        }
+    }
+    if let Some(ca_dir) = &options.ca_dir {
+        // Files without PEM certificates are skipped, as OpenSSL does.
+        let mut certificates = Vec::new();
+        for contents in read_ca_dir(ca_dir)? {
+            certificates.extend(pem_certificates(&contents, ca_dir).unwrap_or_default());
+        }
+        let (added, _) = roots.add_parsable_certificates(certificates);
+        if added == 0 {
+            return Err(no_ca_dir_certificate(ca_dir));
+        }
+    }
+    Ok(roots)
+}
+
+// The client certificate chain with its key, checked to match as rustls does.
+fn certified_key(
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, rustls::Error> {
+    let signing_key = match provider.key_provider.load_private_key(key.clone_key()) {
+        Ok(signing_key) => signing_key,
+        Err(e) => match p521::SecretKey::from_pkcs8_der(key.secret_der()) {
+            Ok(key) => Arc::new(P521Key::new(key)?),
+            Err(_) => return Err(e),
+        },
+    };
+    let certified_key = CertifiedKey::new(chain, signing_key);
+    match certified_key.keys_match() {
+        Ok(()) | Err(rustls::Error::InconsistentKeys(InconsistentKeys::Unknown)) => {
+            Ok(certified_key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// ring cannot sign with P-521 keys, so the handshake is signed with the p521 crate.
+#[derive(Clone)]
+struct P521Key {
+    key: p521::SecretKey,
+    spki: Vec<u8>,
+}
+
+impl P521Key {
+    fn new(key: p521::SecretKey) -> Result<P521Key, rustls::Error> {
+        let spki = key
+            .public_key()
+            .to_public_key_der()
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+        Ok(P521Key {
+            key,
+            spki: spki.as_bytes().to_vec(),
+        })
+    }
+}
+
+impl fmt::Debug for P521Key {
+    // The private key must never end up in a trace.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "P521Key")
+    }
+}
+
+impl SigningKey for P521Key {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        if offered.contains(&SignatureScheme::ECDSA_NISTP521_SHA512) {
+            Some(Box::new(self.clone()))
+        } else {
+            None
+        }
+    }
+
+    fn public_key(&self) -> Option<SubjectPublicKeyInfoDer<'_>> {
+        Some(SubjectPublicKeyInfoDer::from(self.spki.as_slice()))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ECDSA
+    }
+}
+
+impl Signer for P521Key {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        sign_challenge(&PrivateKey::P521(self.key.clone()), message)
+            .map_err(|e| rustls::Error::General(e.to_string()))
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        SignatureScheme::ECDSA_NISTP521_SHA512
+    }
+}
+
+fn load_identity(
+    identity: ClientIdentity,
+    client_cert: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), DriverError> {
+    let error = |reason: &dyn std::fmt::Display| {
+        DriverError::new(
+            ErrorKind::Tls,
+            &format!(
+                "Error loading client certificate '{}': {}",
+                client_cert, reason
+            ),
+        )
+    };
+    match identity {
+        ClientIdentity::Pkcs12 {
+            archive,
+            passphrase,
+        } => {
+            let store = KeyStore::from_pkcs12(&archive, &passphrase).map_err(|e| {
+                DriverError::new(
+                    ErrorKind::Tls,
+                    &format!(
+                        "Error loading PKCS#12 client certificate '{}': {}",
+                        client_cert, e
+                    ),
+                )
+            })?;
+            let (_, chain) = store
+                .private_key_chain()
+                .ok_or_else(|| error(&"no private key in the PKCS#12 archive"))?;
+            let certificates = chain
+                .chain()
+                .iter()
+                .map(|certificate| CertificateDer::from(certificate.as_der().to_vec()))
+                .collect();
+            let key = PrivatePkcs8KeyDer::from(chain.key().to_vec());
+            Ok((certificates, PrivateKeyDer::Pkcs8(key)))
+        }
+        ClientIdentity::Pem { chain, key } => {
+            let certificates = pem_certificates(&chain, client_cert)?;
+            if certificates.is_empty() {
+                return Err(error(&"no PEM certificate found"));
+            }
+            let key = private_key_pkcs8_der(&key).map_err(|e| error(&e))?;
+            Ok((
+                certificates,
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key)),
+            ))
+        }
+    }
+}
+
+fn pem_certificates(
+    contents: &[u8],
+    name: &str,
+) -> Result<Vec<CertificateDer<'static>>, DriverError> {
+    let blocks = pem_blocks(contents).map_err(|e| {
+        DriverError::new(
+            ErrorKind::Tls,
+            &format!("Error reading certificates of '{}': {}", name, e),
+        )
+    })?;
+    Ok(blocks
+        .into_iter()
+        .filter(|block| block.label == "CERTIFICATE")
+        .map(|block| CertificateDer::from(block.der))
+        .collect())
+}
+
+fn tls_error(err: rustls::Error) -> DriverError {
+    DriverError::new(ErrorKind::Tls, &err.to_string()).with_source(err)
+}
+
+// sslmode=require: any certificate, but the handshake must still be signed by its key.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// sslmode=verify-ca: the certificate must chain to a trusted CA, for any host name.
+#[derive(Debug)]
+struct AnyHostName(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for AnyHostName {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+impl<S: Transport> Transport for StreamOwned<ClientConnection, S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    // A failed close_notify is no reason to leave the socket open.
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        while self.conn.wants_write() {
+            if self.conn.write_tls(&mut self.sock).is_err() {
+                break;
+            }
+        }
+        let _ = self.sock.flush();
+        self.sock.shutdown()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sock.is_closed()
+    }
+}
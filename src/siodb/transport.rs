@@ -0,0 +1,259 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Siodb
+use crate::siodb::errors::{io_error_kind, DriverError};
+use crate::siodb::{Scheme, SiodbConnOptions};
+
+// Standard
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+// Sockets
+use socket2::{Domain, SockAddr, SockRef, Socket, TcpKeepalive, Type};
+
+// Async
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The byte stream a connection talks to Siodb over.
+///
+/// The built-in schemes use TCP, TLS and Unix sockets. Implement it to hand
+/// `SiodbConn::new_with_transport` any other stream, e.g. a socket-activated
+/// file descriptor or a tunnel. Streams without timeouts or shutdown can keep
+/// the default methods.
+pub trait Transport: Read + Write + Send {
+    /// Set the time to wait for data. `None` waits forever.
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Set the time to wait for the peer to accept data. `None` waits forever.
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Shut both directions of the stream down.
+    fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the peer closed the stream, checked without blocking between
+    /// statements. Streams that cannot tell return `false`.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn is_closed(&self) -> bool {
+        peer_closed(SockRef::from(self))
+    }
+}
+
+impl Transport for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn is_closed(&self) -> bool {
+        peer_closed(SockRef::from(self))
+    }
+}
+
+// Between statements Siodb sends nothing: a readable socket with no data
+// to read, or in error, was closed by the peer.
+fn peer_closed(socket: SockRef) -> bool {
+    if socket.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let result = socket.peek(&mut [MaybeUninit::uninit()]);
+    if socket.set_nonblocking(false).is_err() {
+        return true;
+    }
+    match result {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => !matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+        ),
+    }
+}
+
+/// A plain `Read + Write` stream, without timeouts nor shutdown.
+pub struct StreamTransport<S>(pub S);
+
+impl<S: Read> Read for StreamTransport<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<S: Write> Write for StreamTransport<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<S: Read + Write + Send> Transport for StreamTransport<S> {}
+
+/// The byte stream an async connection talks to Siodb over.
+#[cfg(feature = "tokio")]
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for S {}
+
+/// A socket connected to Siodb, before TLS.
+pub enum Connected {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+// Connect to the host, or to the socket path of a Unix socket connection, and
+// set the socket options of TCP connections.
+pub fn connect_socket(
+    options: &SiodbConnOptions,
+    host: &str,
+    port: u16,
+) -> Result<Connected, DriverError> {
+    let connect_timeout = options.timeouts.connect;
+    if options.scheme == Scheme::Unix {
+        return Ok(Connected::Unix(
+            connect_unix(host, connect_timeout).map_err(|e| unix_connect_error(host, e))?,
+        ));
+    }
+    let stream = match &options.proxy {
+        Some(proxy) => proxy.connect(host, port, connect_timeout),
+        None => connect_tcp(host, port, connect_timeout),
+    }
+    .map_err(|e| connect_error(options, host, port, e))?;
+    configure_tcp(&stream, options.keepalive, options.nodelay)
+        .map_err(|e| socket_options_error(host, port, e))?;
+    Ok(Connected::Tcp(stream))
+}
+
+pub fn unix_connect_error(path: &str, e: io::Error) -> DriverError {
+    DriverError::new(
+        io_error_kind(&e),
+        &format!("Cannot connect to socket '{}': {}", path, e),
+    )
+    .with_source(e)
+}
+
+pub fn connect_error(
+    options: &SiodbConnOptions,
+    host: &str,
+    port: u16,
+    e: io::Error,
+) -> DriverError {
+    let through = match &options.proxy {
+        Some(proxy) => format!(" through proxy '{}'", proxy),
+        None => String::new(),
+    };
+    DriverError::new(
+        io_error_kind(&e),
+        &format!("Cannot connect to '{}:{}'{}: {}", host, port, through, e),
+    )
+    .with_source(e)
+}
+
+pub fn socket_options_error(host: &str, port: u16, e: io::Error) -> DriverError {
+    DriverError::new(
+        io_error_kind(&e),
+        &format!(
+            "Cannot set the socket options of '{}:{}': {}",
+            host, port, e
+        ),
+    )
+    .with_source(e)
+}
+
+// Try every address of the host in turn, as TcpStream::connect does.
+pub fn connect_tcp(host: &str, port: u16, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect((host, port)),
+    };
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+// The standard library has no connect timeout for Unix sockets.
+pub fn connect_unix(path: &str, timeout: Option<Duration>) -> io::Result<UnixStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return UnixStream::connect(path),
+    };
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    socket.connect_timeout(&SockAddr::unix(path)?, timeout)?;
+    Ok(UnixStream::from(OwnedFd::from(socket)))
+}
+
+// Send keepalive probes after `keepalive` of silence, then every `keepalive`
+// where the system allows it, and set TCP_NODELAY, on blocking and async sockets.
+pub fn configure_tcp<S: AsFd>(
+    stream: &S,
+    keepalive: Option<Duration>,
+    nodelay: bool,
+) -> io::Result<()> {
+    let socket = SockRef::from(stream);
+    if let Some(keepalive) = keepalive {
+        let params = TcpKeepalive::new().with_time(keepalive);
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "netbsd",
+        ))]
+        let params = params.with_interval(keepalive);
+        socket.set_tcp_keepalive(&params)?;
+    }
+    if nodelay {
+        socket.set_nodelay(true)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::{key_path, socket_path, FakeServer};
+
+use siodb::SiodbConn;
+
+use openssl::bn::BigNumContext;
+use openssl::ec::PointConversionForm;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::sign::{Signer, Verifier};
+
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+const CHALLENGE: &[u8] = b"siodb-agent-challenge";
+
+fn load_key(name: &str) -> PKey<Private> {
+    PKey::private_key_from_pem(&fs::read(key_path(name)).unwrap()).unwrap()
+}
+
+fn put_string(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn put_mpint(buf: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.first().is_some_and(|byte| byte & 0x80 != 0) {
+        let mut padded = vec![0u8];
+        padded.extend_from_slice(bytes);
+        put_string(buf, &padded);
+    } else {
+        put_string(buf, bytes);
+    }
+}
+
+fn key_blob(key: &PKey<Private>) -> Vec<u8> {
+    let mut blob = Vec::new();
+    match key.id() {
+        Id::ED25519 => {
+            put_string(&mut blob, b"ssh-ed25519");
+            put_string(&mut blob, &key.raw_public_key().unwrap());
+        }
+        Id::EC => {
+            let ec_key = key.ec_key().unwrap();
+            let mut ctx = BigNumContext::new().unwrap();
+            put_string(&mut blob, b"ecdsa-sha2-nistp256");
+            put_string(&mut blob, b"nistp256");
+            put_string(
+                &mut blob,
+                &ec_key
+                    .public_key()
+                    .to_bytes(ec_key.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)
+                    .unwrap(),
+            );
+        }
+        Id::RSA => {
+            let rsa = key.rsa().unwrap();
+            put_string(&mut blob, b"ssh-rsa");
+            put_mpint(&mut blob, &rsa.e().to_vec());
+            put_mpint(&mut blob, &rsa.n().to_vec());
+        }
+        id => panic!("unexpected key type {:?}", id),
+    }
+    blob
+}
+
+fn ssh_signature(key: &PKey<Private>, data: &[u8], flags: u32) -> Vec<u8> {
+    let mut blob = Vec::new();
+    match key.id() {
+        Id::ED25519 => {
+            let mut signer = Signer::new_without_digest(key).unwrap();
+            put_string(&mut blob, b"ssh-ed25519");
+            put_string(&mut blob, &signer.sign_oneshot_to_vec(data).unwrap());
+        }
+        Id::EC => {
+            let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+            let der = signer.sign_oneshot_to_vec(data).unwrap();
+            let signature = EcdsaSig::from_der(&der).unwrap();
+            let mut rs = Vec::new();
+            put_mpint(&mut rs, &signature.r().to_vec());
+            put_mpint(&mut rs, &signature.s().to_vec());
+            put_string(&mut blob, b"ecdsa-sha2-nistp256");
+            put_string(&mut blob, &rs);
+        }
+        Id::RSA => {
+            assert_eq!(flags, 4, "RSA keys must be asked for rsa-sha2-512");
+            let mut signer = Signer::new(MessageDigest::sha512(), key).unwrap();
+            put_string(&mut blob, b"rsa-sha2-512");
+            put_string(&mut blob, &signer.sign_oneshot_to_vec(data).unwrap());
+        }
+        id => panic!("unexpected key type {:?}", id),
+    }
+    blob
+}
+
+/// A stand-in ssh-agent holding `keys`, serving one client.
+fn start_agent(keys: Vec<PKey<Private>>) -> PathBuf {
+    let path = socket_path();
+    let listener = UnixListener::bind(&path).unwrap();
+    let agent_path = path.clone();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        loop {
+            let mut len = [0u8; 4];
+            if stream.read_exact(&mut len).is_err() {
+                break;
+            }
+            let mut request = vec![0u8; u32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut request).unwrap();
+
+            let mut response = Vec::new();
+            match request[0] {
+                11 => {
+                    response.push(12);
+                    response.extend_from_slice(&(keys.len() as u32).to_be_bytes());
+                    for key in &keys {
+                        put_string(&mut response, &key_blob(key));
+                        put_string(&mut response, b"stand-in");
+                    }
+                }
+                13 => {
+                    let mut rest = &request[1..];
+                    let mut read_string = || {
+                        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+                        let (string, tail) = rest[4..].split_at(len as usize);
+                        rest = tail;
+                        string.to_vec()
+                    };
+                    let blob = read_string();
+                    let data = read_string();
+                    let flags = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+                    match keys.iter().find(|key| key_blob(key) == blob) {
+                        Some(key) => {
+                            response.push(14);
+                            put_string(&mut response, &ssh_signature(key, &data, flags));
+                        }
+                        None => response.push(5),
+                    }
+                }
+                _ => response.push(5),
+            }
+            let mut frame = (response.len() as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(&response);
+            stream.write_all(&frame).unwrap();
+        }
+        let _ = fs::remove_file(&agent_path);
+    });
+    path
+}
+
+fn signature_through_agent(agent_path: &Path, identity_file: &str) -> Vec<u8> {
+    let server = FakeServer::start(|mut session| session.accept(CHALLENGE));
+    let uri = server.uri(&format!(
+        "agent=true&agent_socket={}&identity_file={}",
+        agent_path.display(),
+        identity_file
+    ));
+    SiodbConn::new(&uri).expect("authentication through ssh-agent");
+    server.join()
+}
+
+fn verify(key: &PKey<Private>, digest: Option<MessageDigest>, signature: &[u8]) -> bool {
+    let mut verifier = match digest {
+        Some(digest) => Verifier::new(digest, key).unwrap(),
+        None => Verifier::new_without_digest(key).unwrap(),
+    };
+    verifier.verify_oneshot(signature, CHALLENGE).unwrap()
+}
+
+#[test]
+fn agent_signs_with_its_first_key() {
+    let agent = start_agent(vec![load_key("ed25519.pem")]);
+    let signature = signature_through_agent(&agent, "/nonexistent/id_ed25519");
+    assert!(verify(&load_key("ed25519.pem"), None, &signature));
+}
+
+#[test]
+fn agent_rsa_signature_uses_sha512() {
+    let agent = start_agent(vec![load_key("rsa.pem")]);
+    let signature = signature_through_agent(&agent, "/nonexistent/id_rsa");
+    assert!(verify(
+        &load_key("rsa.pem"),
+        Some(MessageDigest::sha512()),
+        &signature
+    ));
+}
+
+#[test]
+fn agent_ecdsa_signature_is_converted_to_der() {
+    let agent = start_agent(vec![load_key("ecdsa_p256.pem")]);
+    let signature = signature_through_agent(&agent, "/nonexistent/id_ecdsa");
+    assert!(verify(
+        &load_key("ecdsa_p256.pem"),
+        Some(MessageDigest::sha256()),
+        &signature
+    ));
+}
+
+#[test]
+fn agent_key_is_selected_by_public_key_file() {
+    let ed25519 = load_key("ed25519.pem");
+    let agent = start_agent(vec![load_key("ecdsa_p256.pem"), load_key("ed25519.pem")]);
+    let public_key_file = socket_path().with_extension("pub");
+    fs::write(
+        &public_key_file,
+        format!(
+            "ssh-ed25519 {} test\n",
+            openssl::base64::encode_block(&key_blob(&ed25519))
+        ),
+    )
+    .unwrap();
+    let signature = signature_through_agent(&agent, public_key_file.to_str().unwrap());
+    fs::remove_file(&public_key_file).unwrap();
+    assert!(verify(&ed25519, None, &signature));
+}
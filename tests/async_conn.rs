@@ -0,0 +1,225 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+use common::ClientProtocol::ServerResponse;
+use common::ColumnDataType::ColumnDataType;
+use common::CommonTypes::{ColumnDescription, StatusMessage};
+use common::{answer, identity, FakeServer, Session};
+
+use siodb::{AsyncSiodbConn, ErrorKind};
+
+use futures_util::StreamExt;
+
+use std::time::Duration;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+// Answer a command with a dataset of UINT64 `ids`, and return its text.
+fn answer_ids(session: &mut Session, ids: &[u8]) -> String {
+    let command = session.read_command();
+    let mut column = ColumnDescription::new();
+    column.set_name("ID".to_string());
+    column.set_field_type(ColumnDataType::COLUMN_DATA_TYPE_UINT64);
+    let mut response = ServerResponse::new();
+    response.set_request_id(command.get_request_id());
+    response.set_column_description(vec![column].into());
+    session.write_response(&response);
+    let mut bytes = Vec::new();
+    for id in ids {
+        bytes.extend(&[1, *id]);
+    }
+    bytes.push(0);
+    session.write_raw(&bytes);
+    command.get_text().to_string()
+}
+
+#[tokio::test]
+async fn query_streams_rows() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let commands = vec![answer_ids(&mut session, &[1, 2, 3]), answer(&mut session)];
+        session.wait_for_close();
+        commands
+    });
+    // Rows and messages of a few bytes take several reads.
+    let uri = server.uri(&format!("read_buffer_size=3&{}", identity()));
+    let mut conn = AsyncSiodbConn::connect(&uri).await.unwrap();
+    let rows = conn.query("SELECT ID FROM T1".to_string()).await.unwrap();
+    let ids: Vec<String> = rows
+        .map(|row| row.unwrap()[0].as_ref().unwrap().to_string())
+        .collect()
+        .await;
+    assert_eq!(ids, ["1", "2", "3"]);
+    assert_eq!(conn.get_row_count(), 3);
+
+    conn.execute("DELETE FROM T1".to_string()).await.unwrap();
+    conn.close().await.unwrap();
+    assert_eq!(server.join(), ["SELECT ID FROM T1", "DELETE FROM T1"]);
+}
+
+#[tokio::test]
+async fn rows_of_a_dropped_stream_are_skipped() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let commands = vec![
+            answer_ids(&mut session, &[1, 2, 3]),
+            answer_ids(&mut session, &[4]),
+        ];
+        session.wait_for_close();
+        commands
+    });
+    let mut conn = AsyncSiodbConn::connect(&server.uri(&identity()))
+        .await
+        .unwrap();
+    let mut rows = conn.query("SELECT ID FROM T1".to_string()).await.unwrap();
+    rows.next().await.unwrap().unwrap();
+    drop(rows);
+
+    let mut rows = conn.query("SELECT ID FROM T2".to_string()).await.unwrap();
+    let row = rows.next().await.unwrap().unwrap();
+    assert_eq!(row[0].as_ref().unwrap().to_string(), "4");
+    assert!(rows.next().await.is_none());
+    assert!(!conn.is_broken());
+    drop(conn);
+    assert_eq!(server.join(), ["SELECT ID FROM T1", "SELECT ID FROM T2"]);
+}
+
+#[tokio::test]
+async fn dropped_statement_breaks_the_connection() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        // Read the command and never answer.
+        let text = session.read_command().get_text().to_string();
+        session.wait_for_close();
+        text
+    });
+    let mut conn = AsyncSiodbConn::connect(&server.uri(&identity()))
+        .await
+        .unwrap();
+    let execute = conn.execute("DELETE FROM T1".to_string());
+    assert!(tokio::time::timeout(Duration::from_millis(100), execute)
+        .await
+        .is_err());
+    assert!(conn.is_broken());
+
+    let error = conn
+        .execute("DELETE FROM T2".to_string())
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+    drop(conn);
+    assert_eq!(server.join(), "DELETE FROM T1");
+}
+
+#[tokio::test]
+async fn server_errors_and_read_timeouts() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        let mut message = StatusMessage::new();
+        message.set_status_code(2);
+        message.set_text("Table 'T1' does not exist".to_string());
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id());
+        response.set_message(vec![message].into());
+        session.write_response(&response);
+        // Read the next command and never answer.
+        session.read_command();
+        session.wait_for_close();
+    });
+    let uri = server.uri(&format!("read_timeout=0.1&{}", identity()));
+    let mut conn = AsyncSiodbConn::connect(&uri).await.unwrap();
+    let error = conn
+        .execute("DELETE FROM T1".to_string())
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Server);
+    assert_eq!(error.server_messages()[0].status_code, 2);
+    assert!(!conn.is_broken());
+
+    let error = conn
+        .execute("DELETE FROM T2".to_string())
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    assert!(conn.is_broken());
+    drop(conn);
+    server.join();
+}
+
+#[tokio::test]
+async fn wrong_request_id_breaks_the_connection() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id() + 1);
+        session.write_response(&response);
+        session.wait_for_close();
+    });
+    let mut conn = AsyncSiodbConn::connect(&server.uri(&identity()))
+        .await
+        .unwrap();
+    let error = conn
+        .execute("DELETE FROM T1".to_string())
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Protocol);
+    assert!(conn.is_broken());
+    drop(conn);
+    server.join();
+}
+
+#[tokio::test]
+async fn results_of_a_script_are_read_in_turn() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        for response_id in 0..3 {
+            let mut response = ServerResponse::new();
+            response.set_request_id(command.get_request_id());
+            response.set_response_id(response_id);
+            response.set_response_count(3);
+            response.set_affected_row_count(u64::from(response_id) + 1);
+            response.set_has_affected_row_count(true);
+            session.write_response(&response);
+        }
+        let commands = vec![command.get_text().to_string(), answer(&mut session)];
+        session.wait_for_close();
+        commands
+    });
+    let mut conn = AsyncSiodbConn::connect(&server.uri(&identity()))
+        .await
+        .unwrap();
+    let script = "DELETE FROM T1; DELETE FROM T2; DELETE FROM T3";
+    conn.execute(script.to_string()).await.unwrap();
+    assert_eq!(conn.get_affected_row_count(), 1);
+    assert!(conn.next_result().await.unwrap());
+    assert_eq!(conn.get_affected_row_count(), 2);
+
+    // The last result is skipped.
+    conn.execute("DELETE FROM T4".to_string()).await.unwrap();
+    assert!(!conn.next_result().await.unwrap());
+    drop(conn);
+    assert_eq!(server.join(), [script, "DELETE FROM T4"]);
+}
+
+#[tokio::test]
+async fn ipv6_host() {
+    let server = FakeServer::start_tcp_on("[::1]", |mut session: Session| {
+        session.accept(CHALLENGE);
+        let text = answer(&mut session);
+        session.wait_for_close();
+        text
+    });
+    let uri = format!("siodb://root@{}?{}", server.address(), identity());
+    let mut conn = AsyncSiodbConn::connect(&uri).await.unwrap();
+    assert_eq!(conn.host(), "::1");
+    conn.execute("DELETE FROM T1".to_string()).await.unwrap();
+    drop(conn);
+    assert_eq!(server.join(), "DELETE FROM T1");
+}
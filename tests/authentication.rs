@@ -0,0 +1,241 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::{key_path, FakeServer};
+
+use siodb::{ErrorKind, SiodbConn};
+
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+
+use std::env;
+use std::fs;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+fn signature_for(query: &str) -> Vec<u8> {
+    let server = FakeServer::start(|mut session| session.accept(CHALLENGE));
+    SiodbConn::new(&server.uri(query)).expect("authentication");
+    server.join()
+}
+
+fn load_key(name: &str) -> PKey<Private> {
+    PKey::private_key_from_pem(&fs::read(key_path(name)).unwrap()).unwrap()
+}
+
+fn verify(name: &str, digest: Option<MessageDigest>, signature: &[u8]) -> bool {
+    let key = load_key(name);
+    let mut verifier = match digest {
+        Some(digest) => Verifier::new(digest, &key).unwrap(),
+        None => Verifier::new_without_digest(&key).unwrap(),
+    };
+    verifier.verify_oneshot(signature, CHALLENGE).unwrap()
+}
+
+#[test]
+fn rsa_key_signs_with_sha512() {
+    let signature = signature_for(&format!("identity_file={}", key_path("rsa.pem")));
+    assert!(verify("rsa.pem", Some(MessageDigest::sha512()), &signature));
+}
+
+#[test]
+fn ed25519_key_signs_challenge() {
+    let signature = signature_for(&format!("identity_file={}", key_path("ed25519.pem")));
+    assert!(verify("ed25519.pem", None, &signature));
+    assert_eq!(
+        openssl::base64::encode_block(&signature),
+        ED25519_SIGNATURE,
+        "Ed25519 signatures are deterministic"
+    );
+}
+
+#[test]
+fn ecdsa_p256_key_signs_with_sha256() {
+    let signature = signature_for(&format!("identity_file={}", key_path("ecdsa_p256.pem")));
+    assert!(verify(
+        "ecdsa_p256.pem",
+        Some(MessageDigest::sha256()),
+        &signature
+    ));
+}
+
+#[test]
+fn ecdsa_p384_key_signs_with_sha384() {
+    let signature = signature_for(&format!("identity_file={}", key_path("ecdsa_p384.pem")));
+    assert!(verify(
+        "ecdsa_p384.pem",
+        Some(MessageDigest::sha384()),
+        &signature
+    ));
+}
+
+#[test]
+fn ecdsa_p521_key_signs_with_sha512() {
+    let signature = signature_for(&format!("identity_file={}", key_path("ecdsa_p521.pem")));
+    assert!(verify(
+        "ecdsa_p521.pem",
+        Some(MessageDigest::sha512()),
+        &signature
+    ));
+}
+
+#[test]
+fn encrypted_key_with_uri_passphrase() {
+    let signature = signature_for(&format!(
+        "identity_file={}&identity_passphrase=siodb",
+        key_path("ed25519_encrypted.pem")
+    ));
+    assert!(verify("ed25519.pem", None, &signature));
+}
+
+#[test]
+fn legacy_encrypted_pem_key() {
+    // "Proc-Type: 4,ENCRYPTED" with AES-128-CBC, as `openssl ec -aes-128-cbc` writes.
+    let signature = signature_for(&format!(
+        "identity_file={}&identity_passphrase=siodb",
+        key_path("ecdsa_p256_encrypted.pem")
+    ));
+    assert!(verify(
+        "ecdsa_p256.pem",
+        Some(MessageDigest::sha256()),
+        &signature
+    ));
+}
+
+#[test]
+fn encrypted_key_with_passphrase_callback() {
+    let server = FakeServer::start(|mut session| session.accept(CHALLENGE));
+    let uri = server.uri(&format!(
+        "identity_file={}",
+        key_path("ed25519_encrypted.pem")
+    ));
+    SiodbConn::new_with_passphrase_callback(&uri, |_| Some("siodb".to_string()))
+        .expect("authentication");
+    assert!(verify("ed25519.pem", None, &server.join()));
+}
+
+#[test]
+fn encrypted_key_with_environment_passphrase() {
+    let query = format!("identity_file={}", key_path("ed25519_encrypted.pem"));
+    let server = FakeServer::start(|mut session| {
+        let _ = session.begin(CHALLENGE);
+    });
+    let error = SiodbConn::new(&server.uri(&query)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Auth);
+    assert!(error.to_string().contains("passphrase"), "{}", error);
+
+    env::set_var("SIODB_IDENTITY_PASSPHRASE", "siodb");
+    let signature = signature_for(&query);
+    env::remove_var("SIODB_IDENTITY_PASSPHRASE");
+    assert!(verify("ed25519.pem", None, &signature));
+}
+
+#[test]
+fn wrong_passphrase_is_an_error() {
+    let server = FakeServer::start(|mut session| {
+        let _ = session.begin(CHALLENGE);
+    });
+    let uri = server.uri(&format!(
+        "identity_file={}&identity_passphrase=wrong",
+        key_path("ed25519_encrypted.pem")
+    ));
+    let error = SiodbConn::new(&uri).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Auth);
+    assert!(error.to_string().contains("passphrase"), "{}", error);
+
+    let server = FakeServer::start(|mut session| {
+        let _ = session.begin(CHALLENGE);
+    });
+    let uri = server.uri(&format!(
+        "identity_file={}",
+        key_path("ecdsa_p256_encrypted.pem")
+    ));
+    let error =
+        SiodbConn::new_with_passphrase_callback(&uri, |_| Some("wrong".to_string())).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Auth);
+    assert!(error.to_string().contains("passphrase"), "{}", error);
+}
+
+// Signature of CHALLENGE by tests/keys/ed25519.pem, computed with
+// `openssl pkeyutl -sign -rawin`.
+const ED25519_SIGNATURE: &str =
+    "SxmmvJuE2T/tSapKRH7kuj0yRQMNP7MZBSopIS8nxgxai6TeXZHNqfguN1ZI3ZbY2Bj8YxY1wlQzl+vBq5nkBA==";
+
+#[test]
+fn openssh_ed25519_key() {
+    let signature = signature_for(&format!("identity_file={}", key_path("openssh_ed25519")));
+    let key = openssh_public_key("openssh_ed25519.pub");
+    let mut verifier = Verifier::new_without_digest(&key).unwrap();
+    assert!(verifier.verify_oneshot(&signature, CHALLENGE).unwrap());
+}
+
+#[test]
+fn openssh_encrypted_rsa_key() {
+    let signature = signature_for(&format!(
+        "identity_file={}&identity_passphrase=siodb",
+        key_path("openssh_rsa_encrypted")
+    ));
+    let key = openssh_public_key("openssh_rsa_encrypted.pub");
+    let mut verifier = Verifier::new(MessageDigest::sha512(), &key).unwrap();
+    assert!(verifier.verify_oneshot(&signature, CHALLENGE).unwrap());
+}
+
+#[test]
+fn openssh_encrypted_ecdsa_key() {
+    let signature = signature_for(&format!(
+        "identity_file={}&identity_passphrase=siodb",
+        key_path("openssh_ecdsa_p384_encrypted")
+    ));
+    let key = openssh_public_key("openssh_ecdsa_p384_encrypted.pub");
+    let mut verifier = Verifier::new(MessageDigest::sha384(), &key).unwrap();
+    assert!(verifier.verify_oneshot(&signature, CHALLENGE).unwrap());
+}
+
+#[test]
+fn openssh_wrong_passphrase_is_an_error() {
+    let server = FakeServer::start(|mut session| {
+        let _ = session.begin(CHALLENGE);
+    });
+    let uri = server.uri(&format!(
+        "identity_file={}&identity_passphrase=wrong",
+        key_path("openssh_rsa_encrypted")
+    ));
+    let error = SiodbConn::new(&uri).unwrap_err();
+    assert!(error.to_string().contains("Wrong passphrase"), "{}", error);
+}
+
+// Public key written by ssh-keygen, to check the driver's parser against it.
+fn openssh_public_key(name: &str) -> PKey<Public> {
+    let line = fs::read_to_string(key_path(name)).unwrap();
+    let blob = openssl::base64::decode_block(line.split_whitespace().nth(1).unwrap()).unwrap();
+    let mut blob = &blob[..];
+    let mut read_string = || {
+        let len = u32::from_be_bytes([blob[0], blob[1], blob[2], blob[3]]) as usize;
+        let (string, rest) = blob[4..].split_at(len);
+        blob = rest;
+        string.to_vec()
+    };
+    match &read_string()[..] {
+        b"ssh-ed25519" => PKey::public_key_from_raw_bytes(&read_string(), Id::ED25519).unwrap(),
+        b"ssh-rsa" => {
+            let e = BigNum::from_slice(&read_string()).unwrap();
+            let n = BigNum::from_slice(&read_string()).unwrap();
+            PKey::from_rsa(Rsa::from_public_components(n, e).unwrap()).unwrap()
+        }
+        b"ecdsa-sha2-nistp384" => {
+            let _curve = read_string();
+            let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+            let mut ctx = BigNumContext::new().unwrap();
+            let point = EcPoint::from_bytes(&group, &read_string(), &mut ctx).unwrap();
+            PKey::from_ec_key(EcKey::from_public_key(&group, &point).unwrap()).unwrap()
+        }
+        key_type => panic!("unexpected key type {:?}", key_type),
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::ClientProtocol::{Command, ServerResponse};
+use common::CommonTypes::StatusMessage;
+use common::{identity, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn};
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+// Answer a command with its number of affected rows, or an error for a DROP.
+fn respond(session: &mut Session, command: &Command, affected_row_count: u64) {
+    let mut response = ServerResponse::new();
+    response.set_request_id(command.get_request_id());
+    if command.get_text().starts_with("DROP") {
+        let mut message = StatusMessage::new();
+        message.set_status_code(3);
+        message.set_text("Table does not exist.".to_string());
+        response.set_message(vec![message].into());
+    } else {
+        response.set_has_affected_row_count(true);
+        response.set_affected_row_count(affected_row_count);
+    }
+    session.write_response(&response);
+}
+
+#[test]
+fn commands_are_sent_before_the_responses_are_read() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        // Every command arrives before the first response is sent.
+        let commands: Vec<Command> = (0..3).map(|_| session.read_command()).collect();
+        for (index, command) in commands.iter().enumerate() {
+            respond(&mut session, command, index as u64 + 1);
+        }
+        let command = session.read_command();
+        respond(&mut session, &command, 0);
+        commands
+            .iter()
+            .map(|command| command.get_request_id())
+            .collect::<Vec<u64>>()
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    let results = conn
+        .execute_batch(vec![
+            "INSERT INTO T1 VALUES (1)".to_string(),
+            "DROP TABLE T2".to_string(),
+            "DELETE FROM T3".to_string(),
+        ])
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(*results[0].as_ref().unwrap(), 1);
+    let error = results[1].as_ref().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Server);
+    assert_eq!(error.server_messages()[0].status_code, 3);
+    assert_eq!(*results[2].as_ref().unwrap(), 3);
+
+    conn.execute("DELETE FROM T4".to_string()).unwrap();
+    assert_eq!(server.join(), [1, 2, 3]);
+}
+
+#[test]
+fn large_batches_keep_a_window_of_commands() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let mut count = 0;
+        while let Some(command) = session.next_command() {
+            respond(&mut session, &command, 1);
+            count += 1;
+        }
+        count
+    });
+    // Small buffers make the driver send its commands as it writes them.
+    let uri = server.uri(&format!("write_buffer_size=16&{}", identity()));
+    let mut conn = SiodbConn::new(&uri).unwrap();
+    let statements = (0..1000)
+        .map(|index| format!("INSERT INTO T1 VALUES ({})", index))
+        .collect();
+    let results = conn.execute_batch(statements).unwrap();
+    assert!(results.iter().all(|result| *result.as_ref().unwrap() == 1));
+    assert!(conn.execute_batch(Vec::new()).unwrap().is_empty());
+    conn.close().unwrap();
+    assert_eq!(server.join(), 1000);
+}
+
+#[test]
+fn response_to_another_command_stops_the_batch() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let first = session.read_command();
+        let second = session.read_command();
+        respond(&mut session, &second, 1);
+        session.wait_for_close();
+        first.get_request_id()
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    let results = conn
+        .execute_batch(vec![
+            "DELETE FROM T1".to_string(),
+            "DELETE FROM T2".to_string(),
+        ])
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap_err().kind(), ErrorKind::Protocol);
+    let error = conn.execute("DELETE FROM T3".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+    drop(conn);
+    assert_eq!(server.join(), 1);
+}
+
+#[test]
+fn results_read_before_an_io_error_are_returned() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let commands: Vec<Command> = (0..3).map(|_| session.read_command()).collect();
+        respond(&mut session, &commands[0], 2);
+        // Close the connection before the other responses.
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    let results = conn
+        .execute_batch(vec![
+            "DELETE FROM T1".to_string(),
+            "DELETE FROM T2".to_string(),
+            "DELETE FROM T3".to_string(),
+        ])
+        .unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(*results[0].as_ref().unwrap(), 2);
+    assert_eq!(results[1].as_ref().unwrap_err().kind(), ErrorKind::Io);
+    server.join();
+
+    let error = conn.execute_batch(Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+}
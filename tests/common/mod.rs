@@ -0,0 +1,307 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// A stand-in Siodb server speaking the client protocol over a Unix socket or TCP.
+
+#![allow(dead_code)]
+
+// Protobuf (generated code shared with the driver)
+#[allow(warnings)]
+#[path = "../../src/siodb/ClientProtocol.rs"]
+pub mod ClientProtocol;
+#[allow(warnings)]
+#[path = "../../src/siodb/ColumnDataType.rs"]
+pub mod ColumnDataType;
+#[allow(warnings)]
+#[path = "../../src/siodb/CommonTypes.rs"]
+pub mod CommonTypes;
+
+use ClientProtocol::{
+    BeginSessionRequest, BeginSessionResponse, ClientAuthenticationRequest,
+    ClientAuthenticationResponse, Command, ServerResponse,
+};
+
+// Standard
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+static SOCKET_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Path of a test file shipped in `tests/keys`.
+pub fn key_path(name: &str) -> String {
+    format!("{}/tests/keys/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Query string authenticating with the RSA test key.
+pub fn identity() -> String {
+    format!("identity_file={}", key_path("rsa.pem"))
+}
+
+/// A fresh socket path in the temporary directory.
+pub fn socket_path() -> PathBuf {
+    env::temp_dir().join(format!(
+        "siodb-test-{}-{}.sock",
+        process::id(),
+        SOCKET_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ))
+}
+
+/// A server accepting one client connection and running a handler on it.
+pub struct FakeServer<T> {
+    pub socket_path: PathBuf,
+    pub host: &'static str,
+    pub port: u16,
+    handle: Option<JoinHandle<T>>,
+}
+
+impl<T: Send + 'static> FakeServer<T> {
+    pub fn start<F>(handler: F) -> FakeServer<T>
+    where
+        F: FnOnce(Session) -> T + Send + 'static,
+    {
+        let socket_path = socket_path();
+        let listener = UnixListener::bind(&socket_path).expect("bind fake server socket");
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept client");
+            handler(Session::new(stream))
+        });
+        FakeServer {
+            socket_path,
+            host: "",
+            port: 0,
+            handle: Some(handle),
+        }
+    }
+
+    /// A server listening on a TCP port of the loopback interface.
+    pub fn start_tcp<F>(handler: F) -> FakeServer<T>
+    where
+        F: FnOnce(Session) -> T + Send + 'static,
+    {
+        FakeServer::start_tcp_on("127.0.0.1", handler)
+    }
+
+    /// A server listening on a TCP port of `host`, an address as URIs write it.
+    pub fn start_tcp_on<F>(host: &'static str, handler: F) -> FakeServer<T>
+    where
+        F: FnOnce(Session) -> T + Send + 'static,
+    {
+        let listener = TcpListener::bind(format!("{}:0", host)).expect("bind fake server port");
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept client");
+            handler(Session::from_tcp(stream))
+        });
+        FakeServer {
+            socket_path: PathBuf::new(),
+            host,
+            port,
+            handle: Some(handle),
+        }
+    }
+
+    /// URI of the server with the given query string.
+    pub fn uri(&self, query: &str) -> String {
+        format!("siodbu:{}?{}", self.socket_path.display(), query)
+    }
+
+    /// `host:port` of a TCP server.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Wait for the handler and return its result.
+    pub fn join(mut self) -> T {
+        self.handle
+            .take()
+            .unwrap()
+            .join()
+            .expect("fake server handler panicked")
+    }
+}
+
+impl<R: Send + 'static> FakeServer<Vec<R>> {
+    /// A server accepting `sessions` client connections, each handled in its own
+    /// thread, the handler called with the number of the connection.
+    pub fn start_sessions<F>(sessions: usize, handler: F) -> FakeServer<Vec<R>>
+    where
+        F: Fn(usize, Session) -> R + Send + Sync + 'static,
+    {
+        let socket_path = socket_path();
+        let listener = UnixListener::bind(&socket_path).expect("bind fake server socket");
+        let handler = Arc::new(handler);
+        let handle = thread::spawn(move || {
+            let handles: Vec<JoinHandle<R>> = (0..sessions)
+                .map(|index| {
+                    let (stream, _) = listener.accept().expect("accept client");
+                    let handler = handler.clone();
+                    thread::spawn(move || handler(index, Session::new(stream)))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("fake server handler panicked"))
+                .collect()
+        });
+        FakeServer {
+            socket_path,
+            host: "",
+            port: 0,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl<T> Drop for FakeServer<T> {
+    fn drop(&mut self) {
+        if self.port == 0 {
+            let _ = fs::remove_file(&self.socket_path);
+        }
+    }
+}
+
+/// The server side of a client connection.
+pub struct Session {
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+}
+
+impl Session {
+    pub fn new(stream: UnixStream) -> Session {
+        Session {
+            writer: Box::new(stream.try_clone().expect("clone stream")),
+            reader: BufReader::new(Box::new(stream)),
+        }
+    }
+
+    pub fn from_tcp(stream: TcpStream) -> Session {
+        Session {
+            writer: Box::new(stream.try_clone().expect("clone stream")),
+            reader: BufReader::new(Box::new(stream)),
+        }
+    }
+
+    /// A session over a stream that cannot be cloned, e.g. a TLS stream.
+    pub fn from_stream<S: Read + Write + Send + 'static>(stream: S) -> Session {
+        let stream = SharedStream(Arc::new(Mutex::new(stream)));
+        Session {
+            writer: Box::new(stream.clone()),
+            reader: BufReader::new(Box::new(stream)),
+        }
+    }
+
+    pub fn read_message<M: protobuf::Message>(&mut self, message_type: u32) -> M {
+        let mut input = protobuf::CodedInputStream::from_buffered_reader(&mut self.reader);
+        assert_eq!(
+            input.read_raw_varint32().expect("message type"),
+            message_type
+        );
+        input.read_message().expect("message")
+    }
+
+    pub fn write_message(&mut self, message_type: u32, message: &dyn protobuf::Message) {
+        let mut output = protobuf::CodedOutputStream::new(&mut self.writer);
+        output.write_raw_varint32(message_type).unwrap();
+        output.write_raw_varint32(message.compute_size()).unwrap();
+        message.write_to_with_cached_sizes(&mut output).unwrap();
+        output.flush().unwrap();
+    }
+
+    pub fn write_raw(&mut self, bytes: &[u8]) {
+        self.writer.write_all(bytes).unwrap();
+    }
+
+    /// Run the session handshake with `challenge` and return the user name and
+    /// the signature sent by the client.
+    pub fn begin(&mut self, challenge: &[u8]) -> (String, Vec<u8>) {
+        let request: BeginSessionRequest = self.read_message(5);
+        let mut response = BeginSessionResponse::new();
+        response.set_session_started(true);
+        response.set_challenge(challenge.to_vec());
+        self.write_message(6, &response);
+        let authentication: ClientAuthenticationRequest = self.read_message(7);
+        (
+            request.get_user_name().to_string(),
+            authentication.get_signature().to_vec(),
+        )
+    }
+
+    /// Answer the client authentication request.
+    pub fn authenticate(&mut self, authenticated: bool) {
+        let mut response = ClientAuthenticationResponse::new();
+        response.set_authenticated(authenticated);
+        response.set_session_id("test-session".to_string());
+        self.write_message(8, &response);
+    }
+
+    /// Accept any signature and return it.
+    pub fn accept(&mut self, challenge: &[u8]) -> Vec<u8> {
+        let (_, signature) = self.begin(challenge);
+        self.authenticate(true);
+        signature
+    }
+
+    pub fn read_command(&mut self) -> Command {
+        self.read_message(1)
+    }
+
+    /// The next command, or `None` once the client closed the connection.
+    pub fn next_command(&mut self) -> Option<Command> {
+        match self.reader.fill_buf() {
+            Ok(buffer) if !buffer.is_empty() => Some(self.read_command()),
+            _ => None,
+        }
+    }
+
+    pub fn write_response(&mut self, response: &ServerResponse) {
+        self.write_message(2, response);
+    }
+
+    /// Discard whatever the client sends until it closes the connection.
+    pub fn wait_for_close(&mut self) {
+        let _ = io::copy(&mut self.reader, &mut io::sink());
+    }
+}
+
+/// Answer the next command without a dataset and return its text.
+pub fn answer(session: &mut Session) -> String {
+    let command = session.read_command();
+    let mut response = ServerResponse::new();
+    response.set_request_id(command.get_request_id());
+    session.write_response(&response);
+    command.get_text().to_string()
+}
+
+// Reads and writes of a session never overlap, so they can share the stream.
+struct SharedStream<S>(Arc<Mutex<S>>);
+
+impl<S> Clone for SharedStream<S> {
+    fn clone(&self) -> Self {
+        SharedStream(self.0.clone())
+    }
+}
+
+impl<S: Read> Read for SharedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl<S: Write> Write for SharedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::{key_path, FakeServer};
+
+use siodb::{
+    CommandCredentials, CredentialProvider, DriverError, EnvCredentials, FileCredentials,
+    PemCredentials, SiodbConn,
+};
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Verifier;
+
+use std::fs;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+// Authenticate with `credentials` and return the user name and signature the server got.
+fn authenticate<P: CredentialProvider + 'static>(credentials: P) -> (String, Vec<u8>) {
+    let server = FakeServer::start(|mut session| {
+        let begin = session.begin(CHALLENGE);
+        session.authenticate(true);
+        begin
+    });
+    SiodbConn::new_with_credentials(&server.uri("agent=false"), credentials)
+        .expect("authentication");
+    server.join()
+}
+
+fn verify_rsa(signature: &[u8]) -> bool {
+    let key = PKey::private_key_from_pem(&fs::read(key_path("rsa.pem")).unwrap()).unwrap();
+    let mut verifier = Verifier::new(MessageDigest::sha512(), &key).unwrap();
+    verifier.verify_oneshot(signature, CHALLENGE).unwrap()
+}
+
+#[test]
+fn file_credentials_sign_challenge() {
+    let (user, signature) = authenticate(FileCredentials::new("alice", &key_path("rsa.pem")));
+    assert_eq!(user, "alice");
+    assert!(verify_rsa(&signature));
+}
+
+#[test]
+fn pem_credentials_sign_with_encrypted_key() {
+    let pem = fs::read(key_path("ed25519_encrypted.pem")).unwrap();
+    let (user, signature) = authenticate(PemCredentials::new("bob", &pem).with_passphrase("siodb"));
+    assert_eq!(user, "bob");
+    let key = PKey::private_key_from_pem(&fs::read(key_path("ed25519.pem")).unwrap()).unwrap();
+    let mut verifier = Verifier::new_without_digest(&key).unwrap();
+    assert!(verifier.verify_oneshot(&signature, CHALLENGE).unwrap());
+}
+
+#[test]
+fn env_credentials_read_variable() {
+    let variable = "SIODB_TEST_CREDENTIALS_KEY";
+    std::env::set_var(variable, fs::read_to_string(key_path("rsa.pem")).unwrap());
+    let (_, signature) = authenticate(EnvCredentials::new("root", variable));
+    assert!(verify_rsa(&signature));
+}
+
+#[test]
+fn command_credentials_read_stdout() {
+    let credentials = CommandCredentials::new("root", "cat").arg(&key_path("rsa.pem"));
+    let (_, signature) = authenticate(credentials);
+    assert!(verify_rsa(&signature));
+}
+
+#[test]
+fn failing_command_is_an_error() {
+    let credentials = CommandCredentials::new("root", "false");
+    assert!(credentials.sign_challenge(CHALLENGE).is_err());
+}
+
+struct FixedSignature;
+
+impl CredentialProvider for FixedSignature {
+    fn user_name(&self) -> Result<String, DriverError> {
+        Ok("custom".to_string())
+    }
+
+    fn sign_challenge(&self, challenge: &[u8]) -> Result<Vec<u8>, DriverError> {
+        Ok(challenge.iter().rev().cloned().collect())
+    }
+}
+
+#[test]
+fn custom_provider_is_used() {
+    let (user, signature) = authenticate(FixedSignature);
+    assert_eq!(user, "custom");
+    assert_eq!(
+        signature,
+        CHALLENGE.iter().rev().cloned().collect::<Vec<u8>>()
+    );
+}
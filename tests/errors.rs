@@ -0,0 +1,103 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::ClientProtocol::{ClientAuthenticationResponse, ServerResponse};
+use common::CommonTypes::StatusMessage;
+use common::{key_path, FakeServer};
+
+use siodb::{ErrorKind, ServerMessage, SiodbConn};
+
+use std::error::Error;
+use std::io;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+fn status_message(status_code: i32, text: &str) -> StatusMessage {
+    let mut message = StatusMessage::new();
+    message.set_status_code(status_code);
+    message.set_text(text.to_string());
+    message
+}
+
+#[test]
+fn server_error_keeps_status_messages() {
+    let server = FakeServer::start(|mut session| {
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id());
+        response
+            .mut_message()
+            .push(status_message(5, "Table 'T' does not exist."));
+        response
+            .mut_message()
+            .push(status_message(7, "Statement failed."));
+        session.write_response(&response);
+    });
+    let mut conn =
+        SiodbConn::new(&server.uri(&format!("identity_file={}", key_path("rsa.pem")))).unwrap();
+
+    let error = conn.execute("SELECT * FROM T".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Server);
+    assert_eq!(
+        error.server_messages(),
+        &[
+            ServerMessage {
+                status_code: 5,
+                text: "Table 'T' does not exist.".to_string()
+            },
+            ServerMessage {
+                status_code: 7,
+                text: "Statement failed.".to_string()
+            },
+        ]
+    );
+    server.join();
+}
+
+#[test]
+fn refused_authentication_is_an_auth_error() {
+    let server = FakeServer::start(|mut session| {
+        session.begin(CHALLENGE);
+        let mut response = ClientAuthenticationResponse::new();
+        response.set_authenticated(false);
+        response.set_message(status_message(11, "User access denied."));
+        session.write_message(8, &response);
+    });
+    let error =
+        SiodbConn::new(&server.uri(&format!("identity_file={}", key_path("rsa.pem")))).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Auth);
+    assert_eq!(error.server_messages()[0].status_code, 11);
+    assert_eq!(error.server_messages()[0].text, "User access denied.");
+    server.join();
+}
+
+#[test]
+fn missing_identity_file_chains_io_error() {
+    let server = FakeServer::start(|mut session| {
+        session.read_message::<common::ClientProtocol::BeginSessionRequest>(5);
+        let mut response = common::ClientProtocol::BeginSessionResponse::new();
+        response.set_session_started(true);
+        response.set_challenge(CHALLENGE.to_vec());
+        session.write_message(6, &response);
+    });
+    let error =
+        SiodbConn::new(&server.uri("identity_file=/nonexistent/id_rsa&agent=false")).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Auth);
+    let source = error.source().expect("source error");
+    assert_eq!(
+        source.downcast_ref::<io::Error>().unwrap().kind(),
+        io::ErrorKind::NotFound
+    );
+    server.join();
+}
+
+#[test]
+fn wrong_option_is_a_config_error() {
+    let error = SiodbConn::new("siodb://localhost?colour=blue").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+    let error = SiodbConn::new("siodb://localhost?sslmode=maybe").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+}
@@ -0,0 +1,171 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::{identity, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn, SiodbConnOptions};
+
+use std::net::TcpListener;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+// An address nothing listens on.
+fn refused_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("127.0.0.1:{}", listener.local_addr().unwrap().port())
+}
+
+fn accepting_server() -> FakeServer<()> {
+    FakeServer::start_tcp(|mut session: Session| {
+        session.accept(CHALLENGE);
+        session.wait_for_close();
+    })
+}
+
+#[test]
+fn wrong_multi_host_uris_are_config_errors() {
+    for uri in &[
+        "siodb://root@127.0.0.1:1,127.0.0.1:2/?target=primary",
+        "siodb://root@127.0.0.1:1,:2/",
+        "siodb://root@127.0.0.1:1,127.0.0.1:port/",
+        "siodb://root@127.0.0.1:1,user@127.0.0.1:2/",
+    ] {
+        let error = SiodbConn::new(uri).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config, "{}", uri);
+    }
+}
+
+#[test]
+fn fails_over_to_the_next_host() {
+    let server = accepting_server();
+    let conn = SiodbConn::new(&format!(
+        "siodb://root@{},{}/?target=any&{}",
+        refused_address(),
+        server.address(),
+        identity()
+    ))
+    .unwrap();
+    assert_eq!(conn.host(), "127.0.0.1");
+    assert_eq!(conn.port(), server.port);
+    drop(conn);
+    server.join();
+}
+
+#[test]
+fn first_host_is_used_when_it_accepts() {
+    let server = accepting_server();
+    let conn = SiodbConn::new(&format!(
+        "siodb://root@{},{}/?{}",
+        server.address(),
+        refused_address(),
+        identity()
+    ))
+    .unwrap();
+    assert_eq!(conn.port(), server.port);
+    drop(conn);
+    server.join();
+}
+
+#[test]
+fn fails_over_when_a_host_refuses_the_user() {
+    let refusing = FakeServer::start_tcp(|mut session: Session| {
+        session.begin(CHALLENGE);
+        session.authenticate(false);
+    });
+    let server = accepting_server();
+    let conn = SiodbConn::new(&format!(
+        "siodb://root@{},{}/?{}",
+        refusing.address(),
+        server.address(),
+        identity()
+    ))
+    .unwrap();
+    assert_eq!(conn.port(), server.port);
+    refusing.join();
+    drop(conn);
+    server.join();
+}
+
+#[test]
+fn random_target_reaches_the_host_that_is_up() {
+    let server = accepting_server();
+    let conn = SiodbConn::new(&format!(
+        "siodb://root@{},{},{}/?target=random&{}",
+        refused_address(),
+        server.address(),
+        refused_address(),
+        identity()
+    ))
+    .unwrap();
+    assert_eq!(conn.port(), server.port);
+    drop(conn);
+    server.join();
+}
+
+#[test]
+fn error_lists_every_host_when_all_fail() {
+    let first = refused_address();
+    let second = refused_address();
+    let error = SiodbConn::new(&format!(
+        "siodb://root@{},{}/?{}",
+        first,
+        second,
+        identity()
+    ))
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+    let message = error.to_string();
+    assert!(message.contains(&first), "{}", message);
+    assert!(message.contains(&second), "{}", message);
+}
+
+#[test]
+fn configuration_errors_stop_the_failover() {
+    let server = FakeServer::start_tcp(|mut session: Session| session.wait_for_close());
+    let error = SiodbConn::new(&format!(
+        "siodbs://root@{},{}/?sslmode=require&client_key=/tmp/key.pem&{}",
+        server.address(),
+        refused_address(),
+        identity()
+    ))
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+    assert!(!error.to_string().contains("any host"));
+    server.join();
+}
+
+#[test]
+fn ipv6_hosts() {
+    let server = FakeServer::start_tcp_on("[::1]", |mut session: Session| {
+        session.accept(CHALLENGE);
+        session.wait_for_close();
+    });
+    let conn =
+        SiodbConn::new(&format!("siodb://root@{}?{}", server.address(), identity())).unwrap();
+    assert_eq!(conn.host(), "::1");
+    assert_eq!(conn.port(), server.port);
+    drop(conn);
+    server.join();
+
+    let server = FakeServer::start_tcp_on("[::1]", |mut session: Session| {
+        session.accept(CHALLENGE);
+        session.wait_for_close();
+    });
+    let conn = SiodbConn::new(&format!(
+        "siodb://root@{},{}/?connect_timeout=5&{}",
+        refused_address(),
+        server.address(),
+        identity()
+    ))
+    .unwrap();
+    assert_eq!(conn.host(), "::1");
+    drop(conn);
+    server.join();
+
+    let options = SiodbConnOptions::from_uri("siodb://root@[::1]:50000,[fe80::1]").unwrap();
+    assert_eq!(options.to_uri(), "siodb://root@[::1]:50000,[fe80::1]:50000");
+    let options = SiodbConnOptions::new().with_host("[::1]", 50000);
+    assert_eq!(options.to_uri(), "siodbs://root@[::1]:50000");
+}
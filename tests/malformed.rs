@@ -0,0 +1,204 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// Malformed input must come back as errors, never as panics.
+
+mod common;
+use common::ClientProtocol::{BeginSessionResponse, ServerResponse};
+use common::ColumnDataType::ColumnDataType;
+use common::CommonTypes::ColumnDescription;
+use common::{identity, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn};
+
+use std::net::TcpListener;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+fn column(name: &str, field_type: ColumnDataType) -> ColumnDescription {
+    let mut column = ColumnDescription::new();
+    column.set_name(name.to_string());
+    column.set_field_type(field_type);
+    column
+}
+
+// Authenticate, answer the first command with a dataset of `columns`, then send `rows` as is.
+fn serve_rows(columns: Vec<ColumnDescription>, rows: Vec<u8>) -> FakeServer<()> {
+    FakeServer::start(move |mut session: Session| {
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id());
+        response.set_column_description(columns.into());
+        session.write_response(&response);
+        session.write_raw(&rows);
+    })
+}
+
+fn next_error(columns: Vec<ColumnDescription>, rows: Vec<u8>) -> siodb::DriverError {
+    let server = serve_rows(columns, rows);
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.execute("SELECT * FROM T".to_string()).unwrap();
+    let error = conn.next().unwrap_err();
+    server.join();
+    error
+}
+
+#[test]
+fn malformed_uris_are_errors() {
+    for uri in &[
+        "",
+        "not a uri",
+        "siodbx://localhost",
+        "siodb://localhost?trace",
+        "siodb://localhost?sslmode=",
+        "siodb://localhost?pkcs11_slot=abc",
+        "siodb://localhost:99999",
+        "siodbu:/run/siodb.socket?sslmode=require",
+    ] {
+        assert!(SiodbConn::new(uri).is_err(), "URI '{}' should fail", uri);
+    }
+}
+
+#[test]
+fn refused_tcp_connection_is_an_io_error() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let error =
+        SiodbConn::new(&format!("siodb://root@127.0.0.1:{}?{}", port, identity())).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+}
+
+#[test]
+fn missing_unix_socket_is_an_io_error() {
+    let error = SiodbConn::new(&format!(
+        "siodbu:{}?{}",
+        common::socket_path().display(),
+        identity()
+    ))
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+}
+
+#[test]
+fn connection_closed_during_handshake_is_an_error() {
+    let server = FakeServer::start(drop);
+    assert!(SiodbConn::new(&server.uri(&identity())).is_err());
+    server.join();
+}
+
+#[test]
+fn garbage_instead_of_message_is_an_error() {
+    let server = FakeServer::start(|mut session| {
+        session.read_message::<common::ClientProtocol::BeginSessionRequest>(5);
+        session.write_raw(&[6, 0xff, 0xff, 0xff, 0xff, 0x0f, 1, 2, 3]);
+    });
+    assert!(SiodbConn::new(&server.uri(&identity())).is_err());
+    server.join();
+}
+
+#[test]
+fn wrong_message_type_is_a_protocol_error() {
+    let server = FakeServer::start(|mut session| {
+        session.read_message::<common::ClientProtocol::BeginSessionRequest>(5);
+        let mut response = BeginSessionResponse::new();
+        response.set_session_started(true);
+        session.write_message(8, &response);
+    });
+    let error = SiodbConn::new(&server.uri(&identity())).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Protocol);
+    server.join();
+}
+
+#[test]
+fn request_ids_increase_and_are_checked() {
+    let server = FakeServer::start(|mut session| {
+        session.accept(CHALLENGE);
+        let mut request_ids = Vec::new();
+        for answer_id in [1, 2, 7] {
+            let command = session.read_command();
+            request_ids.push(command.get_request_id());
+            let mut response = ServerResponse::new();
+            response.set_request_id(answer_id);
+            session.write_response(&response);
+        }
+        request_ids
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.execute("DELETE FROM T1".to_string()).unwrap();
+    conn.execute("DELETE FROM T2".to_string()).unwrap();
+    let error = conn.execute("DELETE FROM T3".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Protocol);
+    assert_eq!(
+        error.to_string(),
+        "execute | wrong request id received from Siodb: 7. Expected: 3."
+    );
+    assert_eq!(server.join(), [1, 2, 3]);
+
+    // The connection is out of step with Siodb.
+    let error = conn.execute("DELETE FROM T4".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+}
+
+#[test]
+fn invalid_utf8_text_is_a_decode_error() {
+    let error = next_error(
+        vec![column("NAME", ColumnDataType::COLUMN_DATA_TYPE_TEXT)],
+        vec![3, 2, 0xc3, 0x28],
+    );
+    assert_eq!(error.kind(), ErrorKind::Decode);
+}
+
+#[test]
+fn invalid_timestamp_is_a_decode_error() {
+    // Day 32 of month 14.
+    let error = next_error(
+        vec![column("TS", ColumnDataType::COLUMN_DATA_TYPE_TIMESTAMP)],
+        vec![4, 0xf0, 0x1b, 0x00, 0x00],
+    );
+    assert_eq!(error.kind(), ErrorKind::Decode);
+}
+
+#[test]
+fn truncated_row_is_an_error() {
+    let error = next_error(
+        vec![
+            column("ID", ColumnDataType::COLUMN_DATA_TYPE_UINT64),
+            column("NAME", ColumnDataType::COLUMN_DATA_TYPE_TEXT),
+        ],
+        vec![12, 1, 10, b'a', b'b'],
+    );
+    assert_eq!(error.kind(), ErrorKind::Io);
+}
+
+#[test]
+fn query_row_returns_errors() {
+    let server = serve_rows(
+        vec![column("NAME", ColumnDataType::COLUMN_DATA_TYPE_TEXT)],
+        vec![3, 2, 0xff, 0xfe],
+    );
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    assert!(conn.query_row("SELECT NAME FROM T".to_string()).is_err());
+    server.join();
+}
+
+#[test]
+fn misuse_of_a_connection_is_an_error() {
+    let server = FakeServer::start(|mut session| {
+        session.accept(CHALLENGE);
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    server.join();
+
+    assert_eq!(conn.next().unwrap_err().kind(), ErrorKind::Config);
+    assert!(conn.scan().is_empty());
+    assert_eq!(conn.get_row_count(), 0);
+    assert_eq!(conn.get_affected_row_count(), 0);
+    conn.close().unwrap();
+    assert!(conn.close().is_err());
+    assert!(conn.execute("SELECT 1".to_string()).is_err());
+}
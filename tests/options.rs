@@ -0,0 +1,150 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::{key_path, FakeServer, Session};
+
+use siodb::{
+    ErrorKind, PemCredentials, PinSource, Scheme, SiodbConn, SiodbConnOptions, SslMode, Target,
+    Timeouts,
+};
+
+use std::fs;
+use std::time::Duration;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+fn user_server() -> FakeServer<String> {
+    FakeServer::start(|mut session: Session| {
+        let (user, _) = session.begin(CHALLENGE);
+        session.authenticate(true);
+        session.wait_for_close();
+        user
+    })
+}
+
+#[test]
+fn builder_connects_through_unix_socket() {
+    let server = user_server();
+    let options = SiodbConnOptions::new()
+        .with_unix_socket(server.socket_path.to_str().unwrap())
+        .with_user("alice")
+        .with_identity_file(&key_path("rsa.pem"));
+    let conn = SiodbConn::new_with_options(&options).unwrap();
+    drop(conn);
+    assert_eq!(server.join(), "alice");
+}
+
+#[test]
+fn builder_connects_over_tcp() {
+    let server = FakeServer::start_tcp(|mut session: Session| {
+        let (user, _) = session.begin(CHALLENGE);
+        session.authenticate(true);
+        session.wait_for_close();
+        user
+    });
+    let options = SiodbConnOptions::new()
+        .with_scheme(Scheme::Tcp)
+        .with_host("127.0.0.1", server.port)
+        .with_user("bob")
+        .with_identity_file(&key_path("rsa.pem"))
+        .with_timeouts(Timeouts::new().with_connect_timeout(Duration::from_secs(5)));
+    let conn = SiodbConn::new_with_options(&options).unwrap();
+    assert_eq!(conn.port(), server.port);
+    drop(conn);
+    assert_eq!(server.join(), "bob");
+}
+
+#[test]
+fn builder_takes_credentials() {
+    let server = user_server();
+    let pem = fs::read(key_path("rsa.pem")).unwrap();
+    let options = SiodbConnOptions::new()
+        .with_unix_socket(server.socket_path.to_str().unwrap())
+        .with_credentials(PemCredentials::new("carol", &pem));
+    let conn = SiodbConn::new_with_options(&options).unwrap();
+    drop(conn);
+    assert_eq!(server.join(), "carol");
+}
+
+#[test]
+fn options_round_trip_through_uri() {
+    let options = SiodbConnOptions::new()
+        .with_host("db1", 50000)
+        .add_host("::1", 50001)
+        .with_target(Target::Random)
+        .with_user("app user")
+        .with_identity_file("/home/app/keys/id ed25519")
+        .with_identity_passphrase("s3cret&more")
+        .with_sslmode(SslMode::VerifyCa)
+        .with_ca_file("/etc/siodb/ca.pem")
+        .with_client_cert("/etc/siodb/client.p12")
+        .with_client_key_passphrase("p12-secret")
+        .with_pkcs11("/usr/lib/softhsm/libsofthsm2.so", "siodb key")
+        .with_pkcs11_slot(3)
+        .with_pkcs11_pin_source(PinSource::File("/run/pin".to_string()))
+        .with_timeouts(
+            Timeouts::new()
+                .with_connect_timeout(Duration::from_millis(300))
+                .with_read_timeout(Duration::from_secs(30)),
+        )
+        .with_trace(true);
+
+    let uri = options.to_uri();
+    assert!(
+        uri.starts_with("siodbs://app%20user@db1:50000,[::1]:50001?"),
+        "{}",
+        uri
+    );
+    assert!(uri.contains("&pkcs11_pin_source=file:/run/pin&"), "{}", uri);
+    let parsed = SiodbConnOptions::from_uri(&uri).unwrap();
+    assert_eq!(parsed.to_uri(), uri);
+    assert_eq!(uri.parse::<SiodbConnOptions>().unwrap().to_uri(), uri);
+}
+
+#[test]
+fn uris_round_trip_through_options() {
+    for uri in &[
+        "siodbs://root@localhost:50000",
+        "siodb://root@db1:50000,db2:50001?target=random",
+        "siodbs://root@10.0.0.1:50000?sslmode=require&write_timeout=0.25",
+        "siodbu:/run/siodb/siodb.socket?identity_file=/keys/id_rsa&user=app",
+    ] {
+        let options = SiodbConnOptions::from_uri(uri).unwrap();
+        assert_eq!(options.to_uri(), *uri);
+    }
+}
+
+#[test]
+fn printed_options_hide_secrets() {
+    let options = SiodbConnOptions::new()
+        .with_identity_passphrase("identity-secret")
+        .with_client_key_passphrase("client-secret")
+        .with_pkcs11("/lib/p11.so", "key")
+        .with_pkcs11_pin_source(PinSource::Value("1234".to_string()));
+    for printed in &[options.to_string(), format!("{:?}", options)] {
+        assert!(!printed.contains("identity-secret"), "{}", printed);
+        assert!(!printed.contains("client-secret"), "{}", printed);
+        assert!(!printed.contains("1234"), "{}", printed);
+        assert!(printed.contains("identity_passphrase=***"), "{}", printed);
+    }
+    assert_eq!(
+        PinSource::Env("SIODB_PIN".to_string()).to_string(),
+        "env:SIODB_PIN"
+    );
+    assert_eq!(PinSource::Value("1234".to_string()).to_string(), "***");
+}
+
+#[test]
+fn inconsistent_options_are_config_errors() {
+    let unix_tls = SiodbConnOptions::new()
+        .with_unix_socket("/tmp/siodb.sock")
+        .with_sslmode(SslMode::Require);
+    let slot_without_module = SiodbConnOptions::new().with_pkcs11_slot(1);
+    let unix_without_path = SiodbConnOptions::new().with_scheme(Scheme::Unix);
+    for options in &[unix_tls, slot_without_module, unix_without_path] {
+        let error = SiodbConn::new_with_options(options).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config, "{}", options);
+    }
+}
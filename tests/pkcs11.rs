@@ -0,0 +1,210 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+
+use siodb::SiodbConn;
+
+fn uri_error(query: &str) -> String {
+    SiodbConn::new(&format!("siodbu:/nonexistent.socket?{}", query))
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn pkcs11_options_require_module() {
+    assert_eq!(
+        uri_error("pkcs11_key_label=siodb&pkcs11_slot=1"),
+        "PKCS#11 options require pkcs11_module."
+    );
+    assert_eq!(
+        uri_error("pkcs11_module=/usr/lib/softhsm/libsofthsm2.so"),
+        "pkcs11_module requires pkcs11_key_label."
+    );
+}
+
+#[test]
+fn pin_source_is_env_or_file() {
+    assert!(uri_error("pkcs11_pin_source=1234").starts_with("Wrong pkcs11_pin_source"));
+}
+
+// Runs against SoftHSM (`apt install softhsm2`), or against the module of
+// SIODB_TEST_PKCS11_MODULE. The token is created in a temporary directory. It needs the
+// module, so it is ignored by default:
+//
+//     cargo test --features pkcs11 --test pkcs11 -- --ignored
+#[cfg(feature = "pkcs11")]
+mod softhsm {
+    use super::common::{self, FakeServer};
+
+    use siodb::SiodbConn;
+
+    use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11};
+    use cryptoki::mechanism::Mechanism;
+    use cryptoki::object::{Attribute, AttributeType, ObjectHandle};
+    use cryptoki::session::{Session, UserType};
+    use cryptoki::types::AuthPin;
+
+    use openssl::bn::{BigNum, BigNumContext};
+    use openssl::ec::{EcGroup, EcKey, EcPoint};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Public};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+    const USER_PIN: &str = "1234";
+    const MODULES: &[&str] = &[
+        "/usr/lib/softhsm/libsofthsm2.so",
+        "/usr/lib/x86_64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/local/lib/softhsm/libsofthsm2.so",
+    ];
+
+    fn find_module() -> Option<String> {
+        env::var("SIODB_TEST_PKCS11_MODULE").ok().or_else(|| {
+            MODULES
+                .iter()
+                .find(|module| Path::new(module).exists())
+                .map(|module| module.to_string())
+        })
+    }
+
+    // A fresh SoftHSM token holding an RSA and an ECDSA P-256 key.
+    fn init_token(module: &str) -> (Session, u64) {
+        let token_dir = common::socket_path().with_extension("tokens");
+        fs::create_dir_all(&token_dir).unwrap();
+        let config = token_dir.join("softhsm2.conf");
+        fs::write(
+            &config,
+            format!("directories.tokendir = {}\n", token_dir.display()),
+        )
+        .unwrap();
+        env::set_var("SOFTHSM2_CONF", &config);
+
+        let context = Pkcs11::new(module).unwrap();
+        context
+            .initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK))
+            .unwrap();
+        let slot = context.get_all_slots().unwrap()[0];
+        let so_pin = AuthPin::new("5678".into());
+        context.init_token(slot, &so_pin, "siodb").unwrap();
+        let slot = context.get_slots_with_initialized_token().unwrap()[0];
+
+        let session = context.open_rw_session(slot).unwrap();
+        session.login(UserType::So, Some(&so_pin)).unwrap();
+        session.init_pin(&AuthPin::new(USER_PIN.into())).unwrap();
+        session.logout().unwrap();
+        session
+            .login(UserType::User, Some(&AuthPin::new(USER_PIN.into())))
+            .unwrap();
+        (session, slot.id())
+    }
+
+    fn generate(
+        session: &Session,
+        mechanism: &Mechanism,
+        public: &[Attribute],
+        label: &str,
+    ) -> ObjectHandle {
+        let mut public = public.to_vec();
+        public.extend([Attribute::Token(true), Attribute::Verify(true)]);
+        let private = [
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Sign(true),
+            Attribute::Label(label.as_bytes().to_vec()),
+        ];
+        session
+            .generate_key_pair(mechanism, &public, &private)
+            .unwrap()
+            .0
+    }
+
+    fn signature_for(query: &str) -> Vec<u8> {
+        let server = FakeServer::start(|mut session| session.accept(CHALLENGE));
+        SiodbConn::new(&server.uri(query)).expect("authentication");
+        server.join()
+    }
+
+    fn verify(key: &PKey<Public>, digest: MessageDigest, signature: &[u8]) -> bool {
+        let mut verifier = Verifier::new(digest, key).unwrap();
+        verifier.verify_oneshot(signature, CHALLENGE).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs SoftHSM or SIODB_TEST_PKCS11_MODULE"]
+    fn token_key_signs_challenge() {
+        let module = find_module()
+            .expect("SoftHSM is not installed and SIODB_TEST_PKCS11_MODULE is not set");
+        let (session, slot) = init_token(&module);
+        env::set_var("SIODB_TEST_PKCS11_PIN", USER_PIN);
+
+        // RSA, in the first initialized token.
+        let rsa = generate(
+            &session,
+            &Mechanism::RsaPkcsKeyPairGen,
+            &[
+                Attribute::ModulusBits(2048.into()),
+                Attribute::PublicExponent(vec![0x01, 0x00, 0x01]),
+            ],
+            "siodb-rsa",
+        );
+        let attributes = session
+            .get_attributes(
+                rsa,
+                &[AttributeType::Modulus, AttributeType::PublicExponent],
+            )
+            .unwrap();
+        let rsa = match attributes.as_slice() {
+            [Attribute::Modulus(n), Attribute::PublicExponent(e)] => PKey::from_rsa(
+                Rsa::from_public_components(
+                    BigNum::from_slice(n).unwrap(),
+                    BigNum::from_slice(e).unwrap(),
+                )
+                .unwrap(),
+            )
+            .unwrap(),
+            _ => panic!("RSA public key attributes"),
+        };
+        let signature = signature_for(&format!(
+            "pkcs11_module={}&pkcs11_key_label=siodb-rsa&pkcs11_pin_source=env:SIODB_TEST_PKCS11_PIN",
+            module
+        ));
+        assert!(verify(&rsa, MessageDigest::sha512(), &signature));
+
+        // ECDSA P-256, in an explicit slot.
+        let p256_oid = vec![0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+        let ec = generate(
+            &session,
+            &Mechanism::EccKeyPairGen,
+            &[Attribute::EcParams(p256_oid)],
+            "siodb-ec",
+        );
+        let ec = match session
+            .get_attributes(ec, &[AttributeType::EcPoint])
+            .unwrap()
+            .as_slice()
+        {
+            // The point is wrapped in a DER OCTET STRING.
+            [Attribute::EcPoint(point)] => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+                let mut ctx = BigNumContext::new().unwrap();
+                let point = EcPoint::from_bytes(&group, &point[2..], &mut ctx).unwrap();
+                PKey::from_ec_key(EcKey::from_public_key(&group, &point).unwrap()).unwrap()
+            }
+            _ => panic!("EC public key attributes"),
+        };
+        let signature = signature_for(&format!(
+            "pkcs11_module={}&pkcs11_slot={}&pkcs11_key_label=siodb-ec&pkcs11_pin_source=env:SIODB_TEST_PKCS11_PIN",
+            module, slot
+        ));
+        assert!(verify(&ec, MessageDigest::sha256(), &signature));
+    }
+}
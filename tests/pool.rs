@@ -0,0 +1,228 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::ClientProtocol::ServerResponse;
+use common::ColumnDataType::ColumnDataType;
+use common::CommonTypes::ColumnDescription;
+use common::{identity, FakeServer, Session};
+
+use siodb::{ErrorKind, PoolOptions, SiodbPool};
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+fn respond(session: &mut Session, request_id: u64) {
+    let mut response = ServerResponse::new();
+    response.set_request_id(request_id);
+    session.write_response(&response);
+}
+
+// Answer every command without a dataset until the client closes the connection.
+fn serve(mut session: Session) -> Vec<String> {
+    session.accept(CHALLENGE);
+    let mut commands = Vec::new();
+    while let Some(command) = session.next_command() {
+        respond(&mut session, command.get_request_id());
+        commands.push(command.get_text().to_string());
+    }
+    commands
+}
+
+#[test]
+fn connections_are_reused_after_a_ping() {
+    let server = FakeServer::start_sessions(1, |_, session| serve(session));
+    let pool = SiodbPool::new(&server.uri(&identity()), PoolOptions::new()).unwrap();
+    assert_eq!(pool.size(), 0);
+    pool.get()
+        .unwrap()
+        .execute("DELETE FROM T1".to_string())
+        .unwrap();
+    assert_eq!((pool.size(), pool.idle_count()), (1, 1));
+    let mut conn = pool.get().unwrap();
+    assert_eq!(pool.idle_count(), 0);
+    conn.execute("DELETE FROM T2".to_string()).unwrap();
+    drop(conn);
+    drop(pool);
+    assert_eq!(
+        server.join(),
+        [vec!["DELETE FROM T1", "SELECT 1", "DELETE FROM T2"]]
+    );
+}
+
+#[test]
+fn checkout_times_out_when_all_connections_are_in_use() {
+    let server = FakeServer::start_sessions(1, |_, session| serve(session));
+    let options = PoolOptions::new()
+        .with_max_size(1)
+        .with_checkout_timeout(Duration::from_millis(100));
+    let pool = SiodbPool::new(&server.uri(&identity()), options).unwrap();
+    let conn = pool.get().unwrap();
+    let error = pool.get().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    drop(conn);
+    assert!(pool.get().is_ok());
+    drop(pool);
+    server.join();
+}
+
+#[test]
+fn waiting_callers_are_served_in_turn() {
+    let server = FakeServer::start_sessions(1, |_, session| serve(session));
+    let options = PoolOptions::new()
+        .with_max_size(1)
+        .with_checkout_timeout(Duration::from_secs(10));
+    let pool = SiodbPool::new(&server.uri(&identity()), options).unwrap();
+    let conn = pool.get().unwrap();
+    let (served, order) = mpsc::channel();
+    let mut waiters = Vec::new();
+    for name in &["first", "second", "third"] {
+        let pool = pool.clone();
+        let served = served.clone();
+        waiters.push(thread::spawn(move || {
+            let mut conn = pool.get().unwrap();
+            conn.execute(format!("DELETE FROM {}", name)).unwrap();
+            served.send(name.to_string()).unwrap();
+        }));
+        thread::sleep(Duration::from_millis(100));
+    }
+    drop(conn);
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+    let order: Vec<String> = order.try_iter().collect();
+    assert_eq!(order, ["first", "second", "third"]);
+    drop(pool);
+    server.join();
+}
+
+#[test]
+fn dirty_connections_are_not_reused() {
+    let server = FakeServer::start_sessions(2, |index, mut session: Session| {
+        if index == 1 {
+            return serve(session);
+        }
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        let mut column = ColumnDescription::new();
+        column.set_name("ID".to_string());
+        column.set_field_type(ColumnDataType::COLUMN_DATA_TYPE_UINT64);
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id());
+        response.set_column_description(vec![column].into());
+        session.write_response(&response);
+        session.write_raw(&[1, 1, 1, 2, 0]);
+        session.wait_for_close();
+        vec![command.get_text().to_string()]
+    });
+    let pool = SiodbPool::new(&server.uri(&identity()), PoolOptions::new()).unwrap();
+    let mut conn = pool.get().unwrap();
+    conn.query("SELECT ID FROM T1".to_string()).unwrap();
+    assert!(conn.next().unwrap());
+    drop(conn);
+    assert_eq!(pool.size(), 0);
+    pool.get()
+        .unwrap()
+        .execute("DELETE FROM T1".to_string())
+        .unwrap();
+    drop(pool);
+    assert_eq!(
+        server.join(),
+        [vec!["SELECT ID FROM T1"], vec!["DELETE FROM T1"]]
+    );
+}
+
+#[test]
+fn dead_idle_connections_are_replaced() {
+    let server = FakeServer::start_sessions(2, |index, mut session: Session| {
+        if index == 1 {
+            return serve(session);
+        }
+        // Close the connection after the first statement.
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        respond(&mut session, command.get_request_id());
+        vec![command.get_text().to_string()]
+    });
+    let pool = SiodbPool::new(&server.uri(&identity()), PoolOptions::new()).unwrap();
+    pool.get()
+        .unwrap()
+        .execute("DELETE FROM T1".to_string())
+        .unwrap();
+    // Let the server close the first connection.
+    thread::sleep(Duration::from_millis(100));
+    pool.get()
+        .unwrap()
+        .execute("DELETE FROM T2".to_string())
+        .unwrap();
+    assert_eq!(pool.size(), 1);
+    drop(pool);
+    assert_eq!(
+        server.join(),
+        [vec!["DELETE FROM T1"], vec!["DELETE FROM T2"]]
+    );
+}
+
+#[test]
+fn idle_connections_beyond_the_minimum_size_are_closed() {
+    let server = FakeServer::start_sessions(2, |_, session| serve(session));
+    let options = PoolOptions::new()
+        .with_min_size(1)
+        .with_max_size(2)
+        .with_idle_timeout(Duration::from_millis(100));
+    let pool = SiodbPool::new(&server.uri(&identity()), options).unwrap();
+    assert_eq!(pool.size(), 1);
+    let first = pool.get().unwrap();
+    let second = pool.get().unwrap();
+    drop((first, second));
+    assert_eq!((pool.size(), pool.idle_count()), (2, 2));
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!((pool.size(), pool.idle_count()), (1, 1));
+    drop(pool);
+    server.join();
+}
+
+#[test]
+fn connections_past_their_lifetime_are_closed() {
+    let server = FakeServer::start_sessions(1, |_, session| serve(session));
+    let options = PoolOptions::new().with_max_lifetime(Duration::from_millis(100));
+    let pool = SiodbPool::new(&server.uri(&identity()), options).unwrap();
+    let conn = pool.get().unwrap();
+    thread::sleep(Duration::from_millis(200));
+    drop(conn);
+    assert_eq!(pool.size(), 0);
+    drop(pool);
+    server.join();
+}
+
+#[test]
+fn wrong_pool_sizes_are_config_errors() {
+    for options in &[
+        PoolOptions::new().with_max_size(0),
+        PoolOptions::new().with_min_size(3).with_max_size(2),
+    ] {
+        let error = SiodbPool::new("siodbu:/tmp/none.sock", *options).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config);
+    }
+}
+
+#[test]
+fn zero_pool_timeouts_are_config_errors() {
+    let options = PoolOptions::new().with_idle_timeout(Duration::ZERO);
+    let error = SiodbPool::new("siodbu:/tmp/none.sock", options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+    assert_eq!(
+        error.to_string(),
+        "The idle timeout of the pool must not be 0."
+    );
+    let options = PoolOptions::new().with_max_lifetime(Duration::ZERO);
+    let error = SiodbPool::new("siodbu:/tmp/none.sock", options).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "The maximum lifetime of the pool must not be 0."
+    );
+}
@@ -0,0 +1,163 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::{key_path, socket_path, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn, SiodbConnOptions};
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+const VARIABLES: &[&str] = &[
+    "SIODB_CONNECTIONS_FILE",
+    "SIODB_PROFILE",
+    "SIODB_URI",
+    "SIODB_HOST",
+    "SIODB_PORT",
+    "SIODB_USER",
+    "SIODB_IDENTITY_FILE",
+];
+
+// The tests change the process environment, so they run one at a time.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn clean_env() -> MutexGuard<'static, ()> {
+    let guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    for variable in VARIABLES {
+        env::remove_var(variable);
+    }
+    guard
+}
+
+fn connections_file(contents: &str) -> PathBuf {
+    let path = socket_path().with_extension("toml");
+    fs::write(&path, contents).unwrap();
+    env::set_var("SIODB_CONNECTIONS_FILE", &path);
+    path
+}
+
+#[test]
+fn profile_from_connections_file() {
+    let _env = clean_env();
+    let file = connections_file(
+        r#"
+[production]
+host = "db1:50000, db2"
+port = 50001
+user = "app"
+identity_file = "/keys/app_rsa"
+sslmode = "verify-ca"
+connect_timeout = 2.5
+target = "random"
+
+[local]
+uri = "siodbu:/run/siodb/siodb.socket"
+trace = true
+"#,
+    );
+
+    let production = SiodbConnOptions::from_profile("production").unwrap();
+    assert_eq!(
+        production.to_uri(),
+        "siodbs://app@db1:50000,db2:50001?identity_file=/keys/app_rsa&sslmode=verify-ca&connect_timeout=2.5&target=random"
+    );
+    let local = SiodbConnOptions::from_profile("local").unwrap();
+    assert_eq!(local.to_uri(), "siodbu:/run/siodb/siodb.socket?trace=true");
+
+    let error = SiodbConnOptions::from_profile("staging").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+    assert!(error.to_string().contains("staging"));
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn wrong_profiles_are_config_errors() {
+    let _env = clean_env();
+    let file = connections_file(
+        r#"
+[unknown_option]
+colour = "blue"
+
+[wrong_port]
+port = "fifty"
+
+[wrong_value]
+host = ["db1", "db2"]
+
+[wrong_agent]
+agent = "yes"
+"#,
+    );
+    for profile in &["unknown_option", "wrong_port", "wrong_value", "wrong_agent"] {
+        let error = SiodbConnOptions::from_profile(profile).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config, "{}", profile);
+    }
+    fs::write(&file, "not toml [").unwrap();
+    assert_eq!(
+        SiodbConnOptions::from_profile("any").unwrap_err().kind(),
+        ErrorKind::Config
+    );
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn environment_overrides_profile() {
+    let _env = clean_env();
+    let file = connections_file(
+        r#"
+[production]
+host = "db1"
+user = "app"
+"#,
+    );
+    env::set_var("SIODB_PROFILE", "production");
+    env::set_var("SIODB_PORT", "50005");
+    env::set_var("SIODB_USER", "admin");
+    let options = SiodbConnOptions::from_env().unwrap();
+    assert_eq!(options.to_uri(), "siodbs://admin@db1:50005");
+
+    // The URI replaces the profile.
+    env::set_var("SIODB_URI", "siodb://root@db9:50000?trace=true");
+    env::set_var("SIODB_HOST", "db3,db4:50010");
+    env::remove_var("SIODB_PORT");
+    env::set_var("SIODB_IDENTITY_FILE", "/keys/admin_rsa");
+    let options = SiodbConnOptions::from_env().unwrap();
+    assert_eq!(
+        options.to_uri(),
+        "siodb://admin@db3:50000,db4:50010?identity_file=/keys/admin_rsa&trace=true"
+    );
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn default_identity_file_is_expanded() {
+    let _env = clean_env();
+    let home = socket_path().with_extension("home");
+    fs::create_dir_all(home.join(".ssh")).unwrap();
+    fs::copy(key_path("rsa.pem"), home.join(".ssh/id_rsa")).unwrap();
+    let old_home = env::var_os("HOME");
+    env::set_var("HOME", &home);
+
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        session.wait_for_close();
+    });
+    let result = SiodbConn::new(&format!(
+        "siodbu:{}?agent=false",
+        server.socket_path.display()
+    ));
+
+    match old_home {
+        Some(old_home) => env::set_var("HOME", old_home),
+        None => env::remove_var("HOME"),
+    }
+    fs::remove_dir_all(&home).unwrap();
+    drop(result.unwrap());
+    server.join();
+}
@@ -0,0 +1,155 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::ClientProtocol::ServerResponse;
+use common::ColumnDataType::ColumnDataType;
+use common::CommonTypes::ColumnDescription;
+use common::{answer, identity, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn, SiodbConnOptions};
+
+use std::sync::mpsc;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+#[test]
+fn ping_runs_a_round_trip() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let commands = vec![answer(&mut session), answer(&mut session)];
+        // Close the connection after the second round-trip.
+        commands
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.ping().unwrap();
+    assert!(conn.is_valid());
+    assert_eq!(server.join(), ["SELECT 1", "SELECT 1"]);
+
+    assert!(!conn.is_valid());
+    let error = conn.execute("SELECT 1".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+}
+
+#[test]
+fn ping_reconnects_a_connection_broken_in_a_result() {
+    let server = FakeServer::start_sessions(2, |index, mut session: Session| {
+        session.accept(CHALLENGE);
+        if index == 1 {
+            let text = answer(&mut session);
+            session.wait_for_close();
+            return text;
+        }
+        // The columns of a dataset, and no row.
+        let command = session.read_command();
+        let mut column = ColumnDescription::new();
+        column.set_name("ID".to_string());
+        column.set_field_type(ColumnDataType::COLUMN_DATA_TYPE_UINT64);
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id());
+        response.set_column_description(vec![column].into());
+        session.write_response(&response);
+        session.wait_for_close();
+        command.get_text().to_string()
+    });
+    let mut conn = SiodbConn::new(&server.uri(&format!(
+        "auto_reconnect=true&read_timeout=0.2&{}",
+        identity()
+    )))
+    .unwrap();
+    conn.query("SELECT ID FROM T1".to_string()).unwrap();
+    assert_eq!(conn.next().unwrap_err().kind(), ErrorKind::Timeout);
+
+    // The rows left of the broken connection are dropped with it.
+    conn.ping().unwrap();
+    assert!(conn.scan().is_empty());
+    conn.close().unwrap();
+    assert_eq!(server.join(), ["SELECT ID FROM T1", "SELECT 1"]);
+}
+
+#[test]
+fn reconnects_between_statements() {
+    let (closed, wait_closed) = mpsc::channel();
+    let server = FakeServer::start_sessions(2, move |index, mut session: Session| {
+        session.accept(CHALLENGE);
+        let text = answer(&mut session);
+        if index == 0 {
+            drop(session);
+            closed.send(()).unwrap();
+        } else {
+            session.wait_for_close();
+        }
+        text
+    });
+    let mut conn =
+        SiodbConn::new(&server.uri(&format!("auto_reconnect=true&{}", identity()))).unwrap();
+    conn.execute("DELETE FROM T1".to_string()).unwrap();
+    wait_closed.recv().unwrap();
+    conn.execute("DELETE FROM T2".to_string()).unwrap();
+    conn.close().unwrap();
+    assert_eq!(server.join(), ["DELETE FROM T1", "DELETE FROM T2"]);
+}
+
+#[test]
+fn a_sent_statement_is_never_retried() {
+    let server = FakeServer::start_sessions(2, |index, mut session: Session| {
+        session.accept(CHALLENGE);
+        if index == 0 {
+            // Read the command and close without answering.
+            return session.read_command().get_text().to_string();
+        }
+        let text = answer(&mut session);
+        session.wait_for_close();
+        text
+    });
+    let options = SiodbConnOptions::from_uri(&server.uri(&identity()))
+        .unwrap()
+        .with_auto_reconnect(true);
+    let mut conn = SiodbConn::new_with_options(&options).unwrap();
+    let error = conn
+        .execute("INSERT INTO T1 VALUES (1)".to_string())
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+    conn.ping().unwrap();
+    conn.close().unwrap();
+    assert_eq!(server.join(), ["INSERT INTO T1 VALUES (1)", "SELECT 1"]);
+}
+
+#[test]
+fn a_broken_connection_is_refused_without_auto_reconnect() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        session.read_command();
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.execute("DELETE FROM T1".to_string()).unwrap_err();
+    server.join();
+    let error = conn.execute("DELETE FROM T2".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Io);
+    assert_eq!(
+        error.to_string(),
+        "The connection is broken by a previous error."
+    );
+}
+
+#[test]
+fn auto_reconnect_round_trip() {
+    let uri = "siodbu:/run/siodb.sock?auto_reconnect=true";
+    assert!(SiodbConnOptions::new()
+        .with_auto_reconnect(true)
+        .to_uri()
+        .ends_with("?auto_reconnect=true"));
+    assert_eq!(SiodbConnOptions::from_uri(uri).unwrap().to_uri(), uri);
+}
+
+#[test]
+fn wrong_auto_reconnect_is_a_config_error() {
+    let error =
+        SiodbConnOptions::from_uri("siodbu:/run/siodb.sock?auto_reconnect=yes").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+    assert_eq!(
+        error.to_string(),
+        "Wrong auto_reconnect: 'yes'. Should be 'true' or 'false'."
+    );
+}
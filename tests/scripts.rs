@@ -0,0 +1,126 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::ClientProtocol::ServerResponse;
+use common::ColumnDataType::ColumnDataType;
+use common::CommonTypes::{ColumnDescription, StatusMessage};
+use common::{answer, identity, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn};
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+const SCRIPT: &str = "SELECT ID FROM T1; DELETE FROM T1; DROP TABLE T2; DELETE FROM T3";
+
+fn response(request_id: u64, response_id: u32) -> ServerResponse {
+    let mut response = ServerResponse::new();
+    response.set_request_id(request_id);
+    response.set_response_id(response_id);
+    response.set_response_count(4);
+    response
+}
+
+// Answer the statements of SCRIPT: a dataset of two rows, two deleted rows, an
+// error, then one deleted row.
+fn answer_script(session: &mut Session) -> String {
+    let command = session.read_command();
+    let request_id = command.get_request_id();
+
+    let mut column = ColumnDescription::new();
+    column.set_name("ID".to_string());
+    column.set_field_type(ColumnDataType::COLUMN_DATA_TYPE_UINT64);
+    let mut dataset = response(request_id, 0);
+    dataset.set_column_description(vec![column].into());
+    session.write_response(&dataset);
+    session.write_raw(&[1, 1, 1, 2, 0]);
+
+    let mut deleted = response(request_id, 1);
+    deleted.set_has_affected_row_count(true);
+    deleted.set_affected_row_count(2);
+    session.write_response(&deleted);
+
+    let mut message = StatusMessage::new();
+    message.set_status_code(3);
+    message.set_text("Table 'T2' does not exist.".to_string());
+    let mut failed = response(request_id, 2);
+    failed.set_message(vec![message].into());
+    session.write_response(&failed);
+
+    let mut deleted = response(request_id, 3);
+    deleted.set_has_affected_row_count(true);
+    deleted.set_affected_row_count(1);
+    session.write_response(&deleted);
+    command.get_text().to_string()
+}
+
+#[test]
+fn results_of_a_script_are_read_in_turn() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        vec![answer_script(&mut session), answer(&mut session)]
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.execute(SCRIPT.to_string()).unwrap();
+    assert!(conn.next().unwrap());
+    assert_eq!(conn.scan()[0].as_ref().unwrap().to_string(), "1");
+
+    // The rows left are skipped.
+    assert!(conn.next_result().unwrap());
+    assert_eq!(conn.get_affected_row_count(), 2);
+    assert!(!conn.next().unwrap());
+
+    let error = conn.next_result().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Server);
+    assert_eq!(conn.get_server_messages()[0].status_code, 3);
+
+    assert!(conn.next_result().unwrap());
+    assert_eq!(conn.get_affected_row_count(), 1);
+    assert!(conn.get_server_messages().is_empty());
+    assert!(!conn.next_result().unwrap());
+
+    conn.execute("DELETE FROM T4".to_string()).unwrap();
+    assert!(!conn.next_result().unwrap());
+    assert_eq!(server.join(), [SCRIPT, "DELETE FROM T4"]);
+}
+
+#[test]
+fn unread_results_keep_the_next_statement_out() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let text = answer_script(&mut session);
+        session.wait_for_close();
+        text
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.execute(SCRIPT.to_string()).unwrap();
+    while conn.next().unwrap() {}
+    let error = conn.execute("DELETE FROM T4".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+    assert!(conn.ping().is_err());
+    drop(conn);
+    assert_eq!(server.join(), SCRIPT);
+}
+
+#[test]
+fn response_out_of_order_is_a_protocol_error() {
+    let server = FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        session.write_response(&response(command.get_request_id(), 0));
+        session.write_response(&response(command.get_request_id(), 2));
+        session.wait_for_close();
+    });
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.execute(SCRIPT.to_string()).unwrap();
+    let error = conn.next_result().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Protocol);
+    assert_eq!(
+        error.to_string(),
+        "execute | wrong response id received from Siodb: 2. Expected: 1."
+    );
+    assert_eq!(conn.next_result().unwrap_err().kind(), ErrorKind::Io);
+    drop(conn);
+    server.join();
+}
@@ -0,0 +1,107 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::ClientProtocol::ServerResponse;
+use common::ColumnDataType::ColumnDataType;
+use common::CommonTypes::ColumnDescription;
+use common::{key_path, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn, SiodbConnOptions};
+
+use std::time::Duration;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+// Text rows longer than the buffers, so that every row takes several reads.
+fn long_text(index: usize) -> String {
+    format!("{:04}", index).repeat(100)
+}
+
+fn serve_long_rows(rows: usize) -> FakeServer<String> {
+    FakeServer::start_tcp(move |mut session: Session| {
+        session.accept(CHALLENGE);
+        let command = session.read_command();
+        let mut column = ColumnDescription::new();
+        column.set_name("TEXT".to_string());
+        column.set_field_type(ColumnDataType::COLUMN_DATA_TYPE_TEXT);
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id());
+        response.set_column_description(vec![column].into());
+        session.write_response(&response);
+        let mut bytes = Vec::new();
+        for index in 0..rows {
+            let text = long_text(index);
+            // Row length, then the text length, both as two bytes varints.
+            let row_length = text.len() + 2;
+            bytes.extend(&[0x80 | (row_length & 0x7f) as u8, (row_length >> 7) as u8]);
+            bytes.extend(&[0x80 | (text.len() & 0x7f) as u8, (text.len() >> 7) as u8]);
+            bytes.extend(text.as_bytes());
+        }
+        bytes.push(0);
+        session.write_raw(&bytes);
+        session.wait_for_close();
+        command.get_text().to_string()
+    })
+}
+
+#[test]
+fn tcp_options_and_small_buffers() {
+    let server = serve_long_rows(50);
+    let uri = format!(
+        "siodb://root@{}?keepalive=30&nodelay=true&read_buffer_size=64&write_buffer_size=16&identity_file={}",
+        server.address(),
+        key_path("rsa.pem")
+    );
+    let mut conn = SiodbConn::new(&uri).unwrap();
+    conn.query("SELECT TEXT FROM LOGS".to_string()).unwrap();
+    let mut index = 0;
+    while conn.next().unwrap() {
+        assert_eq!(
+            conn.scan()[0].as_ref().unwrap().to_string(),
+            long_text(index)
+        );
+        index += 1;
+    }
+    assert_eq!(index, 50);
+    drop(conn);
+    assert_eq!(server.join(), "SELECT TEXT FROM LOGS");
+}
+
+#[test]
+fn socket_options_round_trip() {
+    let options = SiodbConnOptions::new()
+        .with_host("db1", 50000)
+        .with_keepalive(Duration::from_secs(60))
+        .with_nodelay(true)
+        .with_buffer_sizes(64 * 1024, 8 * 1024);
+    let uri = options.to_uri();
+    assert_eq!(
+        uri,
+        "siodbs://root@db1:50000?keepalive=60&nodelay=true&read_buffer_size=65536"
+    );
+    assert_eq!(SiodbConnOptions::from_uri(&uri).unwrap().to_uri(), uri);
+
+    // No keepalive, as keepalive=0 in the URI.
+    let options = SiodbConnOptions::new()
+        .with_host("db1", 50000)
+        .with_keepalive(Duration::ZERO);
+    assert_eq!(options.to_uri(), "siodbs://root@db1:50000");
+}
+
+#[test]
+fn wrong_socket_options_are_config_errors() {
+    for uri in &[
+        "siodb://root@localhost:50000?keepalive=soon",
+        "siodb://root@localhost:50000?nodelay=yes",
+        "siodb://root@localhost:50000?read_buffer_size=0",
+        "siodb://root@localhost:50000?write_buffer_size=-1",
+    ] {
+        let error = SiodbConnOptions::from_uri(uri).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config, "{}", uri);
+    }
+    let options = SiodbConnOptions::new().with_buffer_sizes(0, 1024);
+    let error = SiodbConn::new_with_options(&options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+}
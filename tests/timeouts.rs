@@ -0,0 +1,151 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::{identity, FakeServer, Session};
+
+use siodb::{ErrorKind, SiodbConn, Timeouts};
+
+use std::io::{self, Read};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+// A TCP server accepting one connection and never answering.
+fn silent_tcp_server() -> (u16, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = io::copy(&mut stream.by_ref(), &mut io::sink());
+    });
+    (port, handle)
+}
+
+// A server authenticating the client, then reading a command and never answering.
+fn silent_after_command() -> FakeServer<()> {
+    FakeServer::start(|mut session: Session| {
+        session.accept(CHALLENGE);
+        session.read_command();
+        session.wait_for_close();
+    })
+}
+
+#[test]
+fn wrong_timeouts_are_config_errors() {
+    for option in &[
+        "connect_timeout=abc",
+        "read_timeout=-1",
+        "write_timeout=inf",
+    ] {
+        let error = SiodbConn::new(&format!("siodbu:/tmp/none.sock?{}", option)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config, "{}", option);
+    }
+}
+
+#[test]
+fn connect_timeout_bounds_unix_socket_handshake() {
+    let server = FakeServer::start(|mut session: Session| session.wait_for_close());
+    let started = Instant::now();
+    let error =
+        SiodbConn::new(&server.uri(&format!("connect_timeout=0.2&{}", identity()))).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    server.join();
+}
+
+#[test]
+fn connect_timeout_bounds_tcp_handshake() {
+    let (port, handle) = silent_tcp_server();
+    let error = SiodbConn::new(&format!(
+        "siodb://root@127.0.0.1:{}?connect_timeout=0.2&{}",
+        port,
+        identity()
+    ))
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    handle.join().unwrap();
+}
+
+#[test]
+fn connect_timeout_bounds_tls_handshake() {
+    let (port, handle) = silent_tcp_server();
+    let error = SiodbConn::new(&format!(
+        "siodbs://root@127.0.0.1:{}?sslmode=require&connect_timeout=0.2&{}",
+        port,
+        identity()
+    ))
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    handle.join().unwrap();
+}
+
+#[test]
+fn read_timeout_from_uri() {
+    let server = silent_after_command();
+    let mut conn =
+        SiodbConn::new(&server.uri(&format!("read_timeout=0.2&{}", identity()))).unwrap();
+    let error = conn.execute("SELECT 1".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    drop(conn);
+    server.join();
+}
+
+#[test]
+fn read_timeout_from_builder_overrides_uri() {
+    let server = silent_after_command();
+    let timeouts = Timeouts::new().with_read_timeout(Duration::from_millis(200));
+    let mut conn = SiodbConn::new_with_timeouts(
+        &server.uri(&format!("read_timeout=60&{}", identity())),
+        timeouts,
+    )
+    .unwrap();
+    let error = conn.execute("SELECT 1".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    drop(conn);
+    server.join();
+}
+
+#[test]
+fn zero_timeouts_from_builder_are_unset() {
+    let timeouts = Timeouts::new()
+        .with_connect_timeout(Duration::ZERO)
+        .with_read_timeout(Duration::ZERO)
+        .with_write_timeout(Duration::ZERO);
+    assert_eq!(timeouts.connect_timeout(), None);
+    assert_eq!(timeouts.read_timeout(), None);
+    assert_eq!(timeouts.write_timeout(), None);
+}
+
+#[test]
+fn read_timeout_can_be_changed_on_a_connection() {
+    let server = silent_after_command();
+    let mut conn = SiodbConn::new(&server.uri(&identity())).unwrap();
+    conn.set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let error = conn.execute("SELECT 1".to_string()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    drop(conn);
+    server.join();
+}
+
+#[test]
+fn write_timeout_from_builder() {
+    // The server reads nothing until the client gave up, so the socket buffers fill up.
+    let (gave_up, wait) = mpsc::channel();
+    let server = FakeServer::start(move |mut session: Session| {
+        session.accept(CHALLENGE);
+        wait.recv().unwrap();
+    });
+    let timeouts = Timeouts::new().with_write_timeout(Duration::from_millis(200));
+    let mut conn = SiodbConn::new_with_timeouts(&server.uri(&identity()), timeouts).unwrap();
+    let statement = format!("SELECT '{}'", "x".repeat(16 * 1024 * 1024));
+    let error = conn.execute(statement).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Timeout);
+    gave_up.send(()).unwrap();
+    server.join();
+}
@@ -0,0 +1,549 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+// TLS against an OpenSSL server, whichever backend the driver is built with.
+
+mod common;
+use common::{key_path, Session};
+
+use siodb::{ErrorKind, SiodbConn, SiodbConnOptions, SslMode, TlsVersion};
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{
+    NameType, SslAcceptor, SslAcceptorBuilder, SslMethod, SslVerifyMode, SslVersion,
+};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+static PKI_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// A CA, a server certificate for `localhost` and a client certificate, written
+// to a fresh directory.
+struct Pki {
+    dir: PathBuf,
+    ca: X509,
+    server: (X509, PKey<Private>),
+}
+
+impl Pki {
+    fn new() -> Pki {
+        let dir = env::temp_dir().join(format!(
+            "siodb-test-pki-{}-{}",
+            process::id(),
+            PKI_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        let ca_key = ec_key();
+        let ca = certificate("Test CA", &ca_key, None, None);
+        let server_key = ec_key();
+        let server = certificate(
+            "localhost",
+            &server_key,
+            Some((&ca, &ca_key)),
+            Some("localhost"),
+        );
+        let client_key = ec_key();
+        let client = certificate("root", &client_key, Some((&ca, &ca_key)), None);
+        let other_key = ec_key();
+        let p521_key = ec_key_on(Nid::SECP521R1);
+        let p521_client = certificate("root", &p521_key, Some((&ca, &ca_key)), None);
+
+        fs::write(dir.join("ca.pem"), ca.to_pem().unwrap()).unwrap();
+        fs::write(dir.join("client.pem"), client.to_pem().unwrap()).unwrap();
+        fs::write(
+            dir.join("client.key"),
+            client_key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("other.key"),
+            other_key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+        for (name, key, certificate) in &[
+            ("client", &client_key, &client),
+            ("client_p521", &p521_key, &p521_client),
+        ] {
+            let archive = Pkcs12::builder()
+                .name("root")
+                .pkey(key)
+                .cert(certificate)
+                .build2("p12pass")
+                .unwrap();
+            fs::write(dir.join(format!("{}.p12", name)), archive.to_der().unwrap()).unwrap();
+        }
+        fs::write(dir.join("client_p521.pem"), p521_client.to_pem().unwrap()).unwrap();
+        fs::write(
+            dir.join("client_p521.key"),
+            p521_key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+
+        Pki {
+            dir,
+            ca,
+            server: (server, server_key),
+        }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).display().to_string()
+    }
+
+    fn server_sha256(&self) -> [u8; 32] {
+        let digest = self.server.0.digest(MessageDigest::sha256()).unwrap();
+        let mut fingerprint = [0u8; 32];
+        fingerprint.copy_from_slice(&digest);
+        fingerprint
+    }
+
+    // A server accepting one TLS connection, requiring a client certificate if
+    // asked, then playing Siodb. It returns the SNI name of a successful handshake.
+    fn serve(&self, require_client_cert: bool) -> (u16, thread::JoinHandle<Option<Handshake>>) {
+        let ca = self.ca.clone();
+        self.serve_with(move |acceptor| {
+            if require_client_cert {
+                acceptor.cert_store_mut().add_cert(ca).unwrap();
+                acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+            }
+        })
+    }
+
+    fn serve_with<F>(&self, configure: F) -> (u16, thread::JoinHandle<Option<Handshake>>)
+    where
+        F: FnOnce(&mut SslAcceptorBuilder),
+    {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&self.server.0).unwrap();
+        acceptor.set_private_key(&self.server.1).unwrap();
+        configure(&mut acceptor);
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let stream = acceptor.accept(stream).ok()?;
+            let handshake = Handshake {
+                server_name: stream
+                    .ssl()
+                    .servername(NameType::HOST_NAME)
+                    .map(|name| name.to_string()),
+                client_name: stream.ssl().peer_certificate().and_then(|certificate| {
+                    let entry = certificate.subject_name().entries().next()?;
+                    entry.data().to_string().ok()
+                }),
+            };
+            let mut session = Session::from_stream(stream);
+            session.accept(CHALLENGE);
+            session.wait_for_close();
+            Some(handshake)
+        });
+        (port, handle)
+    }
+}
+
+// What the server saw of a successful handshake.
+struct Handshake {
+    server_name: Option<String>,
+    client_name: Option<String>,
+}
+
+impl Drop for Pki {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn ec_key() -> PKey<Private> {
+    ec_key_on(Nid::X9_62_PRIME256V1)
+}
+
+fn ec_key_on(curve: Nid) -> PKey<Private> {
+    let group = EcGroup::from_curve_name(curve).unwrap();
+    PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+// A certificate for `name`, self-signed unless an issuer is given.
+fn certificate(
+    name: &str,
+    key: &PKey<Private>,
+    issuer: Option<(&X509, &PKey<Private>)>,
+    dns_name: Option<&str>,
+) -> X509 {
+    let mut subject = X509NameBuilder::new().unwrap();
+    subject.append_entry_by_text("CN", name).unwrap();
+    let subject = subject.build();
+
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(PKI_COUNTER.fetch_add(1, Ordering::SeqCst) as u32 + 1)
+        .unwrap()
+        .to_asn1_integer()
+        .unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&subject).unwrap();
+    builder.set_pubkey(key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    match issuer {
+        Some((issuer, _)) => builder.set_issuer_name(issuer.subject_name()).unwrap(),
+        None => {
+            builder.set_issuer_name(&subject).unwrap();
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+        }
+    }
+    if let Some(dns_name) = dns_name {
+        let san = SubjectAlternativeName::new()
+            .dns(dns_name)
+            .build(&builder.x509v3_context(issuer.map(|(issuer, _)| &**issuer), None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+    }
+    let signing_key = issuer.map_or(key, |(_, issuer_key)| issuer_key);
+    builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+    builder.build()
+}
+
+fn options(host: &str, port: u16, sslmode: SslMode) -> SiodbConnOptions {
+    SiodbConnOptions::from_uri(&format!("siodbs://root@{}:{}", host, port))
+        .unwrap()
+        .with_sslmode(sslmode)
+        .with_identity_file(&key_path("rsa.pem"))
+}
+
+#[test]
+fn require_accepts_any_certificate() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(false);
+    let conn = SiodbConn::new_with_options(&options("127.0.0.1", port, SslMode::Require)).unwrap();
+    drop(conn);
+    assert!(handle.join().unwrap().is_some());
+}
+
+#[test]
+fn verify_ca_trusts_the_ca_file_for_any_host_name() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(false);
+    let options = options("127.0.0.1", port, SslMode::VerifyCa).with_ca_file(&pki.path("ca.pem"));
+    drop(SiodbConn::new_with_options(&options).unwrap());
+    assert!(handle.join().unwrap().is_some());
+}
+
+#[test]
+fn verify_ca_rejects_an_unknown_ca() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(false);
+    let error =
+        SiodbConn::new_with_options(&options("127.0.0.1", port, SslMode::VerifyCa)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+    assert!(handle.join().unwrap().is_none());
+}
+
+#[test]
+fn verify_full_checks_the_host_name() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(false);
+    let uri = format!(
+        "siodbs://root@localhost:{}?sslmode=verify-full&ca_file={}&identity_file={}",
+        port,
+        pki.path("ca.pem"),
+        key_path("rsa.pem")
+    );
+    drop(SiodbConn::new(&uri).unwrap());
+    assert!(handle.join().unwrap().is_some());
+
+    let (port, handle) = pki.serve(false);
+    let options = options("127.0.0.1", port, SslMode::VerifyFull).with_ca_file(&pki.path("ca.pem"));
+    let error = SiodbConn::new_with_options(&options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+    assert!(error.to_string().contains("TLS handshake"), "{}", error);
+    assert!(handle.join().unwrap().is_none());
+}
+
+#[test]
+fn siodbs_verifies_the_full_certificate_by_default() {
+    let pki = Pki::new();
+    let uri = |host: &str, port: u16, query: &str| {
+        format!(
+            "siodbs://root@{}:{}?identity_file={}{}",
+            host,
+            port,
+            key_path("rsa.pem"),
+            query
+        )
+    };
+    let ca_file = format!("&ca_file={}", pki.path("ca.pem"));
+
+    let (port, handle) = pki.serve(false);
+    drop(SiodbConn::new(&uri("localhost", port, &ca_file)).unwrap());
+    assert!(handle.join().unwrap().is_some());
+
+    // The CA is not trusted.
+    let (port, handle) = pki.serve(false);
+    let error = SiodbConn::new(&uri("localhost", port, "")).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+    assert!(handle.join().unwrap().is_none());
+
+    // The certificate is for another host name.
+    let (port, handle) = pki.serve(false);
+    let error = SiodbConn::new(&uri("127.0.0.1", port, &ca_file)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+    assert!(handle.join().unwrap().is_none());
+}
+
+#[test]
+fn sslmode_require_opts_out_of_verification() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(false);
+    let uri = format!(
+        "siodbs://root@127.0.0.1:{}?sslmode=require&identity_file={}",
+        port,
+        key_path("rsa.pem")
+    );
+    drop(SiodbConn::new(&uri).unwrap());
+    assert!(handle.join().unwrap().is_some());
+}
+
+#[test]
+fn sslmode_values() {
+    for (value, sslmode) in &[
+        ("disable", SslMode::Disable),
+        ("require", SslMode::Require),
+        ("verify-ca", SslMode::VerifyCa),
+        ("verify-full", SslMode::VerifyFull),
+    ] {
+        let uri = format!("siodb://root@localhost:50000?sslmode={}", value);
+        let options = SiodbConnOptions::from_uri(&uri).unwrap();
+        assert_eq!(options.to_uri(), uri);
+        assert_eq!(
+            SiodbConnOptions::from_uri("siodb://root@localhost:50000")
+                .unwrap()
+                .with_sslmode(*sslmode)
+                .to_uri(),
+            uri
+        );
+    }
+
+    for uri in &[
+        "siodb://root@localhost:50000?sslmode=allow",
+        "siodb://root@localhost:50000?sslmode=VERIFY-FULL",
+        "siodbu:/run/siodb/siodb.socket?sslmode=require",
+    ] {
+        let error = SiodbConnOptions::from_uri(uri).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config, "{}", uri);
+    }
+}
+
+#[test]
+fn pem_client_certificate() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(true);
+    let options = options("127.0.0.1", port, SslMode::VerifyCa)
+        .with_ca_file(&pki.path("ca.pem"))
+        .with_client_cert(&pki.path("client.pem"))
+        .with_client_key(&pki.path("client.key"));
+    drop(SiodbConn::new_with_options(&options).unwrap());
+    let handshake = handle.join().unwrap().unwrap();
+    assert_eq!(handshake.client_name.as_deref(), Some("root"));
+}
+
+#[test]
+fn p521_client_certificate() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(true);
+    let pem = options("127.0.0.1", port, SslMode::Require)
+        .with_client_cert(&pki.path("client_p521.pem"))
+        .with_client_key(&pki.path("client_p521.key"));
+    drop(SiodbConn::new_with_options(&pem).unwrap());
+    let handshake = handle.join().unwrap().unwrap();
+    assert_eq!(handshake.client_name.as_deref(), Some("root"));
+
+    let (port, handle) = pki.serve(true);
+    let options = options("127.0.0.1", port, SslMode::Require)
+        .with_client_cert(&pki.path("client_p521.p12"))
+        .with_client_key_passphrase("p12pass");
+    drop(SiodbConn::new_with_options(&options).unwrap());
+    assert!(handle.join().unwrap().is_some());
+}
+
+#[test]
+fn client_key_must_match_the_certificate() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(true);
+    let options = options("127.0.0.1", port, SslMode::VerifyCa)
+        .with_ca_file(&pki.path("ca.pem"))
+        .with_client_cert(&pki.path("client.pem"))
+        .with_client_key(&pki.path("other.key"));
+    let error = SiodbConn::new_with_options(&options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls, "{}", error);
+    drop(handle);
+}
+
+#[test]
+fn pkcs12_client_certificate() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(true);
+    let options = options("127.0.0.1", port, SslMode::Require)
+        .with_client_cert(&pki.path("client.p12"))
+        .with_client_key_passphrase("p12pass");
+    drop(SiodbConn::new_with_options(&options).unwrap());
+    assert!(handle.join().unwrap().is_some());
+}
+
+#[test]
+fn wrong_client_certificate_settings() {
+    let pki = Pki::new();
+    let (port, _handle) = pki.serve(false);
+    let error = SiodbConn::new_with_options(
+        &options("127.0.0.1", port, SslMode::Require).with_client_key(&pki.path("client.key")),
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+
+    let (port, _handle) = pki.serve(false);
+    let error = SiodbConn::new_with_options(
+        &options("127.0.0.1", port, SslMode::Require)
+            .with_client_cert(&pki.path("client.p12"))
+            .with_client_key(&pki.path("client.key")),
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+
+    let (port, _handle) = pki.serve(false);
+    let options = options("127.0.0.1", port, SslMode::Require).with_ca_file(&key_path("rsa.pem"));
+    let error = SiodbConn::new_with_options(&options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+    assert!(
+        error.to_string().contains("No certificate found"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn tls_server_name_overrides_the_host() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(false);
+    let options = options("127.0.0.1", port, SslMode::VerifyFull)
+        .with_ca_file(&pki.path("ca.pem"))
+        .with_tls_server_name("localhost");
+    drop(SiodbConn::new_with_options(&options).unwrap());
+    let handshake = handle.join().unwrap().unwrap();
+    assert_eq!(handshake.server_name.as_deref(), Some("localhost"));
+}
+
+#[test]
+fn pinned_certificate() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve(false);
+    let options = options("127.0.0.1", port, SslMode::Require).add_cert_sha256(pki.server_sha256());
+    drop(SiodbConn::new_with_options(&options).unwrap());
+    assert!(handle.join().unwrap().is_some());
+
+    let (port, _handle) = pki.serve(false);
+    let uri = format!(
+        "siodbs://root@127.0.0.1:{}?sslmode=require&cert_sha256={}&identity_file={}",
+        port,
+        "ab".repeat(32),
+        key_path("rsa.pem")
+    );
+    let error = SiodbConn::new(&uri).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+    assert!(error.to_string().contains("pinned"), "{}", error);
+}
+
+#[test]
+fn ca_dir_certificates_are_trusted() {
+    let pki = Pki::new();
+    let ca_dir = pki.dir.join("certs");
+    fs::create_dir(&ca_dir).unwrap();
+    fs::copy(pki.path("ca.pem"), ca_dir.join("ca.pem")).unwrap();
+    fs::write(ca_dir.join("README"), "Not a certificate.").unwrap();
+    let (port, handle) = pki.serve(false);
+    let trusted =
+        options("localhost", port, SslMode::VerifyFull).with_ca_dir(&ca_dir.display().to_string());
+    drop(SiodbConn::new_with_options(&trusted).unwrap());
+    assert!(handle.join().unwrap().is_some());
+
+    let (port, _handle) = pki.serve(false);
+    let options = options("localhost", port, SslMode::VerifyFull)
+        .with_ca_dir(&pki.dir.join("none").display().to_string());
+    let error = SiodbConn::new_with_options(&options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+}
+
+#[test]
+fn minimum_tls_version() {
+    let pki = Pki::new();
+    let (port, handle) = pki.serve_with(|acceptor| {
+        acceptor
+            .set_max_proto_version(Some(SslVersion::TLS1_2))
+            .unwrap()
+    });
+    let tls13 = options("127.0.0.1", port, SslMode::Require)
+        .with_ssl_min_protocol_version(TlsVersion::Tls13);
+    let error = SiodbConn::new_with_options(&tls13).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Tls);
+    assert!(handle.join().unwrap().is_none());
+
+    let (port, handle) = pki.serve(false);
+    let tls13 = options("127.0.0.1", port, SslMode::Require)
+        .with_ssl_min_protocol_version(TlsVersion::Tls13);
+    drop(SiodbConn::new_with_options(&tls13).unwrap());
+    assert!(handle.join().unwrap().is_some());
+}
+
+#[test]
+fn tls_options_round_trip() {
+    let fingerprint = ["01:23:45:67:89:AB:CD:EF"; 4].join(":");
+    let uri = format!(
+        "siodbs://root@10.0.0.5:50000?ssl_min_protocol_version=TLSv1.3&tls_server_name=db.internal&cert_sha256={}&ca_dir=/etc/siodb/certs",
+        fingerprint.replace(':', "").to_lowercase()
+    );
+    let options = SiodbConnOptions::from_uri(&uri).unwrap();
+    let printed = options.to_uri();
+    assert_eq!(
+        printed,
+        format!(
+            "siodbs://root@10.0.0.5:50000?ssl_min_protocol_version=TLSv1.3&tls_server_name=db.internal&cert_sha256={}&ca_dir=/etc/siodb/certs",
+            fingerprint
+        )
+    );
+    assert_eq!(
+        SiodbConnOptions::from_uri(&printed).unwrap().to_uri(),
+        printed
+    );
+
+    for uri in &[
+        "siodbs://root@localhost:50000?ssl_min_protocol_version=TLSv1.1",
+        "siodbs://root@localhost:50000?cert_sha256=abcd",
+        "siodbs://root@localhost:50000?cert_sha256=zz",
+    ] {
+        let error = SiodbConnOptions::from_uri(uri).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Config, "{}", uri);
+    }
+}
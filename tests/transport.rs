@@ -0,0 +1,175 @@
+// Copyright (C) 2019-2020 Siodb GmbH. All rights reserved.
+// Use of this source code is governed by a license that can be found
+// in the LICENSE file.
+
+mod common;
+use common::ClientProtocol::ServerResponse;
+use common::{key_path, Session};
+
+use siodb::{ErrorKind, SiodbConn, SiodbConnOptions, Timeouts, Transport};
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const CHALLENGE: &[u8] = b"siodb-test-challenge-0123456789abcdef";
+
+fn options() -> SiodbConnOptions {
+    SiodbConnOptions::new()
+        .with_user("tunnel")
+        .with_identity_file(&key_path("rsa.pem"))
+}
+
+// A server authenticating the client and answering one statement.
+fn serve(stream: UnixStream) -> thread::JoinHandle<(String, String)> {
+    thread::spawn(move || {
+        let mut session = Session::new(stream);
+        let (user, _) = session.begin(CHALLENGE);
+        session.authenticate(true);
+        let command = session.read_command();
+        let mut response = ServerResponse::new();
+        response.set_request_id(command.get_request_id());
+        response.set_affected_row_count(3);
+        response.set_has_affected_row_count(true);
+        session.write_response(&response);
+        session.wait_for_close();
+        (user, command.get_text().to_string())
+    })
+}
+
+// A stream with nothing but Read and Write.
+struct PlainStream(UnixStream);
+
+impl Read for PlainStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for PlainStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// A transport recording the timeouts and shutdowns it gets.
+struct RecordingTransport {
+    stream: UnixStream,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl Read for RecordingTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for RecordingTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("read {:?}", timeout));
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("write {:?}", timeout));
+        self.stream.set_write_timeout(timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.calls.lock().unwrap().push("shutdown".to_string());
+        self.stream.shutdown(std::net::Shutdown::Both)
+    }
+}
+
+#[test]
+fn unix_stream_pair_as_transport() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let handle = serve(server);
+
+    let mut conn = SiodbConn::new_with_transport(client, &options()).unwrap();
+    assert_eq!(conn.host(), "");
+    assert_eq!(conn.port(), 0);
+    conn.execute("DELETE FROM T".to_string()).unwrap();
+    assert_eq!(conn.get_affected_row_count(), 3);
+    conn.close().unwrap();
+
+    let (user, text) = handle.join().unwrap();
+    assert_eq!(user, "tunnel");
+    assert_eq!(text, "DELETE FROM T");
+}
+
+#[test]
+fn plain_stream_without_timeouts() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let handle = serve(server);
+
+    let mut conn = SiodbConn::new_with_stream(PlainStream(client), &options()).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    conn.execute("DELETE FROM T".to_string()).unwrap();
+    assert_eq!(conn.get_affected_row_count(), 3);
+    // Dropping the stream is all a plain stream has to close.
+    drop(conn);
+
+    assert_eq!(handle.join().unwrap().1, "DELETE FROM T");
+}
+
+#[test]
+fn transport_gets_timeouts_and_shutdown() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let handle = serve(server);
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let transport = RecordingTransport {
+        stream: client,
+        calls: calls.clone(),
+    };
+    let timeouts = Timeouts::new()
+        .with_connect_timeout(Duration::from_secs(5))
+        .with_read_timeout(Duration::from_secs(7));
+
+    let mut conn =
+        SiodbConn::new_with_transport(transport, &options().with_timeouts(timeouts)).unwrap();
+    conn.execute("DELETE FROM T".to_string()).unwrap();
+    conn.close().unwrap();
+    handle.join().unwrap();
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            "read Some(5s)",
+            "write Some(5s)",
+            "read Some(7s)",
+            "write None",
+            "shutdown",
+        ]
+    );
+}
+
+#[test]
+fn transport_options_are_validated() {
+    let (client, _server) = UnixStream::pair().unwrap();
+    let error = SiodbConn::new_with_transport(client, &SiodbConnOptions::new().with_pkcs11_slot(1))
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Config);
+}